@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::adp::TExtendedAddress;
+use crate::app_config;
+
+/// Outcome of an admission check, a declined device gets an LBP_DECLINE instead of the EAP-PSK challenge
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    Decline(String),
+}
+
+/// Decides whether a device may start the bootstrap.
+/// Policies are checked in order on the first joining message of a device, the first decline wins.
+pub trait AdmissionPolicy: fmt::Debug {
+    /// `device_count` is the number of devices known to the coordinator, not counting `ext_addr`
+    fn admit(&self, ext_addr: &TExtendedAddress, device_count: usize) -> Admission;
+}
+
+/// Static allow and deny lists from the configuration, plus a cap on the number of devices.
+/// An empty allow list admits every device that is not denied.
+#[derive(Debug)]
+pub struct ListPolicy {
+    allow: HashSet<TExtendedAddress>,
+    deny: HashSet<TExtendedAddress>,
+    max_devices: usize,
+}
+
+fn parse_list(list: &Option<Vec<String>>, name: &str) -> HashSet<TExtendedAddress> {
+    let mut set = HashSet::new();
+    for s in list.iter().flatten() {
+        match s.parse::<TExtendedAddress>() {
+            Ok(ext_addr) => {
+                set.insert(ext_addr);
+            }
+            Err(e) => log::error!("[BS] Invalid {} entry {} : {}", name, s, e),
+        }
+    }
+    set
+}
+
+impl ListPolicy {
+    pub fn new(allow: HashSet<TExtendedAddress>, deny: HashSet<TExtendedAddress>, max_devices: usize) -> Self {
+        ListPolicy { allow, deny, max_devices }
+    }
+
+    pub fn from_config(g3_config: &app_config::G3, max_devices: usize) -> Self {
+        let max_devices = g3_config
+            .max_devices
+            .map_or(max_devices, |m| (m as usize).min(max_devices));
+        ListPolicy::new(
+            parse_list(&g3_config.allow_list, "allow_list"),
+            parse_list(&g3_config.deny_list, "deny_list"),
+            max_devices,
+        )
+    }
+}
+
+impl AdmissionPolicy for ListPolicy {
+    fn admit(&self, ext_addr: &TExtendedAddress, device_count: usize) -> Admission {
+        if self.deny.contains(ext_addr) {
+            return Admission::Decline("deny list".to_string());
+        }
+        if !self.allow.is_empty() && !self.allow.contains(ext_addr) {
+            return Admission::Decline("not in allow list".to_string());
+        }
+        if device_count >= self.max_devices {
+            return Admission::Decline(format!("max devices {} reached", self.max_devices));
+        }
+        Admission::Accept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext_addr(last: u8) -> TExtendedAddress {
+        TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, last])
+    }
+
+    #[test]
+    fn lists_and_max_devices() {
+        let allow = HashSet::from([ext_addr(1), ext_addr(2)]);
+        let deny = HashSet::from([ext_addr(2), ext_addr(3)]);
+        let policy = ListPolicy::new(allow, deny, 2);
+        assert_eq!(policy.admit(&ext_addr(1), 0), Admission::Accept);
+        //The deny list wins over the allow list
+        assert_eq!(policy.admit(&ext_addr(2), 0), Admission::Decline("deny list".to_string()));
+        assert_eq!(policy.admit(&ext_addr(4), 0), Admission::Decline("not in allow list".to_string()));
+        assert_eq!(policy.admit(&ext_addr(1), 2), Admission::Decline("max devices 2 reached".to_string()));
+        //The lists are checked before the cap
+        assert_eq!(policy.admit(&ext_addr(3), 2), Admission::Decline("deny list".to_string()));
+
+        let open = ListPolicy::new(HashSet::new(), HashSet::from([ext_addr(3)]), 2);
+        assert_eq!(open.admit(&ext_addr(4), 1), Admission::Accept);
+        assert_eq!(open.admit(&ext_addr(3), 1), Admission::Decline("deny list".to_string()));
+    }
+}
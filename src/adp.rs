@@ -1,10 +1,13 @@
 use crate::usi;
+use crate::common;
 use num_enum::FromPrimitive;
 use num_enum::IntoPrimitive;
 use num_enum::TryFromPrimitive;
 use serde::Deserialize;
+use serde::Serialize;
 use std::fmt;
 use std::net::Ipv6Addr;
+use std::str::FromStr;
 use usi::InMessage;
 
 pub const G3_SERIAL_MSG_STATUS: u8 = 0;
@@ -181,6 +184,39 @@ impl TryFrom<Ipv6Addr> for TExtendedAddress {
         ipv6_addr.octets()[8..].try_into()
     }
 }
+impl fmt::Display for TExtendedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let strs: Vec<String> = self.0.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{}", strs.join(":"))
+    }
+}
+/// Parses an EUI-64 written as 16 hex digits, optionally separated by ':' or '-'
+impl FromStr for TExtendedAddress {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: String = s.chars().filter(|c| *c != ':' && *c != '-').collect();
+        if !digits.is_ascii() || digits.len() != ADP_ADDRESS_64BITS * 2 {
+            return Err(format!("invalid extended address {}", s));
+        }
+        let mut r = [0u8; ADP_ADDRESS_64BITS];
+        for i in 0..ADP_ADDRESS_64BITS {
+            r[i] = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid extended address {}", s))?;
+        }
+        Ok(TExtendedAddress(r))
+    }
+}
+impl Serialize for TExtendedAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for TExtendedAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
 
 // #[repr(C)]
 // #[derive(Copy, Clone)]
@@ -261,10 +297,11 @@ pub struct TAdpPanDescriptor {
  * @param m_u8Mns MetricNotSupported: 1 the metric type is not supported by the hop, 0 if supported
  * @param  m_u8LinkCost LinkCost of the node
  **********************************************************************************************************************/
-struct THopDescriptor {
-    m_u16HopAddress: u16,
-    m_u8Mns: u8,
-    m_u8LinkCost: u8,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct THopDescriptor {
+    pub hop_address: u16,
+    pub mns: u8,
+    pub link_cost: u8,
 }
 
 /**********************************************************************************************************************/
@@ -280,15 +317,96 @@ struct THopDescriptor {
  * @param m_aForwardPath Table with the information of each hop in forward direction (according to m_u8ForwardHopsCount)
  * @param m_aReversePath Table with the information of each hop in reverse direction (according to m_u8ReverseHopsCount)
  **********************************************************************************************************************/
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TPathDescriptor {
-    m_u16DstAddr: u16,
-    m_u16ExpectedOrigAddr: u16,
-    m_u16OrigAddr: u16,
-    m_u8MetricType: u8,
-    m_u8ForwardHopsCount: u8,
-    m_u8ReverseHopsCount: u8,
-    m_aForwardPath: [THopDescriptor; 16],
-    m_aReversePath: [THopDescriptor; 16],
+    pub dst_addr: u16,
+    pub expected_orig_addr: u16,
+    pub orig_addr: u16,
+    pub metric_type: u8,
+    pub forward_path: Vec<THopDescriptor>,
+    pub reverse_path: Vec<THopDescriptor>,
+}
+
+/// Hop address (2), MNS (1), link cost (1)
+const HOP_DESCRIPTOR_LEN: usize = 4;
+
+impl TPathDescriptor {
+    /// dst (2), expected originator (2), originator (2), metric type (1), forward hops (1), reverse hops (1),
+    /// then the forward and the reverse hops
+    fn try_from_bytes(buf: &[u8]) -> Option<TPathDescriptor> {
+        let (header, hops) = (buf.get(..9)?, &buf[9..]);
+        let forward_count = header[7] as usize;
+        let reverse_count = header[8] as usize;
+        if hops.len() != (forward_count + reverse_count) * HOP_DESCRIPTOR_LEN {
+            return None;
+        }
+        let mut path = hops.chunks(HOP_DESCRIPTOR_LEN).map(|hop| THopDescriptor {
+            hop_address: u16::from_be_bytes([hop[0], hop[1]]),
+            mns: hop[2],
+            link_cost: hop[3],
+        });
+        Some(TPathDescriptor {
+            dst_addr: u16::from_be_bytes([header[0], header[1]]),
+            expected_orig_addr: u16::from_be_bytes([header[2], header[3]]),
+            orig_addr: u16::from_be_bytes([header[4], header[5]]),
+            metric_type: header[6],
+            forward_path: path.by_ref().take(forward_count).collect(),
+            reverse_path: path.collect(),
+        })
+    }
+}
+/**********************************************************************************************************************/
+/** Routing table entry, the value of ADP_IB_ROUTING_TABLE
+ *
+ ***********************************************************************************************************************
+ * @param dst_addr Short address of the destination
+ * @param next_hop_addr Short address of the next hop towards the destination
+ * @param route_cost Cumulative link cost of the route
+ * @param hop_count Number of hops of the route
+ * @param weak_link_count Number of weak links of the route
+ * @param valid_time Remaining time the entry is valid, in minutes
+ **********************************************************************************************************************/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TAdpRoutingTableEntry {
+    pub dst_addr: u16,
+    pub next_hop_addr: u16,
+    pub route_cost: u16,
+    pub hop_count: u8,
+    pub weak_link_count: u8,
+    pub valid_time: u16,
+}
+
+/// dst (2), next hop (2), route cost (2), hop count (4 bits) and weak link count (4 bits), valid time (2)
+pub const ROUTING_TABLE_ENTRY_LEN: usize = 9;
+
+impl TryFrom<&[u8]> for TAdpRoutingTableEntry {
+    type Error = String;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value {
+            [d0, d1, n0, n1, c0, c1, hops, t0, t1] => Ok(TAdpRoutingTableEntry {
+                dst_addr: u16::from_be_bytes([*d0, *d1]),
+                next_hop_addr: u16::from_be_bytes([*n0, *n1]),
+                route_cost: u16::from_be_bytes([*c0, *c1]),
+                hop_count: hops >> 4,
+                weak_link_count: hops & 0x0F,
+                valid_time: u16::from_be_bytes([*t0, *t1]),
+            }),
+            _ => Err(format!("invalid routing table entry {}", common::to_hex_string(value))),
+        }
+    }
+}
+
+impl Into<Vec<u8>> for &TAdpRoutingTableEntry {
+    fn into(self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(ROUTING_TABLE_ENTRY_LEN);
+        v.extend_from_slice(&self.dst_addr.to_be_bytes());
+        v.extend_from_slice(&self.next_hop_addr.to_be_bytes());
+        v.extend_from_slice(&self.route_cost.to_be_bytes());
+        v.push((self.hop_count << 4) | (self.weak_link_count & 0x0F));
+        v.extend_from_slice(&self.valid_time.to_be_bytes());
+        v
+    }
 }
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive, Hash, Copy, Clone)]
 #[repr(u32)]
@@ -490,6 +608,15 @@ pub enum EMacWrpPibAttribute {
 pub fn usi_message_to_message(msg: &InMessage) -> Option<Message> {
     {
         if let Some(cmd) = msg.buf.get(0) {
+            //The MAC sniffer comes with PROTOCOL_MAC_G3, its cmd does not share its byte with the length
+            //and is above the 6 bits of the ADP cmds
+            if msg.protocol_type == Some(common::PROTOCOL_MAC_G3) && *cmd == G3_SERIAL_MSG_MAC_SNIFFER_INDICATION {
+                if let Some(sniffer_event) = AdpG3MacSnifferEvent::try_from_message(&msg) {
+                    return Some(Message::AdpG3MacSnifferEvent(sniffer_event));
+                }
+                log::warn!("Failed to parse message : {:?}", msg);
+                return None;
+            }
             match *cmd & 0x3F {
                 G3_SERIAL_MSG_ADP_DATA_CONFIRM => {
                     if let Some(data_response) = AdpG3DataResponse::try_from_message(&msg) {
@@ -586,6 +713,46 @@ pub fn usi_message_to_message(msg: &InMessage) -> Option<Message> {
                         return Some(Message::AdpG3BufferEvent(buffer_indication));
                     }
                 }
+                G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_CONFIRM => {
+                    if let Some(route_discovery_response) = AdpG3RouteDiscoveryResponse::try_from_message(&msg) {
+                        return Some(Message::AdpG3RouteDiscoveryResponse(route_discovery_response));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_PATH_DISCOVERY_CONFIRM => {
+                    if let Some(path_discovery_response) = AdpG3PathDiscoveryResponse::try_from_message(&msg) {
+                        return Some(Message::AdpG3PathDiscoveryResponse(path_discovery_response));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_NETWORK_LEAVE_CONFIRM => {
+                    if let Some(leave_response) = AdpG3NetworkLeaveResponse::try_from_message(&msg) {
+                        return Some(Message::AdpG3NetworkLeaveResponse(leave_response));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_NETWORK_LEAVE_INDICATION => {
+                    if let Some(leave_event) = AdpG3NetworkLeaveEvent::try_from_message(&msg) {
+                        return Some(Message::AdpG3NetworkLeaveEvent(leave_event));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_RESET_CONFIRM => {
+                    if let Some(reset_response) = AdpG3ResetResponse::try_from_message(&msg) {
+                        return Some(Message::AdpG3ResetResponse(reset_response));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_PREQ_INDICATION => {
+                    if let Some(preq_event) = AdpG3PreqEvent::try_from_message(&msg) {
+                        return Some(Message::AdpG3PreqEvent(preq_event));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_UPD_NON_VOLATILE_DATA_INDICATION => {
+                    if let Some(non_volatile_event) = AdpG3UpdNonVolatileDataEvent::try_from_message(&msg) {
+                        return Some(Message::AdpG3UpdNonVolatileDataEvent(non_volatile_event));
+                    }
+                }
+                G3_SERIAL_MSG_ADP_ROUTE_NOT_FOUND_INDICATION => {
+                    if let Some(route_not_found_event) = AdpG3RouteNotFoundEvent::try_from_message(&msg) {
+                        return Some(Message::AdpG3RouteNotFoundEvent(route_not_found_event));
+                    }
+                }
                 _ => return None,
             }
         }
@@ -594,7 +761,7 @@ pub fn usi_message_to_message(msg: &InMessage) -> Option<Message> {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum EAdpStatus {
     /// Success
@@ -687,6 +854,7 @@ pub enum Message {
     AdpG3PreqEvent(AdpG3PreqEvent),
     AdpG3UpdNonVolatileDataEvent(AdpG3UpdNonVolatileDataEvent),
     AdpG3RouteNotFoundEvent(AdpG3RouteNotFoundEvent),
+    AdpG3MacSnifferEvent(AdpG3MacSnifferEvent),
     // CoordG3,
     // PrimeApi,
     // UserDefined,
@@ -698,17 +866,17 @@ pub enum Message {
 pub enum AdpG3 {}
 #[derive(Debug)]
 pub struct AdpG3MsgStatusResponse {
-    status: EAdpStatus,
-    cmd: u8,
+    pub status: EAdpStatus,
+    /// Command of the request the status is for
+    pub cmd: u8,
 }
 
 impl AdpG3MsgStatusResponse {
     pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3MsgStatusResponse> {
         if msg.buf.len() > 0 {
-            //cmd is the first byte???
             //Add one byte for cmd
-            if let Ok(status) = EAdpStatus::try_from(msg.buf[0]) {
-                if let Some(&cmd) = msg.buf.get(1) {
+            if let Some(Ok(status)) = msg.buf.get(1).map(|s| EAdpStatus::try_from(*s)) {
+                if let Some(&cmd) = msg.buf.get(2) {
                     return Some(AdpG3MsgStatusResponse { status, cmd });
                 }
             }
@@ -897,10 +1065,36 @@ impl AdpG3LbpEvent {
 }
 
 #[derive(Debug)]
-pub struct AdpG3RouteDiscoveryResponse {}
+pub struct AdpG3RouteDiscoveryResponse {
+    pub status: EAdpStatus,
+}
+
+impl AdpG3RouteDiscoveryResponse {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3RouteDiscoveryResponse> {
+        if msg.buf.len() == 2 {
+            //Add one byte for cmd
+            if let Ok(status) = EAdpStatus::try_from(msg.buf[1]) {
+                return Some(AdpG3RouteDiscoveryResponse { status });
+            }
+        }
+        None
+    }
+}
 
 #[derive(Debug)]
-pub struct AdpG3PathDiscoveryResponse {}
+pub struct AdpG3PathDiscoveryResponse {
+    pub status: EAdpStatus,
+    pub path_descriptor: TPathDescriptor,
+}
+
+impl AdpG3PathDiscoveryResponse {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3PathDiscoveryResponse> {
+        //Add one byte for cmd
+        let status = EAdpStatus::try_from(*msg.buf.get(1)?).ok()?;
+        let path_descriptor = TPathDescriptor::try_from_bytes(&msg.buf[2..])?;
+        Some(AdpG3PathDiscoveryResponse { status, path_descriptor })
+    }
+}
 
 #[derive(Debug)]
 pub struct AdpG3BufferEvent {
@@ -948,14 +1142,123 @@ impl AdpG3DiscoveryEvent {
     }
 }
 
+/// A path request was received, the indication carries no parameter
 #[derive(Debug)]
 pub struct AdpG3PreqEvent {}
 
+impl AdpG3PreqEvent {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3PreqEvent> {
+        match msg.buf.len() {
+            1 => Some(AdpG3PreqEvent {}),
+            _ => None,
+        }
+    }
+}
+
+/// Counters the host has to keep across resets of the modem and give back to it after the initialization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdpG3UpdNonVolatileDataEvent {
+    pub frame_counter: u32,
+    pub discover_seq_number: u16,
+    pub broadcast_seq_number: u8,
+}
+
+const NON_VOLATILE_DATA_EVENT_LEN: usize = 7;
+
+impl AdpG3UpdNonVolatileDataEvent {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3UpdNonVolatileDataEvent> {
+        if msg.buf.len() == NON_VOLATILE_DATA_EVENT_LEN + 1 {
+            //Add one byte for cmd
+            return Some(AdpG3UpdNonVolatileDataEvent {
+                frame_counter: u32::from_be_bytes([msg.buf[1], msg.buf[2], msg.buf[3], msg.buf[4]]),
+                discover_seq_number: u16::from_be_bytes([msg.buf[5], msg.buf[6]]),
+                broadcast_seq_number: msg.buf[7],
+            });
+        }
+        None
+    }
+}
+
+/// A frame could not be routed, it is given back with the addresses of the route
 #[derive(Debug)]
-pub struct AdpG3UpdNonVolatileDataEvent {}
+pub struct AdpG3RouteNotFoundEvent {
+    pub src_addr: u16,
+    pub dst_addr: u16,
+    pub next_hop_addr: u16,
+    pub previous_hop_addr: u16,
+    pub route_cost: u16,
+    pub hop_count: u8,
+    pub weak_link_count: u8,
+    pub route_just_broken: bool,
+    pub compressed_header: bool,
+    pub nsdu: Vec<u8>,
+}
+
+const ROUTE_NOT_FOUND_EVENT_MIN_LEN: usize = 16;
+
+impl AdpG3RouteNotFoundEvent {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3RouteNotFoundEvent> {
+        if msg.buf.len() < ROUTE_NOT_FOUND_EVENT_MIN_LEN + 1 {
+            return None;
+        }
+        //Add one byte for cmd
+        let b = &msg.buf;
+        let nsdu_len = u16::from_be_bytes([b[15], b[16]]) as usize;
+        if b.len() != ROUTE_NOT_FOUND_EVENT_MIN_LEN + 1 + nsdu_len {
+            return None;
+        }
+        Some(AdpG3RouteNotFoundEvent {
+            src_addr: u16::from_be_bytes([b[1], b[2]]),
+            dst_addr: u16::from_be_bytes([b[3], b[4]]),
+            next_hop_addr: u16::from_be_bytes([b[5], b[6]]),
+            previous_hop_addr: u16::from_be_bytes([b[7], b[8]]),
+            route_cost: u16::from_be_bytes([b[9], b[10]]),
+            hop_count: b[11],
+            weak_link_count: b[12],
+            route_just_broken: b[13] != 0,
+            compressed_header: b[14] != 0,
+            nsdu: b[17..].to_vec(),
+        })
+    }
+}
 
+/// A frame heard on the medium by the MAC sniffer (MAC_WRP_PIB_MANUF_ENABLE_MAC_SNIFFER), whoever it is for.
+/// The frame starts with the IEEE 802.15.4 frame control, the G3 segment control and the FCS are removed.
 #[derive(Debug)]
-pub struct AdpG3RouteNotFoundEvent {}
+pub struct AdpG3MacSnifferEvent {
+    pub modulation_type: u8,
+    pub modulation_scheme: u8,
+    pub tone_map: [u8; 3],
+    pub lqi: u8,
+    /// Reception time in microseconds, modem clock
+    pub rx_time: u32,
+    pub frame: Vec<u8>,
+}
+
+/// modulation type (1), modulation scheme (1), tone map (3), lqi (1), rx time (4), frame length (2)
+const MAC_SNIFFER_EVENT_MIN_LEN: usize = 12;
+
+impl AdpG3MacSnifferEvent {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3MacSnifferEvent> {
+        if msg.buf.len() < MAC_SNIFFER_EVENT_MIN_LEN + 1 {
+            return None;
+        }
+        //Add one byte for cmd
+        let b = &msg.buf;
+        let frame_len = u16::from_be_bytes([b[11], b[12]]) as usize;
+        if b.len() != MAC_SNIFFER_EVENT_MIN_LEN + 1 + frame_len {
+            return None;
+        }
+        Some(AdpG3MacSnifferEvent {
+            modulation_type: b[1],
+            modulation_scheme: b[2],
+            tone_map: [b[3], b[4], b[5]],
+            lqi: b[6],
+            rx_time: u32::from_be_bytes([b[7], b[8], b[9], b[10]]),
+            frame: b[13..].to_vec(),
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct AdpG3NetworkJoinResponse {
@@ -992,14 +1295,52 @@ impl AdpG3NetworkJoinResponse {
     }
 }
 
+/// The device was removed from the network, the indication carries no parameter
 #[derive(Debug)]
 pub struct AdpG3NetworkLeaveEvent {}
 
+impl AdpG3NetworkLeaveEvent {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3NetworkLeaveEvent> {
+        match msg.buf.len() {
+            1 => Some(AdpG3NetworkLeaveEvent {}),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
-pub struct AdpG3NetworkLeaveResponse {}
+pub struct AdpG3NetworkLeaveResponse {
+    pub status: EAdpStatus,
+}
+
+impl AdpG3NetworkLeaveResponse {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3NetworkLeaveResponse> {
+        if msg.buf.len() == 2 {
+            //Add one byte for cmd
+            if let Ok(status) = EAdpStatus::try_from(msg.buf[1]) {
+                return Some(AdpG3NetworkLeaveResponse { status });
+            }
+        }
+        None
+    }
+}
 
 #[derive(Debug)]
-pub struct AdpG3ResetResponse {}
+pub struct AdpG3ResetResponse {
+    pub status: EAdpStatus,
+}
+
+impl AdpG3ResetResponse {
+    pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3ResetResponse> {
+        if msg.buf.len() == 2 {
+            //Add one byte for cmd
+            if let Ok(status) = EAdpStatus::try_from(msg.buf[1]) {
+                return Some(AdpG3ResetResponse { status });
+            }
+        }
+        None
+    }
+}
 
 #[derive(Debug)]
 pub struct AdpG3SetMacResponse {
@@ -1176,8 +1517,8 @@ impl AdpG3NetworkStatusEvent {
     }
 }
 pub struct AdpG3DataResponse {
-    status: EAdpStatus,
-    nsdu_handle: u8,
+    pub status: EAdpStatus,
+    pub nsdu_handle: u8,
 }
 impl AdpG3DataResponse {
     pub fn try_from_message(msg: &usi::InMessage) -> Option<AdpG3DataResponse> {
@@ -1221,4 +1562,143 @@ impl AdpG3DataEvent {
         }
         None
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common;
+    use crate::request::{
+        AdpNetworkLeaveRequest, AdpPathDiscoveryRequest, AdpResetRequest, AdpRouteDiscoveryRequest,
+    };
+
+    /// Goes through the USI framing both ways, as a frame sent to or received from the modem
+    fn round_trip(msg: usi::OutMessage) -> usi::InMessage {
+        let mut frames = usi::Decoder::new().decode(&msg.to_usi().unwrap());
+        assert_eq!(frames.len(), 1);
+        frames.remove(0)
+    }
+
+    fn indication(data: &[u8]) -> Option<Message> {
+        usi_message_to_message(&round_trip(usi::OutMessage::new(common::PROTOCOL_ADP_G3, data)))
+    }
+
+    #[test]
+    fn requests() {
+        let msg = round_trip(AdpRouteDiscoveryRequest::new(0x1234, 8).into());
+        assert_eq!(msg.buf, vec![G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_REQUEST, 0x12, 0x34, 8]);
+        let msg = round_trip(AdpPathDiscoveryRequest::new(0x0005, 0x0F).into());
+        assert_eq!(msg.buf, vec![G3_SERIAL_MSG_ADP_PATH_DISCOVERY_REQUEST, 0x00, 0x05, 0x0F]);
+        let msg = round_trip(AdpNetworkLeaveRequest::new().into());
+        assert_eq!(msg.buf, vec![G3_SERIAL_MSG_ADP_NETWORK_LEAVE_REQUEST]);
+        let msg = round_trip(AdpResetRequest::new().into());
+        assert_eq!(msg.buf, vec![G3_SERIAL_MSG_ADP_RESET_REQUEST]);
+        assert_eq!(msg.protocol_type, Some(common::PROTOCOL_ADP_G3));
+    }
+
+    #[test]
+    fn status_confirms() {
+        match indication(&[G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_CONFIRM, 0x00]) {
+            Some(Message::AdpG3RouteDiscoveryResponse(r)) => assert_eq!(r.status, EAdpStatus::G3_SUCCESS),
+            m => panic!("unexpected {:?}", m),
+        }
+        match indication(&[G3_SERIAL_MSG_ADP_NETWORK_LEAVE_CONFIRM, 0xA2]) {
+            Some(Message::AdpG3NetworkLeaveResponse(r)) => assert_eq!(r.status, EAdpStatus::G3_FAILED),
+            m => panic!("unexpected {:?}", m),
+        }
+        match indication(&[G3_SERIAL_MSG_ADP_RESET_CONFIRM, 0x00]) {
+            Some(Message::AdpG3ResetResponse(r)) => assert_eq!(r.status, EAdpStatus::G3_SUCCESS),
+            m => panic!("unexpected {:?}", m),
+        }
+        assert!(matches!(
+            indication(&[G3_SERIAL_MSG_ADP_NETWORK_LEAVE_INDICATION]),
+            Some(Message::AdpG3NetworkLeaveEvent(_))
+        ));
+        assert!(matches!(indication(&[G3_SERIAL_MSG_ADP_PREQ_INDICATION]), Some(Message::AdpG3PreqEvent(_))));
+        assert!(indication(&[G3_SERIAL_MSG_ADP_RESET_CONFIRM]).is_none());
+    }
+
+    #[test]
+    fn path_discovery_confirm() {
+        let data = [
+            G3_SERIAL_MSG_ADP_PATH_DISCOVERY_CONFIRM, 0x00,
+            0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x0F, 2, 1,
+            0x00, 0x02, 0x01, 0x10,
+            0x00, 0x05, 0x00, 0x20,
+            0x00, 0x00, 0x00, 0x30,
+        ];
+        match indication(&data) {
+            Some(Message::AdpG3PathDiscoveryResponse(r)) => {
+                assert_eq!(r.status, EAdpStatus::G3_SUCCESS);
+                let path = r.path_descriptor;
+                assert_eq!(path.dst_addr, 0x0005);
+                assert_eq!(path.metric_type, 0x0F);
+                assert_eq!(
+                    path.forward_path,
+                    vec![
+                        THopDescriptor { hop_address: 2, mns: 1, link_cost: 0x10 },
+                        THopDescriptor { hop_address: 5, mns: 0, link_cost: 0x20 },
+                    ]
+                );
+                assert_eq!(path.reverse_path, vec![THopDescriptor { hop_address: 0, mns: 0, link_cost: 0x30 }]);
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+        // A hop count not matching the hops
+        assert!(indication(&data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn non_volatile_data_indication() {
+        let data = [G3_SERIAL_MSG_ADP_UPD_NON_VOLATILE_DATA_INDICATION, 0x00, 0x01, 0x02, 0x03, 0x00, 0x10, 0x07];
+        match indication(&data) {
+            Some(Message::AdpG3UpdNonVolatileDataEvent(e)) => assert_eq!(
+                e,
+                AdpG3UpdNonVolatileDataEvent {
+                    frame_counter: 0x00010203,
+                    discover_seq_number: 0x0010,
+                    broadcast_seq_number: 7
+                }
+            ),
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    #[test]
+    fn route_not_found_indication() {
+        let data = [
+            G3_SERIAL_MSG_ADP_ROUTE_NOT_FOUND_INDICATION,
+            0x00, 0x00, 0x00, 0x07, 0x00, 0x03, 0xFF, 0xFF, 0x00, 0x2A, 3, 1, 1, 0,
+            0x00, 0x02, 0x60, 0x00,
+        ];
+        match indication(&data) {
+            Some(Message::AdpG3RouteNotFoundEvent(e)) => {
+                assert_eq!((e.src_addr, e.dst_addr, e.next_hop_addr, e.previous_hop_addr), (0, 7, 3, 0xFFFF));
+                assert_eq!((e.route_cost, e.hop_count, e.weak_link_count), (0x2A, 3, 1));
+                assert!(e.route_just_broken);
+                assert!(!e.compressed_header);
+                assert_eq!(e.nsdu, vec![0x60, 0x00]);
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    #[test]
+    fn mac_sniffer_indication() {
+        let data = [
+            G3_SERIAL_MSG_MAC_SNIFFER_INDICATION,
+            1, 0, 0x3F, 0xFF, 0xFF, 0xB4, 0x00, 0x00, 0x10, 0x00,
+            0x00, 0x03, 0x41, 0xCC, 0x2A,
+        ];
+        let indication = |data: &[u8]| usi_message_to_message(&round_trip(usi::OutMessage::new(common::PROTOCOL_MAC_G3, data)));
+        match indication(&data) {
+            Some(Message::AdpG3MacSnifferEvent(e)) => {
+                assert_eq!((e.modulation_type, e.modulation_scheme, e.tone_map), (1, 0, [0x3F, 0xFF, 0xFF]));
+                assert_eq!((e.lqi, e.rx_time), (0xB4, 0x1000));
+                assert_eq!(e.frame, vec![0x41, 0xCC, 0x2A]);
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+        // A frame length not matching the frame
+        assert!(indication(&data[..data.len() - 1]).is_none());
+    }
+}
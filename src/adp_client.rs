@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::adp;
+use crate::adp::{EAdpPibAttribute, EAdpStatus, EMacWrpPibAttribute};
+#[cfg(test)]
+use crate::pib::PibAttribute;
+use crate::request;
+use crate::usi;
+
+/// Time to wait for a confirm, the discoveries wait longer
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time a path discovery can take, adpPathDiscoveryTime of the G3 specification
+pub const PATH_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(40);
+/// Time a route discovery can take with the retries of its route requests
+pub const ROUTE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a confirm is matched to its request with, only one request per key can wait for its confirm
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum CorrelationKey {
+    Get { attribute_id: u32, attribute_idx: u16 },
+    Set { attribute_id: u32, attribute_idx: u16 },
+    MacGet { attribute_id: u32, attribute_idx: u16 },
+    MacSet { attribute_id: u32, attribute_idx: u16 },
+    Data { nsdu_handle: u8 },
+    Lbp { nsdu_handle: u8 },
+    PathDiscovery { dst_addr: u16 },
+    /// The confirm does not give the destination, one route discovery at a time
+    RouteDiscovery,
+}
+
+impl CorrelationKey {
+    /// Command of the request, a G3_SERIAL_MSG_STATUS for it carries this command
+    fn cmd(&self) -> u8 {
+        match self {
+            CorrelationKey::Get { .. } => adp::G3_SERIAL_MSG_ADP_GET_REQUEST,
+            CorrelationKey::Set { .. } => adp::G3_SERIAL_MSG_ADP_SET_REQUEST,
+            CorrelationKey::MacGet { .. } => adp::G3_SERIAL_MSG_ADP_MAC_GET_REQUEST,
+            CorrelationKey::MacSet { .. } => adp::G3_SERIAL_MSG_ADP_MAC_SET_REQUEST,
+            CorrelationKey::Data { .. } => adp::G3_SERIAL_MSG_ADP_DATA_REQUEST,
+            CorrelationKey::Lbp { .. } => adp::G3_SERIAL_MSG_ADP_LBP_REQUEST,
+            CorrelationKey::PathDiscovery { .. } => adp::G3_SERIAL_MSG_ADP_PATH_DISCOVERY_REQUEST,
+            CorrelationKey::RouteDiscovery => adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_REQUEST,
+        }
+    }
+
+    /// Key and status of a confirm, None for the other messages
+    fn of_confirm(msg: &adp::Message) -> Option<(CorrelationKey, EAdpStatus)> {
+        match msg {
+            adp::Message::AdpG3GetResponse(r) => Some((
+                CorrelationKey::Get { attribute_id: r.attribute_id, attribute_idx: r.attribute_idx },
+                r.status,
+            )),
+            adp::Message::AdpG3SetResponse(r) => Some((
+                CorrelationKey::Set { attribute_id: r.attribute_id, attribute_idx: r.attribute_idx },
+                r.status,
+            )),
+            adp::Message::AdpG3GetMacResponse(r) => Some((
+                CorrelationKey::MacGet { attribute_id: r.attribute_id, attribute_idx: r.attribute_idx },
+                r.status,
+            )),
+            adp::Message::AdpG3SetMacResponse(r) => Some((
+                CorrelationKey::MacSet { attribute_id: r.attribute_id, attribute_idx: r.attribute_idx },
+                r.status,
+            )),
+            adp::Message::AdpG3DataResponse(r) => Some((CorrelationKey::Data { nsdu_handle: r.nsdu_handle }, r.status)),
+            adp::Message::AdpG3LbpReponse(r) => Some((CorrelationKey::Lbp { nsdu_handle: r.handle }, r.status)),
+            adp::Message::AdpG3PathDiscoveryResponse(r) => Some((
+                CorrelationKey::PathDiscovery { dst_addr: r.path_descriptor.dst_addr },
+                r.status,
+            )),
+            adp::Message::AdpG3RouteDiscoveryResponse(r) => Some((CorrelationKey::RouteDiscovery, r.status)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// A request with the same key is still waiting for its confirm
+    Busy(CorrelationKey),
+    Timeout(CorrelationKey),
+    /// The modem answered with an error status
+    Status(CorrelationKey, EAdpStatus),
+    /// The confirm does not have the type of the request
+    UnexpectedConfirm(CorrelationKey),
+    /// The USI port is gone
+    Disconnected,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Busy(key) => write!(f, "request {:?} already waiting for its confirm", key),
+            ClientError::Timeout(key) => write!(f, "no confirm for {:?}", key),
+            ClientError::Status(key, status) => write!(f, "{:?} failed with {:?}", key, status),
+            ClientError::UnexpectedConfirm(key) => write!(f, "unexpected confirm for {:?}", key),
+            ClientError::Disconnected => write!(f, "usi port disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Error of a read of the PIB tables of the modem
+#[derive(Debug)]
+pub enum PibError {
+    Client(ClientError),
+    /// The modem gave a count or an entry that does not parse
+    InvalidValue(String),
+}
+
+impl fmt::Display for PibError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PibError::Client(e) => write!(f, "{}", e),
+            PibError::InvalidValue(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PibError {}
+
+impl From<ClientError> for PibError {
+    fn from(e: ClientError) -> Self {
+        PibError::Client(e)
+    }
+}
+
+/// Confirm a request completes with
+pub trait Confirm: Sized {
+    fn from_message(msg: adp::Message) -> Option<Self>;
+}
+
+impl Confirm for adp::AdpG3GetResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3GetResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3SetResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3SetResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3GetMacResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3GetMacResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3SetMacResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3SetMacResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3DataResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3DataResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3LbpReponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3LbpReponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3PathDiscoveryResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3PathDiscoveryResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl Confirm for adp::AdpG3RouteDiscoveryResponse {
+    fn from_message(msg: adp::Message) -> Option<Self> {
+        match msg {
+            adp::Message::AdpG3RouteDiscoveryResponse(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    tx: flume::Sender<adp::Message>,
+    sent: Instant,
+    deadline: Instant,
+}
+
+type PendingMap = Arc<Mutex<HashMap<CorrelationKey, Pending>>>;
+
+/// Request waiting for its confirm
+#[derive(Debug)]
+pub struct PendingConfirm<T> {
+    key: CorrelationKey,
+    rx: flume::Receiver<adp::Message>,
+    deadline: Instant,
+    pending: PendingMap,
+    confirm: PhantomData<T>,
+}
+
+impl<T: Confirm> PendingConfirm<T> {
+    /// Blocks until the confirm is received or the timeout
+    pub fn wait(self) -> Result<T, ClientError> {
+        match self.rx.recv_deadline(self.deadline) {
+            Ok(msg) => self.result(msg),
+            Err(flume::RecvTimeoutError::Timeout) => {
+                self.expire();
+                Err(ClientError::Timeout(self.key))
+            }
+            Err(flume::RecvTimeoutError::Disconnected) => Err(self.disconnected()),
+        }
+    }
+
+    /// For the polling loops, None while the confirm can still come
+    pub fn try_wait(&self) -> Option<Result<T, ClientError>> {
+        match self.rx.try_recv() {
+            Ok(msg) => Some(self.result(msg)),
+            Err(flume::TryRecvError::Empty) if Instant::now() < self.deadline => None,
+            Err(flume::TryRecvError::Empty) => {
+                self.expire();
+                Some(Err(ClientError::Timeout(self.key)))
+            }
+            Err(flume::TryRecvError::Disconnected) => Some(Err(self.disconnected())),
+        }
+    }
+
+    fn result(&self, msg: adp::Message) -> Result<T, ClientError> {
+        let status = match msg {
+            adp::Message::AdpG3MsgStatusResponse(ref r) => Some(r.status),
+            ref msg => CorrelationKey::of_confirm(msg).map(|(_, status)| status),
+        };
+        match status {
+            Some(EAdpStatus::G3_SUCCESS) => T::from_message(msg).ok_or(ClientError::UnexpectedConfirm(self.key)),
+            Some(status) => Err(ClientError::Status(self.key, status)),
+            None => Err(ClientError::UnexpectedConfirm(self.key)),
+        }
+    }
+
+    /// The client drops the expired requests, and the sender with them
+    fn disconnected(&self) -> ClientError {
+        match Instant::now() < self.deadline {
+            true => ClientError::Disconnected,
+            false => ClientError::Timeout(self.key),
+        }
+    }
+
+    /// Frees the key, unless a newer request already took it
+    fn expire(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.get(&self.key).is_some_and(|p| p.deadline == self.deadline) {
+            pending.remove(&self.key);
+        }
+    }
+}
+
+/// Sends ADP and MAC requests to the modem and hands their confirm back to the caller.
+///
+/// The app manager gives every message from the modem to `dispatch`, the confirms of requests
+/// sent through the client go to their `PendingConfirm` instead of the other consumers.
+#[derive(Debug, Clone)]
+pub struct AdpClient {
+    usi_tx: flume::Sender<usi::Message>,
+    pending: PendingMap,
+    timeout: Duration,
+    /// Last nsdu handle given by send_data, shared by the clones
+    data_handle: Arc<AtomicU8>,
+}
+
+impl AdpClient {
+    pub fn new(usi_tx: flume::Sender<usi::Message>) -> Self {
+        AdpClient {
+            usi_tx,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            timeout: CONFIRM_TIMEOUT,
+            data_handle: Arc::new(AtomicU8::new(0)),
+        }
+    }
+
+    pub fn get(&self, attribute_id: EAdpPibAttribute, attribute_idx: u16) -> Result<PendingConfirm<adp::AdpG3GetResponse>, ClientError> {
+        let key = CorrelationKey::Get { attribute_id: attribute_id.into(), attribute_idx };
+        self.send(key, request::AdpGetRequest::new(attribute_id, attribute_idx).into(), self.timeout)
+    }
+
+    pub fn mac_get(&self, attribute_id: EMacWrpPibAttribute, attribute_idx: u16) -> Result<PendingConfirm<adp::AdpG3GetMacResponse>, ClientError> {
+        let key = CorrelationKey::MacGet { attribute_id: attribute_id.into(), attribute_idx };
+        self.send(key, request::AdpMacGetRequest::new(attribute_id, attribute_idx).into(), self.timeout)
+    }
+
+    pub fn data(&self, nsdu_handle: u8, data: &Vec<u8>, discover_route: bool, quality_of_service: u8) -> Result<PendingConfirm<adp::AdpG3DataResponse>, ClientError> {
+        let key = CorrelationKey::Data { nsdu_handle };
+        let request = request::AdpDataRequest::new(nsdu_handle, data, discover_route, quality_of_service);
+        self.send(key, request.into(), self.timeout)
+    }
+
+    /// Sends data with the next free nsdu handle, the handles of the data requests waiting for their
+    /// confirm are skipped. Fails with Busy when all of them are waiting.
+    pub fn send_data(&self, data: &Vec<u8>, discover_route: bool, quality_of_service: u8) -> Result<PendingConfirm<adp::AdpG3DataResponse>, ClientError> {
+        for _ in 0..=u8::MAX {
+            let nsdu_handle = self.data_handle.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+            match self.data(nsdu_handle, data, discover_route, quality_of_service) {
+                Err(ClientError::Busy(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(ClientError::Busy(CorrelationKey::Data { nsdu_handle: self.data_handle.load(Ordering::Relaxed) }))
+    }
+
+    /// Discovers the path to dst_addr and back, waits PATH_DISCOVERY_TIMEOUT at least
+    pub fn path_discovery(&self, dst_addr: u16, metric_type: u8) -> Result<PendingConfirm<adp::AdpG3PathDiscoveryResponse>, ClientError> {
+        let key = CorrelationKey::PathDiscovery { dst_addr };
+        let request = request::AdpPathDiscoveryRequest::new(dst_addr, metric_type);
+        self.send(key, request.into(), self.timeout.max(PATH_DISCOVERY_TIMEOUT))
+    }
+
+    /// Discovers a route to dst_addr of at most max_hops, it is then in the routing table of the modem
+    pub fn route_discovery(&self, dst_addr: u16, max_hops: u8) -> Result<PendingConfirm<adp::AdpG3RouteDiscoveryResponse>, ClientError> {
+        let request = request::AdpRouteDiscoveryRequest::new(dst_addr, max_hops);
+        self.send(CorrelationKey::RouteDiscovery, request.into(), self.timeout.max(ROUTE_DISCOVERY_TIMEOUT))
+    }
+
+    /// Sends a request whose confirm matches key, fails with Busy while another request with the same key waits
+    pub fn send<T: Confirm>(&self, key: CorrelationKey, out: usi::OutMessage, timeout: Duration) -> Result<PendingConfirm<T>, ClientError> {
+        let (tx, rx) = flume::bounded(1);
+        let now = Instant::now();
+        let deadline = now + timeout;
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.get(&key).is_some_and(|p| p.deadline > now) {
+                return Err(ClientError::Busy(key));
+            }
+            pending.insert(key, Pending { tx, sent: now, deadline });
+        }
+        if self.usi_tx.send(usi::Message::UsiOut(out)).is_err() {
+            self.pending.lock().unwrap().remove(&key);
+            return Err(ClientError::Disconnected);
+        }
+        Ok(PendingConfirm {
+            key,
+            rx,
+            deadline,
+            pending: self.pending.clone(),
+            confirm: PhantomData,
+        })
+    }
+
+    /// Completes the request msg confirms. Returns msg when it is not for a request of the client.
+    pub fn dispatch(&self, msg: adp::Message) -> Option<adp::Message> {
+        let mut pending = self.pending.lock().unwrap();
+        let now = Instant::now();
+        pending.retain(|_, p| p.deadline > now);
+        let key = match msg {
+            //The modem rejected a request, the oldest one of that command is the one
+            adp::Message::AdpG3MsgStatusResponse(ref r) if r.status != EAdpStatus::G3_SUCCESS => pending
+                .iter()
+                .filter(|(k, _)| k.cmd() == r.cmd)
+                .min_by_key(|(_, p)| p.sent)
+                .map(|(k, _)| *k),
+            ref msg => CorrelationKey::of_confirm(msg).map(|(key, _)| key),
+        };
+        match key.and_then(|key| pending.remove(&key)) {
+            Some(p) => {
+                //The caller may have dropped the PendingConfirm, the confirm is not needed anymore
+                let _ = p.tx.send(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+}
+
+/// Answers count gets and MAC gets of the client as the modem does, with the status or the value
+/// `value` gives for the attribute and index of each
+#[cfg(test)]
+pub(crate) fn answer_gets<F>(client: &AdpClient, usi_rx: &flume::Receiver<usi::Message>, count: usize, mut value: F)
+where
+    F: FnMut(PibAttribute, u16) -> Result<Vec<u8>, EAdpStatus>,
+{
+    for _ in 0..count {
+        let out = match usi_rx.recv() {
+            Ok(usi::Message::UsiOut(out)) => out,
+            other => panic!("unexpected {:?}", other),
+        };
+        let data = out.data();
+        let attribute_id = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        let attribute_idx = u16::from_be_bytes([data[5], data[6]]);
+        let attribute = match data[0] {
+            adp::G3_SERIAL_MSG_ADP_GET_REQUEST => PibAttribute::Adp(EAdpPibAttribute::try_from(attribute_id).unwrap()),
+            adp::G3_SERIAL_MSG_ADP_MAC_GET_REQUEST => PibAttribute::Mac(EMacWrpPibAttribute::try_from(attribute_id).unwrap()),
+            command => panic!("unexpected command {:02X}", command),
+        };
+        let (status, attribute_val) = match value(attribute, attribute_idx) {
+            Ok(attribute_val) => (EAdpStatus::G3_SUCCESS, attribute_val),
+            Err(status) => (status, vec![]),
+        };
+        let attribute_len = attribute_val.len() as u8;
+        let response = match attribute {
+            PibAttribute::Adp(_) => adp::Message::AdpG3GetResponse(adp::AdpG3GetResponse {
+                status,
+                attribute_id,
+                attribute_idx,
+                attribute_len,
+                attribute_val,
+            }),
+            PibAttribute::Mac(_) => adp::Message::AdpG3GetMacResponse(adp::AdpG3GetMacResponse {
+                status,
+                attribute_id,
+                attribute_idx,
+                attribute_len,
+                attribute_val,
+            }),
+        };
+        client.dispatch(response);
+    }
+}
+
+/// A client whose requests a thread answers with answer_gets, join the thread once the gets are done
+#[cfg(test)]
+pub(crate) fn fake_modem<F>(count: usize, value: F) -> (AdpClient, std::thread::JoinHandle<()>)
+where
+    F: FnMut(PibAttribute, u16) -> Result<Vec<u8>, EAdpStatus> + Send + 'static,
+{
+    let (usi_tx, usi_rx) = flume::unbounded();
+    let client = AdpClient::new(usi_tx);
+    let modem = {
+        let client = client.clone();
+        std::thread::spawn(move || answer_gets(&client, &usi_rx, count, value))
+    };
+    (client, modem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_response(status: EAdpStatus) -> adp::Message {
+        adp::Message::AdpG3SetResponse(adp::AdpG3SetResponse {
+            status,
+            attribute_id: EAdpPibAttribute::ADP_IB_COORD_SHORT_ADDRESS.into(),
+            attribute_idx: 0,
+        })
+    }
+
+    fn set(client: &AdpClient) -> Result<PendingConfirm<adp::AdpG3SetResponse>, ClientError> {
+        let attribute_id = EAdpPibAttribute::ADP_IB_COORD_SHORT_ADDRESS;
+        let key = CorrelationKey::Set { attribute_id: attribute_id.into(), attribute_idx: 0 };
+        client.send(key, request::AdpSetRequest::new(attribute_id, 0, &vec![0, 0]).into(), client.timeout)
+    }
+
+    #[test]
+    fn confirm_completes_its_request() {
+        let (usi_tx, usi_rx) = flume::unbounded();
+        let client = AdpClient::new(usi_tx);
+        let confirm = set(&client).unwrap();
+        assert!(matches!(usi_rx.try_recv(), Ok(usi::Message::UsiOut(_))));
+        assert!(confirm.try_wait().is_none());
+        assert!(matches!(set(&client), Err(ClientError::Busy(_))));
+
+        assert!(client.dispatch(set_response(EAdpStatus::G3_SUCCESS)).is_none());
+        assert!(confirm.wait().is_ok());
+        //Not waited for anymore, goes to the other consumers
+        assert!(client.dispatch(set_response(EAdpStatus::G3_SUCCESS)).is_some());
+        assert!(set(&client).is_ok());
+    }
+
+    #[test]
+    fn error_status() {
+        let (usi_tx, _usi_rx) = flume::unbounded();
+        let client = AdpClient::new(usi_tx);
+        let confirm = set(&client).unwrap();
+        client.dispatch(set_response(EAdpStatus::G3_INVALID_REQUEST));
+        assert!(matches!(confirm.wait(), Err(ClientError::Status(_, EAdpStatus::G3_INVALID_REQUEST))));
+
+        let confirm = set(&client).unwrap();
+        let status = adp::AdpG3MsgStatusResponse {
+            status: EAdpStatus::G3_INVALID_REQUEST,
+            cmd: adp::G3_SERIAL_MSG_ADP_SET_REQUEST,
+        };
+        assert!(client.dispatch(adp::Message::AdpG3MsgStatusResponse(status)).is_none());
+        assert!(matches!(confirm.wait(), Err(ClientError::Status(_, EAdpStatus::G3_INVALID_REQUEST))));
+    }
+
+    #[test]
+    fn data_handles_are_not_reused_while_pending() {
+        let (usi_tx, _usi_rx) = flume::unbounded();
+        let client = AdpClient::new(usi_tx);
+        let first = client.data(2, &vec![0x60], true, 0).unwrap();
+        let handles: Vec<u8> = (0..2).map(|_| match client.send_data(&vec![0x60], true, 0).unwrap().key {
+            CorrelationKey::Data { nsdu_handle } => nsdu_handle,
+            key => panic!("unexpected key {:?}", key),
+        }).collect();
+        assert_eq!(handles, vec![1, 3]);
+
+        let response = adp::AdpG3DataResponse { status: EAdpStatus::G3_SUCCESS, nsdu_handle: 2 };
+        assert!(client.dispatch(adp::Message::AdpG3DataResponse(response)).is_none());
+        assert!(first.wait().is_ok());
+    }
+
+    #[test]
+    fn timeout_frees_the_key() {
+        let (usi_tx, _usi_rx) = flume::unbounded();
+        let mut client = AdpClient::new(usi_tx);
+        client.timeout = Duration::from_millis(10);
+        let confirm = set(&client).unwrap();
+        assert!(matches!(confirm.wait(), Err(ClientError::Timeout(_))));
+        assert!(set(&client).is_ok());
+    }
+}
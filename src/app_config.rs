@@ -9,15 +9,18 @@ use num_enum::TryFromPrimitive;
 use lazy_static::lazy_static;
 use serde::Serialize;
 use crate::network_manager::NetworkManager;
+use crate::gmk_store::StoredGmk;
 
 
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ArgEnum, Debug, 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, Debug, 
     TryFromPrimitive, IntoPrimitive, Deserialize)]
 #[repr(u8)]
 pub enum Mode{
     Coordinator = 0u8,
-    Modem
+    Modem,
+    /// Listens to all the frames of the medium, see sniffer
+    Sniffer,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -238,7 +241,24 @@ pub struct G3 {
     pub ids_arib: Vec<u8>,
     pub ids_cenelec_fcc: Vec<u8>,
     pub max_hops: u8,
-    pub discovery_timeout_secs: u8
+    pub discovery_timeout_secs: u8,
+    pub device_db: Option<String>,
+    pub psk_file: Option<String>,
+    pub psk_fallback: Option<bool>,
+    pub allow_list: Option<Vec<String>>,
+    pub deny_list: Option<Vec<String>>,
+    pub max_devices: Option<u16>,
+    pub active_key_index: Option<u8>,
+    /// File the GMK is kept in once changed by a rekey, see gmk_store
+    pub gmk_file: Option<String>,
+    pub bootstrap_max_retries: Option<u8>,
+    pub host_lbd: Option<bool>,
+    pub short_addr_allocation: Option<String>,
+    pub static_short_addrs: Option<Vec<String>>,
+    /// File the non-volatile data of the modem is kept in, see nv_data
+    pub nv_data_file: Option<String>,
+    /// pcap file or named pipe the sniffed frames are written to
+    pub pcap: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -246,6 +266,8 @@ pub struct G3 {
 pub struct Serial {
     pub name: String,
     pub speed: u32,
+    /// Capture file the USI frames are appended to, see usi_capture
+    pub record: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -254,6 +276,25 @@ pub struct Settings {
     pub g3: G3,
     pub serial: Serial,
     pub network: Network,
+    pub metrics: Option<Metrics>,
+    pub control: Option<Control>,
+}
+
+/// Prometheus endpoint, see stats
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Metrics {
+    /// Address of the HTTP endpoint, e.g. "127.0.0.1:9898"
+    pub listen: String,
+    /// Seconds between two reads of the modem counters
+    pub interval_secs: Option<u64>,
+}
+
+/// Unix socket of the operator commands, see control
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Control {
+    pub socket: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -274,7 +315,22 @@ impl Settings {
             .add_source(File::with_name(file_name))
             .add_source(Environment::with_prefix("NEG3"))
             .build()?;
-        s.try_deserialize()
+        let mut settings: Settings = s.try_deserialize()?;
+        //A GMK activated by a rekey replaces the one of the configuration
+        if let Some(ref path) = settings.g3.gmk_file {
+            match StoredGmk::load(path) {
+                Ok(Some(stored)) => {
+                    settings.g3.gmk = stored.gmk;
+                    settings.g3.active_key_index = Some(stored.key_index);
+                    if let Some(rekey_gmk) = stored.rekey_gmk {
+                        settings.g3.rekey_gmk = rekey_gmk;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to read the GMK from {} : {}, using the configured one", path, e),
+            }
+        }
+        Ok(settings)
     }
 }
 
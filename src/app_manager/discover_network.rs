@@ -8,6 +8,7 @@ impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for Dis
     fn on_enter(&mut self, cs: &flume::Sender<usi::Message>, context: &mut Context) -> Response<State> {
 
         log::info!("State : DiscoverNetwork - onEnter : context {:?}", context);
+        context.discovered.lock().unwrap().clear();
 
         let cmd = request::AdpDiscoveryRequest::new(context.settings.g3.discovery_timeout_secs);
         if let Err(e) = cs.send(usi::Message::UsiOut(cmd.into())) {
@@ -25,12 +26,16 @@ impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for Dis
                         if response.status != EAdpStatus::G3_SUCCESS {
                             Response::Transition(State::NetworkDiscoverFailed)
                         }
+                        else if context.settings.g3.host_lbd.unwrap_or(false) {
+                            Response::Transition(State::HostJoin)
+                        }
                         else {
                             Response::Transition(State::JoinNetwork)
                         }
                     }
                     adp::Message::AdpG3DiscoveryEvent(event) => {
                         context.pan_descriptors.push(event.pan_descriptor.clone());
+                        context.discovered.lock().unwrap().push(event.pan_descriptor.clone());
                         Response::Handled
                     }
                     _=>{
@@ -1,4 +1,4 @@
-use crate::{usi, app_config, request::{AdpMacGetRequest, AdpSetRequest}, adp::{EMacWrpPibAttribute, G3_SERIAL_MSG_MAC_GET_CONFIRM, self, TExtendedAddress, ipv6_prefix}};
+use crate::{usi, app_config, request::{AdpMacGetRequest, AdpSetRequest}, adp::{EMacWrpPibAttribute, G3_SERIAL_MSG_MAC_GET_CONFIRM, self, ipv6_prefix}, pib::PibValue};
 
 use super::{State, Stateful, Context, Response, Message};
 use num_enum::TryFromPrimitive;
@@ -41,10 +41,10 @@ impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for Get
                             if let Ok(attr) = EMacWrpPibAttribute::try_from(mac_get_response.attribute_id) {
                                 match attr {
                                     EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS => {
-                                       let mut v = mac_get_response.attribute_val.clone();
-                                       v.reverse();
-                                        context.extended_addr = 
-                                            TExtendedAddress::try_from(v.as_slice()).map_or(None, |v| Some(v));
+                                        context.extended_addr = match mac_get_response.value() {
+                                            Ok(PibValue::ExtendedAddress(ext_addr)) => Some(ext_addr),
+                                            _ => None,
+                                        };
                                        if let Some(ipv6_addr) = 
                                         app_config::ula_ipv6_addr_from_pan_id_extended_addr(&context.settings.network.ula_net_prefix,
                                             context.settings.g3.pan_id, &context.extended_addr.unwrap()) {
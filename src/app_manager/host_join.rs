@@ -0,0 +1,122 @@
+use crate::{usi, lbp, lbd::{Lbd, LbdState}, lbp_functions::TEapPskKey, adp::{EAdpStatus, self}};
+
+use super::{State, Stateful, Context, Response, Message};
+
+/// Heartbeats without any progress of the bootstrap before giving up on the PAN
+const HOST_JOIN_MAX_IDLE_HEARTBEATS: u8 = 3;
+
+/// Join of the modem with the EAP-PSK peer running on the host, see lbd::Lbd
+pub struct HostJoin {
+    lbd: Option<Lbd>,
+    pan_id: u16,
+    last_state: Option<LbdState>,
+    idle_heartbeats: u8,
+}
+
+impl HostJoin {
+    pub fn new() -> Self {
+        HostJoin {
+            lbd: None,
+            pan_id: 0,
+            last_state: None,
+            idle_heartbeats: 0,
+        }
+    }
+}
+
+impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for HostJoin {
+    fn on_enter(
+        &mut self,
+        cs: &flume::Sender<usi::Message>,
+        context: &mut Context,
+    ) -> Response<State> {
+        log::info!("State : HostJoin - onEnter : context {:?}", context);
+
+        let ext_addr = match context.extended_addr {
+            Some(ext_addr) => ext_addr,
+            None => {
+                log::error!("Trying to join network without extended address");
+                return Response::Transition(State::NetworkDiscoverFailed);
+            }
+        };
+        if let Some(ref pan_descriptor) = context.pan_descriptors.pop() {
+            let mut lbd = Lbd::new(ext_addr, TEapPskKey(context.settings.g3.psk), context.settings.g3.max_hops);
+            let out = lbd.start(pan_descriptor.lba_address);
+            if let Err(e) = cs.send(usi::Message::UsiOut(out)) {
+                log::warn!("Failed to send lbp joining {}", e);
+            }
+            self.pan_id = pan_descriptor.pan_id;
+            self.last_state = Some(lbd.state());
+            self.idle_heartbeats = 0;
+            self.lbd = Some(lbd);
+            Response::Handled
+        }
+        else{
+            log::error!("Trying to join network without pan descriptor");
+            Response::Transition(State::NetworkDiscoverFailed)
+        }
+    }
+
+    fn on_event(
+        &mut self,
+        cs: &flume::Sender<usi::Message>,
+        event: &Message,
+        context: &mut Context,
+    ) -> Response<State> {
+        let lbd = match self.lbd {
+            Some(ref mut lbd) => lbd,
+            None => return Response::Handled,
+        };
+        match event {
+            Message::Adp(adp::Message::AdpG3LbpEvent(lbp_event)) => {
+                if let Some(lbp_message) = lbp::adp_message_to_lbp_message(lbp_event) {
+                    let was_joined = lbd.is_joined();
+                    for out in lbd.process_msg(&lbp_message) {
+                        if let Err(e) = cs.send(usi::Message::UsiOut(out)) {
+                            log::warn!("Failed to send lbd message {}", e);
+                        }
+                    }
+                    match lbd.state() {
+                        LbdState::Joined if !was_joined => {
+                            //Same path as a join done by the modem, so that the network manager starts the TUN
+                            let response = adp::AdpG3NetworkJoinResponse {
+                                status: EAdpStatus::G3_SUCCESS,
+                                network_addr: lbd.short_addr().unwrap_or(0xFFFF),
+                                pan_id: self.pan_id,
+                            };
+                            if let Err(e) = context.net_tx.send(adp::Message::AdpG3NetworkJoinResponse(response)) {
+                                log::warn!("Failed to send join response to network manager {}", e);
+                            }
+                        }
+                        LbdState::Declined | LbdState::Kicked => {
+                            return Response::Transition(State::NetworkDiscoverFailed);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Message::HeartBeat(_) => {
+                //Once joined the state is kept to answer the rekeys and kicks of the coordinator
+                let state = lbd.state();
+                if !lbd.is_joined() {
+                    if self.last_state == Some(state) {
+                        self.idle_heartbeats += 1;
+                    } else {
+                        self.idle_heartbeats = 0;
+                    }
+                    if self.idle_heartbeats >= HOST_JOIN_MAX_IDLE_HEARTBEATS {
+                        log::warn!("Host join timed out in {:?}", state);
+                        return Response::Transition(State::NetworkDiscoverFailed);
+                    }
+                }
+                self.last_state = Some(state);
+            }
+            _ => {}
+        }
+        Response::Handled
+    }
+
+    fn on_exit(&mut self, context: &mut Context) {
+        self.lbd = None;
+    }
+}
@@ -4,7 +4,7 @@ use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::current;
 use std::time::SystemTime;
@@ -14,6 +14,7 @@ use flume::SendError;
 use serialport::new;
 
 use crate::adp;
+use crate::adp_client::AdpClient;
 
 use crate::adp::TAdpPanDescriptor;
 use crate::adp::TExtendedAddress;
@@ -35,6 +36,8 @@ use self::network_discover_failed::NetworkDiscoverFailed;
 use self::set_coord_short_addr::SetCoordShortAddr;
 use self::start_network::StartNetwork;
 use self::discover_network::DiscoverNetwork;
+use self::host_join::HostJoin;
+use self::sniff::Sniff;
 
 mod stack_initialize;
 mod ready;
@@ -47,6 +50,8 @@ mod set_coord_short_addr;
 mod join_network_failed;
 mod discover_network;
 mod network_discover_failed;
+mod host_join;
+mod sniff;
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub enum State {
@@ -61,6 +66,8 @@ pub enum State {
     JoinNetworkFailed,
     DiscoverNetwork,
     NetworkDiscoverFailed,
+    HostJoin,
+    Sniff,
 }
 #[derive(Debug)]
 pub enum Message<'a> {
@@ -187,26 +194,37 @@ pub struct Context {
     is_coordinator: bool,
     extended_addr: Option<TExtendedAddress>,
     settings: app_config::Settings,
-    pan_descriptors: Vec<TAdpPanDescriptor>
-   
+    pan_descriptors: Vec<TAdpPanDescriptor>,
+    /// All the PAN descriptors of the last discovery, pan_descriptors is emptied by the joins
+    discovered: Arc<Mutex<Vec<TAdpPanDescriptor>>>,
+    net_tx: flume::Sender<adp::Message>,
 }
 
 pub struct AppManager {
     usi_tx: flume::Sender<usi::Message>,
     net_tx: flume::Sender<adp::Message>,
+    adp_client: AdpClient,
+    discovered: Arc<Mutex<Vec<TAdpPanDescriptor>>>,
 }
 
 impl AppManager {
     pub fn new(
         usi_tx: flume::Sender<usi::Message>,
         net_tx: flume::Sender<adp::Message>,
-        
+        adp_client: AdpClient,
     ) -> Self {
         AppManager {
             usi_tx,
             net_tx,
+            adp_client,
+            discovered: Arc::new(Mutex::new(Vec::new())),
         }
     }
+
+    /// The PAN descriptors of the last network discovery of the modem mode
+    pub fn pan_descriptors(&self) -> Arc<Mutex<Vec<TAdpPanDescriptor>>> {
+        self.discovered.clone()
+    }
     
     fn init_states( state_machine: &mut StateMachine::<State, usi::Message, flume::Sender<usi::Message>, Context>) {
         state_machine.add_state(State::Idle, Box::new(Idle {}));
@@ -220,6 +238,8 @@ impl AppManager {
         state_machine.add_state(State::DiscoverNetwork, Box::new(DiscoverNetwork {}));
         state_machine.add_state(State::SetCoordShortAddr, Box::new(SetCoordShortAddr {}));
         state_machine.add_state(State::NetworkDiscoverFailed, Box::new(NetworkDiscoverFailed {}));
+        state_machine.add_state(State::HostJoin, Box::new(HostJoin::new()));
+        state_machine.add_state(State::Sniff, Box::new(Sniff {}));
     }
     pub fn start(self, settings: &app_config::Settings,  usi_receiver: flume::Receiver<usi::Message>, is_coordinator: bool) {
         log::info!("App Manager started ...");
@@ -230,7 +250,7 @@ impl AppManager {
                     State::Idle,
                     self.usi_tx.clone(),
                     Context { is_coordinator: is_coordinator, extended_addr: None, 
-                        settings: settings, pan_descriptors: Vec::new() }
+                        settings: settings, pan_descriptors: Vec::new(), discovered: self.discovered.clone(), net_tx: self.net_tx.clone() }
                 );
             // let mut lbp_manager = lbp_manager::LbpManager::new();
             Self::init_states(&mut state_machine);
@@ -242,7 +262,9 @@ impl AppManager {
                         log::info!("app_manager - {:?} received msg : {:?}", state_machine.current_state, event);
                         match event {
                             usi::Message::UsiIn(usi_msg) => {
-                                if let Some(adp_msg) = adp::usi_message_to_message(&usi_msg){
+                                //The confirms of the requests sent through the client go back to their sender only
+                                if let Some(adp_msg) = adp::usi_message_to_message(&usi_msg)
+                                    .and_then(|adp_msg| self.adp_client.dispatch(adp_msg)) {
                                     //TODO optimize, split event those needed by the state machine and those needed by network manager
                                     state_machine.process_event(&Message::Adp(&adp_msg));
                                     if let Err(e) = self.net_tx.send(adp_msg) {
@@ -2,22 +2,54 @@ use std::collections::VecDeque;
 
 use crate::{
     adp,
-    app_config::{self, G3ParamType},
+    app_config::{self, G3ParamType, Mode},
+    nv_data::NvDataFile,
     request::{AdpMacSetRequest, AdpSetRequest},
     usi,
 };
 
+use num_enum::TryFromPrimitive;
+
 use super::{Context, Message, Response, State, Stateful};
 
 pub struct SetParams {
     params: Option<VecDeque<app_config::G3Param>>,
 }
 
+fn is_sniffer(context: &Context) -> bool {
+    Mode::try_from_primitive(context.settings.g3.mode) == Ok(Mode::Sniffer)
+}
+
 impl SetParams {
     pub fn new() -> Self {
         SetParams { params: None }
     }
     fn init_params(&mut self, context: &Context) {
+        if is_sniffer(context) {
+            //The frames of all the PANs and devices, with their MAC header
+            let params = vec![
+                (
+                    G3ParamType::Mac,
+                    adp::EMacWrpPibAttribute::MAC_WRP_PIB_PROMISCUOUS_MODE.into(),
+                    0,
+                    vec![0x01],
+                ),
+                (
+                    G3ParamType::Mac,
+                    adp::EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_ENABLE_MAC_SNIFFER.into(),
+                    0,
+                    vec![0x01],
+                ),
+                (
+                    G3ParamType::Adp,
+                    adp::EAdpPibAttribute::ADP_IB_SNIFFER_MODE.into(),
+                    0,
+                    vec![0x01],
+                ),
+            ];
+            self.params = Some(params.into());
+            return;
+        }
         if context.is_coordinator {
             let params = vec![
                 (
@@ -29,10 +61,9 @@ impl SetParams {
                 (
                     G3ParamType::Mac,
                     adp::EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE.into(),
-                    0,
+                    context.settings.g3.active_key_index.unwrap_or(0) as u16,
                     context.settings.g3.gmk.to_vec(),
                 ),
-                //TODO rekey
                 (
                     G3ParamType::Adp,
                     adp::EAdpPibAttribute::ADP_IB_SECURITY_LEVEL.into(),
@@ -43,8 +74,8 @@ impl SetParams {
                     G3ParamType::Adp,
                     adp::EAdpPibAttribute::ADP_IB_ACTIVE_KEY_INDEX.into(),
                     0,
-                    vec![0x00],
-                ), //TODO parameterize
+                    vec![context.settings.g3.active_key_index.unwrap_or(0)],
+                ),
                 (
                     G3ParamType::Adp,
                     adp::EAdpPibAttribute::ADP_IB_MAX_JOIN_WAIT_TIME.into(),
@@ -156,6 +187,17 @@ impl SetParams {
             ];
             self.params = Some(params.into());
         }
+        if let (Some(params), Some(path)) = (&mut self.params, &context.settings.g3.nv_data_file) {
+            match NvDataFile::open(path) {
+                Ok(file) => {
+                    if let Some(data) = file.data() {
+                        log::info!("Restoring non-volatile data {:?}", data);
+                        params.extend(data.restore_params());
+                    }
+                }
+                Err(e) => log::error!("Failed to read non-volatile data {} : {}, the modem keeps its own", path, e),
+            }
+        }
     }
     fn set_param(&self, cs: &flume::Sender<usi::Message>, param: &app_config::G3Param) -> bool {
         let msg = if param.0 == G3ParamType::Mac {
@@ -203,6 +245,8 @@ impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for Set
         log::trace!("SetParams : {:?}", event);
         if self.send_next_param(cs) {
             Response::Handled
+        } else if is_sniffer(context) {
+            Response::Transition(State::Sniff)
         } else {
             Response::Transition(State::GetParams)
         }
@@ -0,0 +1,24 @@
+use crate::{usi, adp};
+
+use super::{Stateful, Response, State, Message, Context};
+
+/// Sniffer mode, the modem only listens once its sniffer is enabled by SetParams.
+/// The sniffed frames go to the pcap writer, see crate::sniffer.
+pub struct Sniff {}
+
+impl Stateful<State, usi::Message, flume::Sender<usi::Message>, Context> for Sniff {
+    fn on_enter(&mut self, cs: &flume::Sender<usi::Message>, context: &mut Context) -> Response<State> {
+        log::info!("State : Sniff - onEnter");
+        Response::Handled
+    }
+
+    fn on_event(&mut self, cs: &flume::Sender<usi::Message>, event: &Message, context: &mut Context) -> Response<State> {
+        match event {
+            Message::Adp(adp::Message::AdpG3MacSnifferEvent(_)) => {}
+            _ => log::trace!("Sniff : {:?}", event),
+        }
+        Response::Handled
+    }
+
+    fn on_exit(&mut self, context: &mut Context) {}
+}
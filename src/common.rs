@@ -1,3 +1,7 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
 
 pub const MNGP_PRIME: u8 = 0x00;
 pub const MNGP_PRIME_GETQRY: u8 = 0x00;
@@ -103,6 +107,31 @@ pub fn array_to_hex_string(bytes: Vec<u8>) -> String {
     let strs: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
     strs.join(" ")
 }
+/// Parses hex digits, ignoring ' ', ':' and '-' separators
+pub fn from_hex_string(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|c| !matches!(c, b' ' | b':' | b'-')).collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Replaces the file at path with content. It is written and synced to `<path>.tmp` then renamed over
+/// path, a crash leaves the old or the new content, never a truncated file.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    //The rename is only on disk once its directory is synced
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::File::open(dir)?.sync_all()
+}
 
 #[derive(Clone)]
 pub struct Parameter {
@@ -120,4 +149,21 @@ impl Parameter {
             value,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces() {
+        let path = std::env::temp_dir().join(format!("ne-g3-atomic-{}.json", std::process::id()));
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
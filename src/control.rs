@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::adp::{TAddress, TExtendedAddress};
+use crate::common;
+use crate::device_registry::DeviceRegistry;
+use crate::network_manager::{CommandReply, NetworkCommand};
+
+/// Time the network manager has to give the outcome of a command, it handles them between two events
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses one line of the control socket:
+///
+/// `kick <EUI-64 | short address in hex>` forces the device out of the network,
+/// `rekey <GMK in hex>` distributes the new GMK to the devices then activates it
+pub fn parse_command(line: &str) -> Result<NetworkCommand, String> {
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some("kick"), Some(addr), None) => parse_addr(addr).map(NetworkCommand::Kick),
+        (Some("kick"), _, _) => Err("usage: kick <EUI-64 | short address>".to_string()),
+        (Some("rekey"), Some(gmk), None) => match common::from_hex_string(gmk) {
+            Some(gmk) if gmk.len() == 16 => Ok(NetworkCommand::Rekey(gmk)),
+            _ => Err(format!("invalid GMK {}, 16 bytes in hex", gmk)),
+        },
+        (Some("rekey"), _, _) => Err("usage: rekey <GMK>".to_string()),
+        (Some(command), _, _) => Err(format!("unknown command {}", command)),
+        (None, _, _) => Err("empty command".to_string()),
+    }
+}
+
+fn parse_addr(s: &str) -> Result<TAddress, String> {
+    if s.contains(':') {
+        return s
+            .parse::<TExtendedAddress>()
+            .map(TAddress::Extended)
+            .map_err(|_| format!("invalid EUI-64 {}", s));
+    }
+    let hex = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(hex, 16)
+        .map(TAddress::Short)
+        .map_err(|_| format!("invalid short address {}", s))
+}
+
+/// Serves the commands of the operator on a Unix socket, one command per line answered by `ok` or
+/// `error: <reason>`, e.g. `echo "kick 0005" | socat - UNIX-CONNECT:/run/ne-g3.sock`.
+/// The commands are handed to the network manager through its command sender, the answer is its outcome.
+/// `devices` lists the devices of the registry, one per line before the `ok`.
+pub fn spawn(
+    path: &str,
+    command_tx: flume::Sender<(NetworkCommand, CommandReply)>,
+    registry: DeviceRegistry,
+) -> io::Result<thread::JoinHandle<()>> {
+    //The socket of a previous run is left behind on exit
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| serve(stream, &command_tx, &registry));
+            if let Err(e) = result {
+                log::warn!("Control connection failed : {}", e);
+            }
+        }
+    }))
+}
+
+/// `<short address> <EUI-64> <state> key_index=<index> join_time=<s> last_seen=<s>`, `-` when not set
+fn list_devices(registry: &DeviceRegistry) -> String {
+    let mut reply = String::new();
+    for device in registry.list() {
+        reply.push_str(&format!(
+            "0x{:04x} {} {:?} key_index={} join_time={} last_seen={}\n",
+            device.short_addr,
+            device.ext_addr,
+            device.state,
+            device.key_index.map_or("-".to_string(), |i| i.to_string()),
+            device.join_time.map_or("-".to_string(), |t| t.to_string()),
+            device.last_seen
+        ));
+    }
+    reply.push_str("ok");
+    reply
+}
+
+fn serve(
+    stream: UnixStream,
+    command_tx: &flume::Sender<(NetworkCommand, CommandReply)>,
+    registry: &DeviceRegistry,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.split_whitespace().eq(["devices"]) {
+            writeln!(writer, "{}", list_devices(registry))?;
+            continue;
+        }
+        let reply = match parse_command(&line) {
+            Ok(command) => {
+                log::info!(target: "audit", "control command \"{}\"", line.trim());
+                let (reply_tx, reply_rx) = flume::bounded(1);
+                match command_tx.send((command, reply_tx)).map(|_| reply_rx.recv_timeout(REPLY_TIMEOUT)) {
+                    Ok(Ok(Ok(()))) => "ok".to_string(),
+                    Ok(Ok(Err(e))) => format!("error: {}", e),
+                    Ok(Err(flume::RecvTimeoutError::Timeout)) => "error: no answer from the network manager".to_string(),
+                    Ok(Err(flume::RecvTimeoutError::Disconnected)) | Err(_) => "error: network manager stopped".to_string(),
+                }
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_registry::{BootstrapState, DeviceInfo};
+
+    #[test]
+    fn commands_over_socket() {
+        let path = std::env::temp_dir().join(format!("ne-g3-control-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (command_tx, command_rx) = flume::unbounded::<(NetworkCommand, CommandReply)>();
+        let registry = DeviceRegistry::new([0u8; 6]);
+        registry.update(DeviceInfo {
+            ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]),
+            short_addr: 0x0005,
+            state: BootstrapState::Joined,
+            key_index: Some(0),
+            join_time: Some(1_697_539_200),
+            last_seen: 1_697_539_260,
+        });
+        spawn(path, command_tx, registry).unwrap();
+        //The network manager knows 0005 and 00:80:E1:FF:FE:00:00:01 only
+        let network_manager = thread::spawn(move || {
+            let mut commands = Vec::new();
+            for (command, reply) in command_rx.iter().take(5) {
+                let known = match command {
+                    NetworkCommand::Kick(TAddress::Short(short_addr)) => short_addr == 0x0005,
+                    NetworkCommand::Kick(TAddress::Extended(ext_addr)) => ext_addr.0[7] == 0x01,
+                    _ => true,
+                };
+                reply.send(if known { Ok(()) } else { Err("unknown device".to_string()) }).unwrap();
+                commands.push(command);
+            }
+            commands
+        });
+
+        let stream = UnixStream::connect(path).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        writer
+            .write_all(b"kick 0x0005\nkick 00:80:E1:FF:FE:00:00:01\nkick 0006\nkick 00:80:E1:FF:FE:00:00:02\nkick\nleave 0005\nrekey 000102030405060708090A0B0C0D0E0F\nrekey 0001\ndevices\n")
+            .unwrap();
+        let replies: Vec<String> = BufReader::new(stream).lines().take(10).map(Result::unwrap).collect();
+        assert_eq!(
+            replies,
+            vec![
+                "ok",
+                "ok",
+                "error: unknown device",
+                "error: unknown device",
+                "error: usage: kick <EUI-64 | short address>",
+                "error: unknown command leave",
+                "ok",
+                "error: invalid GMK 0001, 16 bytes in hex",
+                "0x0005 00:80:E1:FF:FE:00:00:01 Joined key_index=0 join_time=1697539200 last_seen=1697539260",
+                "ok"
+            ]
+        );
+        fs::remove_file(path).unwrap();
+
+        let commands = network_manager.join().unwrap();
+        assert!(matches!(commands[0], NetworkCommand::Kick(TAddress::Short(0x0005))));
+        match commands[1] {
+            NetworkCommand::Kick(TAddress::Extended(ext_addr)) => {
+                assert_eq!(ext_addr, TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]))
+            }
+            ref other => panic!("unexpected command {:?}", other),
+        }
+        assert!(matches!(commands[2], NetworkCommand::Kick(TAddress::Short(0x0006))));
+        match commands[4] {
+            NetworkCommand::Rekey(ref gmk) => assert_eq!(*gmk, (0u8..16).collect::<Vec<u8>>()),
+            ref other => panic!("unexpected command {:?}", other),
+        }
+    }
+}
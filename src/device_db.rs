@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::adp::TExtendedAddress;
+use crate::common;
+
+/// One registered device, as stored in the device database file.
+/// Timestamps are seconds since the UNIX epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub ext_addr: TExtendedAddress,
+    pub short_addr: u16,
+    pub join_time: u64,
+    pub last_seen: u64,
+    pub key_index: u8,
+    pub state: String,
+}
+
+/// File backed registry of the devices that completed the bootstrap.
+/// The whole table is rewritten on every update, devices are few and updates happen only on joins.
+#[derive(Debug)]
+pub struct DeviceDb {
+    path: PathBuf,
+    records: HashMap<TExtendedAddress, DeviceRecord>,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl DeviceDb {
+    /// Opens the database, a missing file is an empty database
+    pub fn open(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let mut records = HashMap::new();
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                let list: Vec<DeviceRecord> = serde_json::from_str(&content)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                for record in list {
+                    records.insert(record.ext_addr, record);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::info!("Device database {:?} not found, starting empty", path);
+            }
+            Err(e) => return Err(e),
+        }
+        log::info!("Device database {:?} loaded {} devices", path, records.len());
+        Ok(DeviceDb { path, records })
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &DeviceRecord> {
+        self.records.values()
+    }
+
+    pub fn get(&self, ext_addr: &TExtendedAddress) -> Option<&DeviceRecord> {
+        self.records.get(ext_addr)
+    }
+
+    pub fn update(&mut self, record: DeviceRecord) -> io::Result<()> {
+        self.records.insert(record.ext_addr, record);
+        self.save()
+    }
+
+    pub fn remove(&mut self, ext_addr: &TExtendedAddress) -> io::Result<()> {
+        if self.records.remove(ext_addr).is_some() {
+            return self.save();
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut list: Vec<&DeviceRecord> = self.records.values().collect();
+        list.sort_by_key(|r| r.short_addr);
+        let content = serde_json::to_string_pretty(&list)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        common::write_atomic(&self.path, content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ne-g3-{}-{}.json", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn record(last: u8, short_addr: u16) -> DeviceRecord {
+        DeviceRecord {
+            ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, last]),
+            short_addr,
+            join_time: 1_697_539_200,
+            last_seen: 1_697_539_260,
+            key_index: 1,
+            state: "BS_STATE_ACCEPTED".to_string(),
+        }
+    }
+
+    fn sorted(db: &DeviceDb) -> Vec<DeviceRecord> {
+        let mut records: Vec<DeviceRecord> = db.records().cloned().collect();
+        records.sort_by_key(|r| r.short_addr);
+        records
+    }
+
+    #[test]
+    fn save_and_reopen() {
+        let path = temp_path("device-db");
+        let _ = fs::remove_file(&path);
+        let mut db = DeviceDb::open(&path).unwrap();
+        assert_eq!(db.records().count(), 0);
+        db.update(record(2, 0x0002)).unwrap();
+        db.update(record(1, 0x0001)).unwrap();
+        db.update(record(3, 0x0003)).unwrap();
+        db.remove(&record(3, 0x0003).ext_addr).unwrap();
+
+        let reopened = DeviceDb::open(&path).unwrap();
+        assert_eq!(sorted(&reopened), vec![record(1, 0x0001), record(2, 0x0002)]);
+        assert_eq!(reopened.get(&record(2, 0).ext_addr), Some(&record(2, 0x0002)));
+        //Written through a temporary file renamed over the database
+        let mut tmp = PathBuf::from(&path).into_os_string();
+        tmp.push(".tmp");
+        assert!(!PathBuf::from(tmp).exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_file() {
+        let path = temp_path("device-db-corrupted");
+        fs::write(&path, "[{\"ext_addr\": ").unwrap();
+        let e = DeviceDb::open(&path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, "{\"devices\": 3}").unwrap();
+        assert!(DeviceDb::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::sync::{Arc, RwLock};
+
+use crate::adp::TExtendedAddress;
+
+/// Bootstrap state of a device, as seen by the coordinator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapState {
+    WaitingJoining,
+    SentEapMsg1,
+    WaitingEapMsg2,
+    SentEapMsg3,
+    WaitingEapMsg4,
+    /// LBP_ACCEPTED sent, waiting for its confirm
+    Accepted,
+    Declined,
+    /// Completed the bootstrap, the device is part of the network
+    Joined,
+}
+
+/// Snapshot of a device known to the coordinator.
+/// Timestamps are seconds since the UNIX epoch.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub ext_addr: TExtendedAddress,
+    pub short_addr: u16,
+    pub state: BootstrapState,
+    /// Index of the GMK the device holds, None until it completes the bootstrap
+    pub key_index: Option<u8>,
+    /// First completed bootstrap
+    pub join_time: Option<u64>,
+    /// Last bootstrap message from or to the device
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Default)]
+struct Devices {
+    devices: HashMap<TExtendedAddress, DeviceInfo>,
+    short_addresses: HashMap<u16, TExtendedAddress>,
+}
+
+/// Devices of the coordinator, shared with the other threads.
+///
+/// Written by the LbpManager on every bootstrap step, the clones all see the same devices.
+#[derive(Debug, Clone)]
+pub struct DeviceRegistry {
+    devices: Arc<RwLock<Devices>>,
+    ula_host_prefix: [u8; 6],
+}
+
+impl DeviceRegistry {
+    pub fn new(ula_host_prefix: [u8; 6]) -> Self {
+        DeviceRegistry {
+            devices: Arc::new(RwLock::new(Devices::default())),
+            ula_host_prefix,
+        }
+    }
+
+    pub fn list(&self) -> Vec<DeviceInfo> {
+        let devices = self.devices.read().unwrap();
+        let mut list: Vec<DeviceInfo> = devices.devices.values().cloned().collect();
+        list.sort_by_key(|d| d.short_addr);
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.read().unwrap().devices.len()
+    }
+
+    pub fn get_by_ext_addr(&self, ext_addr: &TExtendedAddress) -> Option<DeviceInfo> {
+        self.devices.read().unwrap().devices.get(ext_addr).cloned()
+    }
+
+    pub fn get_by_short_addr(&self, short_addr: u16) -> Option<DeviceInfo> {
+        let devices = self.devices.read().unwrap();
+        let ext_addr = devices.short_addresses.get(&short_addr)?;
+        devices.devices.get(ext_addr).cloned()
+    }
+
+    /// The device of a ULA (ula_host_prefix followed by the short address), of a link local
+    /// address built from the PAN id and short address, or of an address with the EUI-64 as interface id
+    pub fn get_by_ipv6(&self, ipv6_addr: &Ipv6Addr) -> Option<DeviceInfo> {
+        let o = ipv6_addr.octets();
+        if o[8..14] == self.ula_host_prefix || o[10..14] == [0x00, 0xff, 0xfe, 0x00] {
+            return self.get_by_short_addr(u16::from_be_bytes([o[14], o[15]]));
+        }
+        TExtendedAddress::try_from(*ipv6_addr)
+            .ok()
+            .and_then(|ext_addr| self.get_by_ext_addr(&ext_addr))
+    }
+
+    /// Called by the LbpManager when the slot of the device changes
+    pub fn update(&self, info: DeviceInfo) {
+        let mut devices = self.devices.write().unwrap();
+        if let Some(old) = devices.devices.get(&info.ext_addr) {
+            let old_short_addr = old.short_addr;
+            devices.short_addresses.remove(&old_short_addr);
+        }
+        devices.short_addresses.insert(info.short_addr, info.ext_addr);
+        devices.devices.insert(info.ext_addr, info);
+    }
+
+    pub fn remove(&self, ext_addr: &TExtendedAddress) {
+        let mut devices = self.devices.write().unwrap();
+        if let Some(info) = devices.devices.remove(ext_addr) {
+            devices.short_addresses.remove(&info.short_addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXT_ADDR: TExtendedAddress = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]);
+    const ULA_HOST_PREFIX: [u8; 6] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+
+    fn info(short_addr: u16) -> DeviceInfo {
+        DeviceInfo {
+            ext_addr: EXT_ADDR,
+            short_addr,
+            state: BootstrapState::Joined,
+            key_index: Some(0),
+            join_time: Some(1_697_539_200),
+            last_seen: 1_697_539_260,
+        }
+    }
+
+    #[test]
+    fn lookups() {
+        let registry = DeviceRegistry::new(ULA_HOST_PREFIX);
+        registry.update(info(0x0005));
+        assert_eq!(registry.get_by_ext_addr(&EXT_ADDR).unwrap().short_addr, 0x0005);
+        assert_eq!(registry.get_by_short_addr(0x0005).unwrap().ext_addr, EXT_ADDR);
+        let ula: Ipv6Addr = "fd00:0:2:781d:1122:3344:5566:5".parse().unwrap();
+        let link_local: Ipv6Addr = "fe80::781d:ff:fe00:5".parse().unwrap();
+        let eui64: Ipv6Addr = "fe80::80:e1ff:fe00:1".parse().unwrap();
+        for addr in [ula, link_local, eui64] {
+            assert_eq!(registry.get_by_ipv6(&addr).map(|d| d.ext_addr), Some(EXT_ADDR), "{}", addr);
+        }
+        assert!(registry.get_by_ipv6(&"fd00:0:2:781d:1122:3344:5566:6".parse().unwrap()).is_none());
+
+        //A new short address replaces the old one
+        registry.update(info(0x0007));
+        assert!(registry.get_by_short_addr(0x0005).is_none());
+        assert_eq!(registry.get_by_short_addr(0x0007).unwrap().ext_addr, EXT_ADDR);
+        assert_eq!(registry.len(), 1);
+
+        registry.remove(&EXT_ADDR);
+        assert!(registry.get_by_ext_addr(&EXT_ADDR).is_none());
+        assert!(registry.get_by_short_addr(0x0007).is_none());
+        assert!(registry.list().is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common;
+
+/// The GMK of the network as left by the last rekey, kept in the `gmk_file` (JSON).
+///
+/// It replaces `gmk` and `active_key_index` of the configuration once a rekey activated a new key.
+/// The key being distributed is stored as soon as the rekey starts, so a rekey interrupted by a
+/// restart can be resumed with the same key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredGmk {
+    pub gmk: Vec<u8>,
+    pub key_index: u8,
+    pub rekey_gmk: Option<Vec<u8>>,
+}
+
+impl StoredGmk {
+    /// A missing file is no stored GMK
+    pub fn load(path: &str) -> io::Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        //A crash while saving never loses the key in use
+        common::write_atomic(Path::new(path), content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("ne-g3-gmk-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        assert_eq!(StoredGmk::load(path).unwrap(), None);
+
+        let stored = StoredGmk { gmk: vec![0x11; 16], key_index: 1, rekey_gmk: Some(vec![0x22; 16]) };
+        stored.save(path).unwrap();
+        assert_eq!(StoredGmk::load(path).unwrap(), Some(stored));
+
+        fs::write(path, "{\"gmk\": [1, 2]").unwrap();
+        assert_eq!(StoredGmk::load(path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use crate::adp::EAdpPibAttribute;
+use crate::adp::EMacWrpPibAttribute;
+use crate::adp::TAddress;
+use crate::adp::TExtendedAddress;
+use crate::lbp;
+use crate::lbp::{CONF_PARAM_GMK, CONF_PARAM_GMK_ACTIVATION, CONF_PARAM_SHORT_ADDR};
+use crate::lbp_functions::*;
+use crate::request;
+use crate::usi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LbdState {
+    Idle,
+    SentJoining,
+    SentEapMsg2,
+    SentEapMsg4,
+    Joined,
+    Declined,
+    Kicked,
+}
+
+/// Host side bootstrapping device (LBD), the EAP-PSK peer of the coordinator.
+///
+/// Used in modem mode instead of the join of the firmware, the identity (EUI-64) and the PSK
+/// are the ones of the host. The short address and the GMK received in the p-channel are written
+/// to the modem once the coordinator accepts the device. A rekey from the coordinator is answered
+/// the same way once joined.
+#[derive(Debug)]
+pub struct Lbd {
+    state: LbdState,
+    ext_addr: TExtendedAddress,
+    psk: TEapPskKey,
+    lba_addr: u16,
+    max_hops: u8,
+    nsdu_handle: u8,
+    psk_context: TEapPskContext,
+    rand_s: TEapPskRand,
+    short_addr: Option<u16>,
+    /// Set once accepted by the coordinator, stays set during a rekey
+    joined: bool,
+    /// Index and GMK received in the last message 3, written to the modem on ACCEPTED
+    gmk: Option<(u8, Vec<u8>)>,
+    activate_key_index: Option<u8>,
+    rand_source: Box<dyn EapPskRandSource>,
+}
+
+impl Lbd {
+    pub fn new(ext_addr: TExtendedAddress, psk: TEapPskKey, max_hops: u8) -> Self {
+        Lbd {
+            state: LbdState::Idle,
+            ext_addr,
+            psk,
+            lba_addr: 0,
+            max_hops,
+            nsdu_handle: 0,
+            psk_context: TEapPskContext::new(),
+            rand_s: TEapPskRand::new(),
+            short_addr: None,
+            joined: false,
+            gmk: None,
+            activate_key_index: None,
+            rand_source: Box::new(ThreadRandSource),
+        }
+    }
+
+    pub fn state(&self) -> LbdState {
+        self.state
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.joined
+    }
+
+    /// Short address given by the coordinator, once joined
+    pub fn short_addr(&self) -> Option<u16> {
+        self.short_addr.filter(|_| self.joined)
+    }
+
+    fn lbp_request(&mut self, data: Vec<u8>) -> usi::OutMessage {
+        self.nsdu_handle = self.nsdu_handle.wrapping_add(1);
+        //Until it is joined, the device reaches the coordinator through its LBA
+        let dst_addr = match self.joined {
+            true => TAddress::Short(0),
+            false => TAddress::Short(self.lba_addr),
+        };
+        request::AdpLbpRequest::new(
+            dst_addr,
+            data,
+            self.nsdu_handle,
+            self.max_hops,
+            true,
+            0,
+            self.joined,
+        )
+        .into()
+    }
+
+    /// Sends the first LBP_JOINING, without bootstrapping data, through the LBA
+    pub fn start(&mut self, lba_addr: u16) -> usi::OutMessage {
+        log::info!("[LBD] Joining through LBA 0x{:04x}", lba_addr);
+        self.lba_addr = lba_addr;
+        self.state = LbdState::Idle;
+        self.short_addr = None;
+        self.joined = false;
+        self.gmk = None;
+        self.activate_key_index = None;
+        let joining = lbp::JoiningMessage {
+            ext_addr: self.ext_addr,
+            bootstrapping_data: Vec::new(),
+        };
+        let out = self.lbp_request(joining.into());
+        self.state = LbdState::SentJoining;
+        out
+    }
+
+    fn joining(&mut self, bootstrapping_data: Vec<u8>) -> usi::OutMessage {
+        let joining = lbp::JoiningMessage {
+            ext_addr: self.ext_addr,
+            bootstrapping_data,
+        };
+        self.lbp_request(joining.into())
+    }
+
+    /// Answers EAP-PSK message 1 with message 2
+    fn process_eap_t0(&mut self, identifier: u8, eap_data: &Vec<u8>) -> Option<usi::OutMessage> {
+        let mut rand_s = TEapPskRand::new();
+        let mut id_s = TEapPskNetworkAccessIdentifier::new();
+        if !eap_psk_decode_message1(eap_data, &mut rand_s, &mut id_s) {
+            log::warn!("[LBD] Failed to decode EAP-PSK message 1");
+            return None;
+        }
+        let rand_p = self.rand_source.next_rand();
+        eap_psk_initialize(&self.psk, &mut self.psk_context);
+        eap_psk_initialize_peer(&id_s, &rand_s, &rand_p, &mut self.psk_context);
+
+        let id_p: TEapPskNetworkAccessIdentifier = self.ext_addr.0.to_vec().into();
+        let mut message2 = Vec::with_capacity(64 + self.ext_addr.0.len());
+        eap_psk_encode_message2(
+            &self.psk_context,
+            identifier,
+            &rand_s,
+            &rand_p,
+            &id_s,
+            &id_p,
+            &mut message2,
+        );
+        self.rand_s = rand_s;
+        log::info!("[LBD] Sending EAP-PSK message 2");
+        let out = self.joining(message2);
+        self.state = LbdState::SentEapMsg2;
+        Some(out)
+    }
+
+    /// Takes the configuration parameters out of message 3 and answers with message 4
+    fn process_eap_t2(&mut self, identifier: u8, bootstrapping_data: &[u8], eap_data: &Vec<u8>) -> Option<usi::OutMessage> {
+        let mut rand_s = TEapPskRand::new();
+        let mut nonce = 0u32;
+        let mut pchannel_result = 0u8;
+        let mut pchannel_data = Vec::new();
+        if bootstrapping_data.len() < 22
+            || !eap_psk_decode_message3(
+                eap_data,
+                &self.psk_context,
+                bootstrapping_data[0..22].to_vec(),
+                &mut rand_s,
+                &mut nonce,
+                &mut pchannel_result,
+                &mut pchannel_data,
+            )
+        {
+            log::warn!("[LBD] Failed to decode EAP-PSK message 3");
+            return None;
+        }
+        if rand_s.0 != self.rand_s.0 {
+            log::warn!("[LBD] Bad RandS received");
+            return None;
+        }
+        if pchannel_result != PCHANNEL_RESULT_DONE_SUCCESS {
+            log::warn!("[LBD] P-channel result {}", pchannel_result);
+            return None;
+        }
+        //The p-channel data is a type byte followed by the parameters
+        self.process_conf_params(pchannel_data.get(1..).unwrap_or_default());
+
+        let mut message4 = Vec::new();
+        if !eap_psk_encode_message4(
+            &self.psk_context,
+            identifier,
+            &self.rand_s,
+            nonce.wrapping_add(1),
+            PCHANNEL_RESULT_DONE_SUCCESS,
+            Vec::new(),
+            &mut message4,
+        ) {
+            log::warn!("[LBD] Failed to encode EAP-PSK message 4");
+            return None;
+        }
+        log::info!("[LBD] Sending EAP-PSK message 4");
+        let out = self.joining(message4);
+        self.state = LbdState::SentEapMsg4;
+        Some(out)
+    }
+
+    /// Reads the (attribute, length, value) configuration parameters
+    fn process_conf_params(&mut self, data: &[u8]) {
+        let mut pos = 0usize;
+        while let (Some(&attr), Some(&len)) = (data.get(pos), data.get(pos + 1)) {
+            let value = match data.get((pos + 2)..(pos + 2 + len as usize)) {
+                Some(value) => value,
+                None => {
+                    log::warn!("[LBD] Truncated configuration parameter 0x{:02x}", attr);
+                    return;
+                }
+            };
+            match attr {
+                CONF_PARAM_SHORT_ADDR if len == 2 => {
+                    self.short_addr = Some(u16::from_be_bytes([value[0], value[1]]));
+                }
+                CONF_PARAM_GMK if len == 17 => {
+                    self.gmk = Some((value[0], value[1..].to_vec()));
+                }
+                CONF_PARAM_GMK_ACTIVATION if len == 1 => {
+                    self.activate_key_index = Some(value[0]);
+                }
+                _ => {
+                    log::info!("[LBD] Ignoring configuration parameter 0x{:02x}", attr);
+                }
+            }
+            pos += 2 + len as usize;
+        }
+    }
+
+    /// Writes what the coordinator gave in the p-channel to the modem
+    fn pib_requests(&mut self) -> Vec<usi::OutMessage> {
+        let mut out = Vec::new();
+        if let Some(short_addr) = self.short_addr {
+            let v = short_addr.to_be_bytes().to_vec();
+            out.push(request::AdpMacSetRequest::new(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, &v).into());
+        }
+        if let Some((key_index, gmk)) = self.gmk.take() {
+            out.push(request::AdpMacSetRequest::new(EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE, key_index as u16, &gmk).into());
+        }
+        if let Some(key_index) = self.activate_key_index.take() {
+            let v = vec![key_index];
+            out.push(request::AdpSetRequest::new(EAdpPibAttribute::ADP_IB_ACTIVE_KEY_INDEX, 0, &v).into());
+        }
+        out
+    }
+
+    pub fn process_msg(&mut self, lbp_message: &lbp::LbpMessage) -> Vec<usi::OutMessage> {
+        let mut out = Vec::new();
+        match lbp_message {
+            lbp::LbpMessage::Challenge(msg) if msg.ext_addr == self.ext_addr => {
+                let data = msg.bootstrapping_data.clone().unwrap_or_default();
+                let mut code = 0u8;
+                let mut identifier = 0u8;
+                let mut tsubfield = 0u8;
+                let mut eap_data = Vec::new();
+                if !eap_psk_decode_message(&data, &mut code, &mut identifier, &mut tsubfield, &mut eap_data)
+                    || code != EAP_REQUEST
+                {
+                    log::warn!("[LBD] Invalid challenge {:?}", msg);
+                    return out;
+                }
+                //Message 1 is also accepted once joined, that is how the coordinator starts a rekey
+                let result = match (tsubfield, self.state) {
+                    (EAP_PSK_T0, LbdState::SentJoining | LbdState::SentEapMsg2 | LbdState::Joined) => {
+                        self.process_eap_t0(identifier, &eap_data)
+                    }
+                    (EAP_PSK_T2, LbdState::SentEapMsg2 | LbdState::SentEapMsg4) => {
+                        self.process_eap_t2(identifier, &data, &eap_data)
+                    }
+                    _ => {
+                        log::warn!("[LBD] Unexpected challenge T{} in {:?}", tsubfield >> 6, self.state);
+                        None
+                    }
+                };
+                out.extend(result);
+            }
+            lbp::LbpMessage::Accepted(msg) if msg.ext_addr == self.ext_addr => {
+                let data = msg.bootstrapping_data.clone().unwrap_or_default();
+                //Once joined, the parameters of an ACCEPTED activate the GMK distributed by a rekey
+                if self.state == LbdState::Joined && data.first() == Some(&CONF_PARAM_GMK_ACTIVATION) {
+                    self.process_conf_params(&data);
+                    log::info!("[LBD] Activating key index {:?}", self.activate_key_index);
+                    out.extend(self.pib_requests());
+                    return out;
+                }
+                if self.state != LbdState::SentEapMsg4 || data.first() != Some(&EAP_SUCCESS) {
+                    log::warn!("[LBD] Unexpected accepted in {:?}", self.state);
+                    return out;
+                }
+                self.state = LbdState::Joined;
+                self.joined = true;
+                log::info!("[LBD] Joined, short address {:?}", self.short_addr);
+                out.extend(self.pib_requests());
+            }
+            lbp::LbpMessage::Decline(msg) if msg.ext_addr == self.ext_addr => {
+                log::warn!("[LBD] Join declined by the coordinator");
+                self.state = LbdState::Declined;
+            }
+            //Anyone can send an unsecured frame, only the coordinator holds the GMK
+            lbp::LbpMessage::KickToLbd(msg) if msg.ext_addr == self.ext_addr && (!msg.security_enabled || msg.src_addr != 0x0000) => {
+                log::warn!("[LBD] Dropping kick from 0x{:04x}, security {}", msg.src_addr, msg.security_enabled);
+            }
+            lbp::LbpMessage::KickToLbd(msg) if msg.ext_addr == self.ext_addr => {
+                log::warn!("[LBD] Kicked by the coordinator");
+                self.state = LbdState::Kicked;
+                self.joined = false;
+                self.short_addr = None;
+            }
+            _ => {
+                log::warn!("[LBD] Dropping LBP message {:?}", lbp_message);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp::AdpG3LbpEvent;
+    use crate::app_config;
+    use crate::lbp_manager::LbpManager;
+
+    const EXT_ADDR: TExtendedAddress = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]);
+
+    /// The LBP message of a request, as the ADP indication of the receiver gives it
+    fn received(data: &[u8], src_addr: u16) -> lbp::LbpMessage {
+        //Command, handle, max hops, discover route, QoS, security, address length, NSDU length, address, NSDU
+        let event = AdpG3LbpEvent {
+            src_addr,
+            nsdu: data[9 + data[6] as usize..].to_vec(),
+            link_quality_indicator: 0,
+            security_enabled: data[5] != 0,
+        };
+        lbp::adp_message_to_lbp_message(&event).unwrap()
+    }
+
+    #[test]
+    fn join_and_forged_kick() {
+        let settings = app_config::Settings::new("ne-g3.toml").unwrap();
+        let mut config = settings.g3.clone();
+        config.device_db = None;
+        config.gmk_file = None;
+        let mut lbp_manager = LbpManager::new(&config);
+        lbp_manager.set_short_addr(0);
+        let mut lbd = Lbd::new(EXT_ADDR, TEapPskKey(config.psk), config.max_hops);
+        lbd.rand_source = Box::new(FixedRandSource::new(vec![[0x5A; 16]]));
+
+        let mut out = vec![lbd.start(0x0000)];
+        while !lbd.is_joined() {
+            assert_eq!(out.len(), 1, "lbd in {:?}", lbd.state());
+            let reply: usi::OutMessage = lbp_manager.process_msg(&received(out[0].data(), 0xFFFF)).unwrap().into();
+            out = lbd.process_msg(&received(reply.data(), 0x0000));
+        }
+        assert_eq!(lbd.state(), LbdState::Joined);
+        let short_addr = lbd.short_addr().unwrap();
+        assert_ne!(short_addr, 0x0000);
+        //Short address, GMK and its index are written to the modem
+        assert_eq!(out.len(), 3);
+
+        //Another device of the PAN sends an unsecured kick, or a secured one from its own address
+        for (src_addr, security_enabled) in [(0x0000, false), (0x0002, true)] {
+            let event = AdpG3LbpEvent {
+                src_addr,
+                nsdu: lbp::KickToLbdMessage::new(EXT_ADDR).into(),
+                link_quality_indicator: 0,
+                security_enabled,
+            };
+            assert!(lbd.process_msg(&lbp::adp_message_to_lbp_message(&event).unwrap()).is_empty());
+            assert_eq!(lbd.state(), LbdState::Joined);
+            assert_eq!(lbd.short_addr(), Some(short_addr));
+        }
+
+        //The kick of the coordinator is secured
+        let kick = lbp_manager.kick(&TAddress::Short(short_addr)).unwrap();
+        let kick: usi::OutMessage = kick.into();
+        lbd.process_msg(&received(kick.data(), 0x0000));
+        assert_eq!(lbd.state(), LbdState::Kicked);
+        assert!(!lbd.is_joined());
+    }
+}
@@ -45,11 +45,27 @@ pub enum LbpMessage {
     KickToLbd(KickToLbdMessage),
 }
 
+/// Attributes of the configuration parameters carried in the EAP-PSK p-channel
+pub const CONF_PARAM_SHORT_ADDR: u8 = 0x1D;
+pub const CONF_PARAM_GMK: u8 = 0x27;
+pub const CONF_PARAM_GMK_ACTIVATION: u8 = 0x2B;
+pub const CONF_PARAM_GMK_REMOVAL: u8 = 0x2F;
+pub const CONF_PARAM_RESULT: u8 = 0x31;
+
 #[derive(Debug)]
 pub struct JoiningMessage {
     pub ext_addr: TExtendedAddress,
     pub bootstrapping_data: Vec<u8>,
 }
+impl Into<Vec<u8>> for JoiningMessage {
+    fn into(self) -> Vec<u8> {
+        let cmd: u8 = LbpMessageType::LBP_JOINING.into();
+        let mut v = vec![(cmd << 4), 0x0 /*transaction id is reserved */];
+        v.extend_from_slice(&self.ext_addr.0);
+        v.extend_from_slice(&self.bootstrapping_data);
+        return v;
+    }
+}
 
 // pdata[u16PDataLen++] = CONF_PARAM_SHORT_ADDR;
 // 			pdata[u16PDataLen++] = 2;
@@ -148,7 +164,16 @@ impl Into<Vec<u8>> for DeclineMessage {
 
 #[derive(Debug)]
 pub struct KickFromLbdMessage {
-    ext_addr: TExtendedAddress,
+    pub ext_addr: TExtendedAddress,
+    /// Short address of the sender, from the ADP indication
+    pub src_addr: u16,
+    /// The frame was secured with the GMK, from the ADP indication
+    pub security_enabled: bool,
+}
+impl KickFromLbdMessage {
+    pub fn new(ext_addr: TExtendedAddress) -> Self {
+        KickFromLbdMessage { ext_addr, src_addr: 0, security_enabled: false }
+    }
 }
 impl Into<Vec<u8>> for KickFromLbdMessage {
     fn into(self) -> Vec<u8> {
@@ -161,7 +186,16 @@ impl Into<Vec<u8>> for KickFromLbdMessage {
 
 #[derive(Debug)]
 pub struct KickToLbdMessage {
-    ext_addr: TExtendedAddress,
+    pub ext_addr: TExtendedAddress,
+    /// Short address of the sender, from the ADP indication
+    pub src_addr: u16,
+    /// The frame was secured with the GMK, from the ADP indication
+    pub security_enabled: bool,
+}
+impl KickToLbdMessage {
+    pub fn new(ext_addr: TExtendedAddress) -> Self {
+        KickToLbdMessage { ext_addr, src_addr: 0, security_enabled: false }
+    }
 }
 impl Into<Vec<u8>> for KickToLbdMessage {
     fn into(self) -> Vec<u8> {
@@ -207,10 +241,10 @@ pub fn adp_message_to_lbp_message(msg: &adp::AdpG3LbpEvent) -> Option<LbpMessage
                 return Some(LbpMessage::Decline(DeclineMessage {ext_addr: *ext_addr}));
             },
             LbpMessageType::LBP_KICK_FROM_LBD => {
-                return Some(LbpMessage::KickFromLbd(KickFromLbdMessage {ext_addr: *ext_addr}));
+                return Some(LbpMessage::KickFromLbd(KickFromLbdMessage {ext_addr: *ext_addr, src_addr: msg.src_addr, security_enabled: msg.security_enabled}));
             },
             LbpMessageType::LBP_KICK_TO_LBD => {
-                return Some(LbpMessage::KickToLbd(KickToLbdMessage {ext_addr: *ext_addr}));
+                return Some(LbpMessage::KickToLbd(KickToLbdMessage {ext_addr: *ext_addr, src_addr: msg.src_addr, security_enabled: msg.security_enabled}));
             },
         }
     }
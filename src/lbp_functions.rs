@@ -114,6 +114,40 @@ impl TEapPskRand {
         TEapPskRand(arr)
     }
 }
+/// Source of the RandS / RandP values of the bootstrap.
+/// Replaced by fixed values to reproduce an exchange byte for byte, e.g. against the C coordinator.
+pub trait EapPskRandSource: Debug {
+    fn next_rand(&mut self) -> TEapPskRand;
+}
+
+/// Values from the thread RNG, the default
+#[derive(Debug, Default)]
+pub struct ThreadRandSource;
+impl EapPskRandSource for ThreadRandSource {
+    fn next_rand(&mut self) -> TEapPskRand {
+        TEapPskRand::new_random()
+    }
+}
+
+/// Gives the values in order, then starts over
+#[derive(Debug)]
+pub struct FixedRandSource {
+    values: Vec<[u8; 16]>,
+    next: usize,
+}
+impl FixedRandSource {
+    pub fn new(values: Vec<[u8; 16]>) -> Self {
+        FixedRandSource { values, next: 0 }
+    }
+}
+impl EapPskRandSource for FixedRandSource {
+    fn next_rand(&mut self) -> TEapPskRand {
+        let v = self.values[self.next % self.values.len()];
+        self.next += 1;
+        TEapPskRand(v)
+    }
+}
+
 impl From<Vec<u8>> for TEapPskRand {
     fn from(v: Vec<u8>) -> Self {
         let u: [u8; 16] = v.try_into().unwrap(); //TODO, remove the unwrap
@@ -155,6 +189,11 @@ impl TEapPskNetworkAccessIdentifier {
         TEapPskNetworkAccessIdentifier(Vec::new())
     }
 }
+impl From<Vec<u8>> for TEapPskNetworkAccessIdentifier {
+    fn from(v: Vec<u8>) -> Self {
+        TEapPskNetworkAccessIdentifier(v)
+    }
+}
 
 /**********************************************************************************************************************/
 /** The EAP_PSK_Context type keeps information needed for EAP-PSK calls
@@ -228,6 +267,19 @@ pub fn eap_psk_initialize_tek(p_rand_p: &TEapPskRand, p_psk_context: &mut TEapPs
     }
 }
 
+/// Peer side counterpart of eap_psk_initialize_tek, also keeps IdS and RandP which are needed to check MacS of message 3
+pub fn eap_psk_initialize_peer(
+    p_id_s: &TEapPskNetworkAccessIdentifier,
+    p_rand_s: &TEapPskRand,
+    p_rand_p: &TEapPskRand,
+    p_psk_context: &mut TEapPskContext,
+) -> bool {
+    p_psk_context.m_IdS = TEapPskNetworkAccessIdentifier(p_id_s.0.clone());
+    p_psk_context.m_RandS = TEapPskRand(p_rand_s.0);
+    p_psk_context.m_RandP = TEapPskRand(p_rand_p.0);
+    eap_psk_initialize_tek(p_rand_p, p_psk_context)
+}
+
 pub fn eap_psk_decode_message(
     p_message: &Vec<u8>,
     pu8_code: &mut u8,
@@ -352,8 +404,9 @@ pub fn eap_psk_decode_message3(
         if au8_mac_s == mac_s {
             let key = eax::aead::generic_array::GenericArray::from_slice(&p_psk_context.m_Tek.0);
             let p_nonce = &p_message[32..36];
-            // let pTag = pMessage[36..52];
-            let p_protected_data = &p_message[36..];
+            // The tag comes before the encrypted data on the wire, eax expects it after
+            let mut p_protected_data = p_message[52..].to_vec();
+            p_protected_data.extend_from_slice(&p_message[36..52]);
             let mut au8_nonce: [u8; 16] = [0; 16];
             au8_nonce[12] = p_nonce[0];
             au8_nonce[13] = p_nonce[1];
@@ -374,13 +427,14 @@ pub fn eap_psk_decode_message3(
             if let Ok(data) = cipher.decrypt(
                 GenericArray::from_slice(&au8_nonce),
                 Payload {
-                    msg: p_protected_data,
+                    msg: &p_protected_data,
                     aad: &header,
                 },
             ) {
                 *p_u8_pchannel_result = (data[0] & 0xC0) >> 6;
                 *p_pchannel_data = data[1..].to_vec();
-                *p_u32_nonce = u32::from_be_bytes([p_nonce[3], p_nonce[2], p_nonce[1], p_nonce[0]]);
+                *p_u32_nonce = u32::from_be_bytes([p_nonce[0], p_nonce[1], p_nonce[2], p_nonce[3]]);
+                b_ret = true;
             }
         }
     }
@@ -437,6 +491,11 @@ pub fn eap_psk_encode_message4(
         &p_psk_context.m_Tek.0,
     ));
 
+    // the length is part of the authenticated header, set it before encrypting
+    let len = header.len() + 4 + protected_data.len() + 16 /*TAG */;
+    header[2] = ((len >> 8) & 0x00FF) as u8;
+    header[3] = (len & 0x00FF) as u8;
+
     header[0] >>= 2;
 
     if let Ok(data) = cipher.encrypt(
@@ -446,14 +505,14 @@ pub fn eap_psk_encode_message4(
             aad: &header,
         },
     ) {
+        // same layout as message 3: nonce, tag, then the encrypted data
+        let (payload, tag) = data.split_at(protected_data.len());
         header[0] <<= 2;
-        let len = header.len() + 4 + data.len();
-        header[2] = ((len >> 8) & 0x00FF) as u8;
-        header[3] = (len & 0x00FF) as u8;
         p_protected_data.clear();
         p_protected_data.extend_from_slice(&header);
         p_protected_data.extend_from_slice(&au8_nonce[12..]);
-        p_protected_data.extend_from_slice(&data);
+        p_protected_data.extend_from_slice(tag);
+        p_protected_data.extend_from_slice(payload);
         return true;
     }
     return false;
@@ -637,8 +696,8 @@ pub fn EAP_PSK_Decode_Message4(
 ) -> bool {
     let mut bRet = false;
 
-    // TODO: review size (ARIB)
-    if (pMessage.len() >= 41) {
+    // RandS, nonce, tag and at least the p-channel result
+    if (pMessage.len() >= 37) {
         *p_rand_s = pMessage[0..16].to_vec().into();
 
         // decrypt P-CHANNEL
@@ -676,7 +735,7 @@ pub fn EAP_PSK_Decode_Message4(
         ) {
             *pu8PChannelResult = (data[0] & 0xC0) >> 6;
             *pPChannelData = data[1..].to_vec();
-            *pu32Nonce = u32::from_be_bytes([p_nonce[3], p_nonce[2], p_nonce[1], p_nonce[0]]);
+            *pu32Nonce = u32::from_be_bytes([p_nonce[0], p_nonce[1], p_nonce[2], p_nonce[3]]);
             bRet = true;
         }
     }
@@ -709,3 +768,110 @@ pub fn EAP_PSK_Decode_Message4(
     pMemoryBuffer.push(pPChannelData[2]);
   }
 
+
+/// Reference vectors computed with an independent AES / CMAC / EAX implementation (RFC 4764, G3-PLC Annex E),
+/// for the PSK of ne-g3.toml, ID_S = 81:72:63:54:45:36:27:18, ID_P = 00:80:E1:FF:FE:00:00:01,
+/// RandS = 00..0F and RandP = 10..1F.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::from_hex_string;
+
+    const PSK: [u8; 16] = [0xab, 0x10, 0x34, 0x11, 0x45, 0x11, 0x1b, 0xc3, 0xc1, 0x2d, 0xe8, 0xff, 0x11, 0x14, 0x22, 0x04];
+    const ID_S: [u8; 8] = [0x81, 0x72, 0x63, 0x54, 0x45, 0x36, 0x27, 0x18];
+    const ID_P: [u8; 8] = [0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01];
+    const AK: &str = "62c726977737d61587df88e48c42a681";
+    const KDK: &str = "97405263d14cd0990cc652b97745f2db";
+    const TEK: &str = "f78806e24f321085333686e2b48b9752";
+    const PCHANNEL_DATA: &str = "021d020001271100af4d6dccf14de7c1c4235e6fef6c151f2b0100";
+    const MESSAGE3: &str = "040100562f80000102030405060708090a0b0c0d0e0fab046374a7d68c208b89fca38c39bce8\
+        000000003d969d0edf8dfb637cb2bd88a8b3cafaca532cdaed49396ac18cac1ca429b0b338a605556d186a127928e972";
+    const MESSAGE4: &str = "0801002b2fc0000102030405060708090a0b0c0d0e0f00000001b0babb49ef9760ab7f6f3f75f8804ec534";
+
+    fn hex(s: &str) -> Vec<u8> {
+        from_hex_string(s).unwrap()
+    }
+
+    fn rand_s() -> TEapPskRand {
+        TEapPskRand(core::array::from_fn(|i| i as u8))
+    }
+
+    fn rand_p() -> TEapPskRand {
+        TEapPskRand(core::array::from_fn(|i| 0x10 + i as u8))
+    }
+
+    fn context() -> TEapPskContext {
+        let mut context = TEapPskContext::new();
+        assert!(eap_psk_initialize(&TEapPskKey(PSK), &mut context));
+        assert!(eap_psk_initialize_tek(&rand_p(), &mut context));
+        context
+    }
+
+    fn peer_context() -> TEapPskContext {
+        let mut context = TEapPskContext::new();
+        assert!(eap_psk_initialize(&TEapPskKey(PSK), &mut context));
+        assert!(eap_psk_initialize_peer(&ID_S.to_vec().into(), &rand_s(), &rand_p(), &mut context));
+        context
+    }
+
+    #[test]
+    fn initialize_derives_ak_kdk_and_tek() {
+        let context = context();
+        assert_eq!(context.m_Ak.0.to_vec(), hex(AK));
+        assert_eq!(context.m_Kdk.0.to_vec(), hex(KDK));
+        assert_eq!(context.m_Tek.0.to_vec(), hex(TEK));
+    }
+
+    #[test]
+    fn message2_mac_p() {
+        let mut message2 = Vec::with_capacity(64);
+        eap_psk_encode_message2(&peer_context(), 0, &rand_s(), &rand_p(), &ID_S.to_vec().into(), &ID_P.to_vec().into(), &mut message2);
+        assert_eq!(
+            message2,
+            hex("0800003e2f40000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\
+                708d05e4cb459ea4c68f00e5588ef1fe0080e1fffe000001")
+        );
+
+        let (mut r_s, mut r_p) = (TEapPskRand::new(), TEapPskRand::new());
+        let id_s = TEapPskNetworkAccessIdentifierS(ID_S.to_vec());
+        assert!(eap_psk_decode_message2(&message2[6..], &context(), &id_s, &mut r_s, &mut r_p));
+        assert_eq!(r_p.0, rand_p().0);
+    }
+
+    #[test]
+    fn message3_pchannel() {
+        let id_s = TEapPskNetworkAccessIdentifierS(ID_S.to_vec());
+        let message3 = EAP_PSK_Encode_Message3(&context(), 1, &rand_s(), &rand_p(), &id_s, 0,
+            PCHANNEL_RESULT_DONE_SUCCESS, &hex(PCHANNEL_DATA)).unwrap();
+        assert_eq!(message3, hex(MESSAGE3));
+
+        let (mut r_s, mut nonce, mut result, mut data) = (TEapPskRand::new(), 1u32, 0u8, Vec::new());
+        assert!(eap_psk_decode_message3(&message3[6..].to_vec(), &peer_context(), message3[0..22].to_vec(),
+            &mut r_s, &mut nonce, &mut result, &mut data));
+        assert_eq!((r_s.0, nonce, result), (rand_s().0, 0, PCHANNEL_RESULT_DONE_SUCCESS));
+        assert_eq!(data, hex(PCHANNEL_DATA));
+    }
+
+    #[test]
+    fn message3_tampered_is_rejected() {
+        let mut message3 = hex(MESSAGE3);
+        let last = message3.len() - 1;
+        message3[last] ^= 0x01;
+        let (mut r_s, mut nonce, mut result, mut data) = (TEapPskRand::new(), 0u32, 0u8, Vec::new());
+        assert!(!eap_psk_decode_message3(&message3[6..].to_vec(), &peer_context(), message3[0..22].to_vec(),
+            &mut r_s, &mut nonce, &mut result, &mut data));
+    }
+
+    #[test]
+    fn message4_pchannel() {
+        let mut message4 = Vec::new();
+        assert!(eap_psk_encode_message4(&peer_context(), 1, &rand_s(), 1, PCHANNEL_RESULT_DONE_SUCCESS, Vec::new(), &mut message4));
+        assert_eq!(message4, hex(MESSAGE4));
+
+        let (mut r_s, mut nonce, mut result, mut data) = (TEapPskRand::new(), 0u32, 0u8, Vec::new());
+        assert!(EAP_PSK_Decode_Message4(&message4[6..].to_vec(), &context(), &message4,
+            &mut r_s, &mut nonce, &mut result, &mut data));
+        assert_eq!((r_s.0, nonce, result), (rand_s().0, 1, PCHANNEL_RESULT_DONE_SUCCESS));
+        assert!(data.is_empty());
+    }
+}
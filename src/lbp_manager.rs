@@ -8,27 +8,39 @@ use std::net::Ipv6Addr;
 use std::rc::Rc;
 use std::time::Instant;
 
+use crate::admission::{Admission, AdmissionPolicy, ListPolicy};
 use crate::adp::AdpG3LbpReponse;
+use crate::adp::EAdpPibAttribute;
 use crate::adp::EAdpStatus;
+use crate::adp::EMacWrpPibAttribute;
+use crate::adp::TAddress;
 use crate::adp::TAdpBand;
 use crate::adp::TExtendedAddress;
 use crate::app_config;
+use crate::device_db;
+use crate::device_db::DeviceDb;
+use crate::device_registry::{BootstrapState, DeviceInfo, DeviceRegistry};
+use crate::gmk_store::StoredGmk;
 use crate::lbp;
 use crate::lbp::JoiningMessage;
+use crate::lbp::{CONF_PARAM_GMK, CONF_PARAM_GMK_ACTIVATION, CONF_PARAM_SHORT_ADDR};
 use crate::lbp_functions::*;
+use crate::psk_store::PskStore;
+use crate::rekey::Rekey;
+use crate::short_addr;
+use crate::short_addr::ShortAddrAllocator;
 use crate::request;
+use crate::usi;
 
 use num_enum::IntoPrimitive;
 use num_enum::FromPrimitive;
 use num_enum::TryFromPrimitive;
 
 const UC_MESSAGE_TIMEOUT_MS: u128 = 40_000;
-
-const CONF_PARAM_SHORT_ADDR: u8 = 0x1D;
-const CONF_PARAM_GMK: u8 = 0x27;
-const CONF_PARAM_GMK_ACTIVATION: u8 = 0x2B;
-const CONF_PARAM_GMK_REMOVAL: u8 = 0x2F;
-const CONF_PARAM_RESULT: u8 = 0x31;
+//Resends of a bootstrap message before the slot is given up, when not configured
+const BOOTSTRAP_MSG_MAX_RETRIES: u8 = 1;
+//The rekey of a device is two round trips
+const REKEY_DEVICE_TIMEOUT_MS: u128 = 2 * UC_MESSAGE_TIMEOUT_MS;
 
 #[derive(PartialEq, Eq, Debug)]
 enum DeviceState {
@@ -43,6 +55,27 @@ enum DeviceState {
 
 const MAX_DEVICES:u16 = 500u16;
 
+/// Bootstrap counters, since the start of the coordinator
+#[derive(Debug, Default, Clone)]
+pub struct LbpStats {
+    /// Bootstrap messages sent again after UC_MESSAGE_TIMEOUT_MS without answer
+    pub retransmissions: u32,
+    /// Slots reset to BS_STATE_WAITING_JOINNING after the last attempt
+    pub slot_timeouts: u32,
+}
+
+/// Device lifecycle notifications, sent to the listeners added with `LbpManager::add_listener`
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    Joined { ext_addr: TExtendedAddress, short_addr: u16 },
+    /// The device sent LBP_KICK_FROM_LBD
+    Left { ext_addr: TExtendedAddress, short_addr: u16 },
+    /// The coordinator sent LBP_KICK_TO_LBD
+    Kicked { ext_addr: TExtendedAddress, short_addr: u16 },
+    /// The new GMK is active under key_index, the failed devices did not take it
+    RekeyCompleted { key_index: u8, failed: Vec<TExtendedAddress> },
+}
+
 #[derive(Debug)]
 pub struct DeviceSlot {
     state: DeviceState,
@@ -54,9 +87,16 @@ pub struct DeviceSlot {
     uc_tx_attemps: u8,
     m_rand_s: TEapPskRand,
     uc_pending_confirms: u8,
+    /// Timeouts spent waiting for the confirm of the last message, see update_devices
+    uc_confirm_waits: u8,
     uc_pending_tx_handler: u8,
     m_psk_context: TEapPskContext,
     data: Option<Vec<u8>>,
+    /// Index of the GMK the device holds, None until it completes the bootstrap
+    key_index: Option<u8>,
+    /// Seconds since the UNIX epoch, see DeviceInfo
+    join_time: Option<u64>,
+    last_seen: u64,
 }
 impl DeviceSlot {
     pub fn new(ext_addr: TExtendedAddress, short_address: u16) -> Self {
@@ -70,9 +110,13 @@ impl DeviceSlot {
             uc_tx_attemps: 0,
             m_rand_s: TEapPskRand::new(),
             uc_pending_confirms: 0,
+            uc_confirm_waits: 0,
             uc_pending_tx_handler: 0,
             m_psk_context: TEapPskContext::new(),
             data: None,
+            key_index: None,
+            join_time: None,
+            last_seen: 0,
         }
     }
     pub fn reset (&mut self, ext_addr: TExtendedAddress, short_addr: u16) {
@@ -85,11 +129,32 @@ impl DeviceSlot {
         self.uc_tx_attemps = 0;
         self.m_rand_s = TEapPskRand::new();
         self.uc_pending_confirms = 0;
+        self.uc_confirm_waits = 0;
         self.uc_pending_tx_handler = 0;
         self.m_psk_context = TEapPskContext::new();
         self.data = None;
 
     }
+    fn info(&self) -> DeviceInfo {
+        let state = match self.state {
+            DeviceState::BS_STATE_WAITING_JOINNING if self.key_index.is_some() => BootstrapState::Joined,
+            DeviceState::BS_STATE_WAITING_JOINNING => BootstrapState::WaitingJoining,
+            DeviceState::BS_STATE_SENT_EAP_MSG_1 => BootstrapState::SentEapMsg1,
+            DeviceState::BS_STATE_WAITING_EAP_MSG_2 => BootstrapState::WaitingEapMsg2,
+            DeviceState::BS_STATE_SENT_EAP_MSG_3 => BootstrapState::SentEapMsg3,
+            DeviceState::BS_STATE_WAITING_EAP_MSG_4 => BootstrapState::WaitingEapMsg4,
+            DeviceState::BS_STATE_SENT_EAP_MSG_ACCEPTED => BootstrapState::Accepted,
+            DeviceState::BS_STATE_SENT_EAP_MSG_DECLINED => BootstrapState::Declined,
+        };
+        DeviceInfo {
+            ext_addr: self.m_lbd_address,
+            short_addr: self.us_assigned_short_address,
+            state,
+            key_index: self.key_index,
+            join_time: self.join_time,
+            last_seen: self.last_seen,
+        }
+    }
 }
 
 type DeviceSlotRef = Rc<RefCell<DeviceSlot>>;
@@ -99,21 +164,72 @@ struct DeviceManager {
     devices: HashMap<TExtendedAddress, DeviceSlotRef>,
     short_addresses: HashMap<u16, DeviceSlotRef>,
     // ip_addresses: HashMap<IpAddr, DeviceSlotRef>,
-    initial_short_address: u16,
-    current_short_address: u16
+    allocator: Box<dyn ShortAddrAllocator>,
+    db: Option<DeviceDb>,
+    registry: Option<DeviceRegistry>
 }
 impl DeviceManager {
-    fn new() -> Self {
-        DeviceManager {
+    fn new(db: Option<DeviceDb>, allocator: Box<dyn ShortAddrAllocator>) -> Self {
+        let mut device_manager = DeviceManager {
             devices: HashMap::new(),
             short_addresses: HashMap::new(),
             // ip_addresses: HashMap::new(),
-            initial_short_address: 0,
-            current_short_address: 0
+            allocator,
+            db: None,
+            registry: None
+        };
+        //Registered devices keep their short address, they only have to go through the bootstrap again
+        if let Some(ref db) = db {
+            for record in db.records() {
+                if !device_manager.allocator.reserve(&record.ext_addr, record.short_addr) {
+                    log::warn!("[BS] Device {} cannot keep short address 0x{:04x}, it gets a new one on its next join",
+                        record.ext_addr, record.short_addr);
+                    continue;
+                }
+                let mut slot = DeviceSlot::new(record.ext_addr, record.short_addr);
+                slot.key_index = Some(record.key_index);
+                slot.join_time = Some(record.join_time);
+                slot.last_seen = record.last_seen;
+                let d = Rc::new(RefCell::new(slot));
+                device_manager.devices.insert(record.ext_addr, d.clone());
+                device_manager.short_addresses.insert(record.short_addr, d);
+            }
         }
+        device_manager.db = db;
+        device_manager
     }
     fn set_initial_short_address (&mut self, initial_short_address: u16) {
-        self.initial_short_address = initial_short_address;
+        self.allocator.set_coord_short_addr(initial_short_address);
+    }
+    /// Publishes the known devices to the registry, and every change from now on
+    fn set_registry(&mut self, registry: DeviceRegistry) {
+        for device in self.devices.values() {
+            registry.update(device.borrow().info());
+        }
+        self.registry = Some(registry);
+    }
+    fn publish(&self, device: &DeviceSlot) {
+        if let Some(ref registry) = self.registry {
+            registry.update(device.info());
+        }
+    }
+    /// A bootstrap message was received from the device
+    fn seen(&self, ext_addr: &TExtendedAddress) {
+        if let Some(device) = self.devices.get(ext_addr) {
+            let mut device = device.deref().borrow_mut();
+            device.last_seen = device_db::now_secs();
+            self.publish(&device);
+        }
+    }
+    /// Replaces the allocator, the addresses of the known devices are reserved in the new one
+    fn set_allocator(&mut self, mut allocator: Box<dyn ShortAddrAllocator>) {
+        for (ext_addr, device) in self.devices.iter() {
+            let short_addr = device.borrow().us_assigned_short_address;
+            if !allocator.reserve(ext_addr, short_addr) {
+                log::warn!("[BS] Short address 0x{:04x} of {} is not reserved by the new allocator", short_addr, ext_addr);
+            }
+        }
+        self.allocator = allocator;
     }
     
    
@@ -126,33 +242,72 @@ impl DeviceManager {
     //         IpAddr::V6(_) => None,
     //     }
     // }
-    // fn get_device_by_short_addr(&self, short_addr: u16) -> Option<&DeviceSlotRef> {
-    //     self.short_addresses.get(&short_addr)
-    // }
+    fn get_device_by_short_addr(&self, short_addr: u16) -> Option<&DeviceSlotRef> {
+        self.short_addresses.get(&short_addr)
+    }
     fn get_device_by_addr(&self, addr: &TExtendedAddress) -> Option<&DeviceSlotRef> {
         self.devices.get(addr)
     }
     // fn get_device_by_ip(&self, ip: &IpAddr) -> Option<&DeviceSlotRef> {
     //     self.ip_addresses.get(&ip)
     // }
-    fn next_short_address(&self) -> u16 {
-        let short_addr = MAX_DEVICES * self.initial_short_address + (self.current_short_address) + 1;        
-        short_addr
-    }
-    fn add_or_get_by_addr(&mut self, addr: &TExtendedAddress) -> &DeviceSlotRef {
-        let short_addr = self.next_short_address();
-        self.devices
-            .entry(*addr)
-            .or_insert_with(|| {
-                self.current_short_address = short_addr;
-                let d = Rc::new(RefCell::new(DeviceSlot::new (*addr, short_addr)));
-                self.short_addresses.insert(short_addr, d.clone());
-                return d;
-            })
+    /// The slot of the device, a new one is created with a short address from the allocator.
+    /// None when the allocator has no address left for the device.
+    fn add_or_get_by_addr(&mut self, addr: &TExtendedAddress) -> Option<&DeviceSlotRef> {
+        if !self.devices.contains_key(addr) {
+            let short_addr = self.allocator.allocate(addr)?;
+            if let Some(other) = self.short_addresses.get(&short_addr) {
+                log::error!("[BS] Short address 0x{:04x} for {} is used by {}", short_addr, addr, other.borrow().m_lbd_address);
+                self.allocator.release(short_addr);
+                return None;
+            }
+            let d = Rc::new(RefCell::new(DeviceSlot::new (*addr, short_addr)));
+            self.short_addresses.insert(short_addr, d.clone());
+            self.devices.insert(*addr, d);
+        }
+        self.devices.get(addr)
     }
     fn get_devices(&self) -> &HashMap<TExtendedAddress, DeviceSlotRef> {
         &self.devices
     }
+    /// Number of known devices, not counting ext_addr
+    fn count_other_devices(&self, ext_addr: &TExtendedAddress) -> usize {
+        self.devices.len() - self.devices.contains_key(ext_addr) as usize
+    }
+    /// Frees the slot and the short address of the device, and removes it from the device database
+    fn remove(&mut self, ext_addr: &TExtendedAddress) -> Option<DeviceSlotRef> {
+        let device = self.devices.remove(ext_addr)?;
+        let short_addr = device.borrow().us_assigned_short_address;
+        self.short_addresses.remove(&short_addr);
+        self.allocator.release(short_addr);
+        if let Some(ref registry) = self.registry {
+            registry.remove(ext_addr);
+        }
+        if let Some(ref mut db) = self.db {
+            if let Err(e) = db.remove(ext_addr) {
+                log::error!("[BS] Failed to remove device {} from the device database : {}", ext_addr, e);
+            }
+        }
+        Some(device)
+    }
+    /// Stores the device in the device database, called when the device is accepted
+    fn register(&mut self, device: &DeviceSlot, key_index: u8) {
+        if let Some(ref mut db) = self.db {
+            let now = device_db::now_secs();
+            let join_time = db.get(&device.m_lbd_address).map_or(now, |r| r.join_time);
+            let record = device_db::DeviceRecord {
+                ext_addr: device.m_lbd_address,
+                short_addr: device.us_assigned_short_address,
+                join_time,
+                last_seen: now,
+                key_index,
+                state: format!("{:?}", device.state),
+            };
+            if let Err(e) = db.update(record) {
+                log::error!("[BS] Failed to store device {} in the device database : {}", device.m_lbd_address, e);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -169,8 +324,15 @@ pub struct LbpManager {
     device_manager: DeviceManager,
     gmk: Vec<u8>,
     rekey_gmk: Vec<u8>,
-    psk: TEapPskKey,
-    max_hops: u8
+    gmk_file: Option<String>,
+    psk_store: PskStore,
+    admission_policies: Vec<Box<dyn AdmissionPolicy>>,
+    listeners: Vec<flume::Sender<DeviceEvent>>,
+    rekey: Option<Rekey>,
+    max_retries: u8,
+    stats: LbpStats,
+    max_hops: u8,
+    rand_source: Box<dyn EapPskRandSource>
 }
 
 impl LbpManager {
@@ -182,26 +344,107 @@ impl LbpManager {
             id_s = TEapPskNetworkAccessIdentifierS(g3_config.ids_arib.clone());
         }
 
+        let db = g3_config.device_db.as_ref().and_then(|path| {
+            match DeviceDb::open(path) {
+                Ok(db) => Some(db),
+                Err(e) => {
+                    log::error!("[BS] Failed to open device database {} : {}, devices will not be persisted", path, e);
+                    None
+                }
+            }
+        });
+
+        let fallback = if g3_config.psk_fallback.unwrap_or(true) {
+            Some(TEapPskKey(g3_config.psk))
+        } else {
+            None
+        };
+        let psk_store = match g3_config.psk_file {
+            //Without the device keys the network wide key must not be used either, every join is declined
+            Some(ref path) => PskStore::load(path, fallback).unwrap_or_else(|e| {
+                log::error!("[BS] Failed to load device keys from {} : {}, all joins are refused", path, e);
+                PskStore::new(None)
+            }),
+            None => PskStore::new(fallback),
+        };
+
         LbpManager {
             u8_eap_identifier: 0,
             ext_addr: [0u8; 8],
             pending: 0,
-            current_key_index: 0,
+            current_key_index: g3_config.active_key_index.unwrap_or(0),
             uc_nsdu_handle: 0,
             g_id_s: id_s,
             // devices: HashMap::new(),
             start_time: Instant::now(),
             g_u32_nonce: 0,
-            device_manager: DeviceManager::new(),
+            device_manager: DeviceManager::new(db, short_addr::from_config(g3_config, MAX_DEVICES)),
             gmk: g3_config.gmk.clone(),
             rekey_gmk: g3_config.rekey_gmk.clone(),
-            psk: TEapPskKey(g3_config.psk),
-            max_hops: g3_config.max_hops
+            gmk_file: g3_config.gmk_file.clone(),
+            psk_store,
+            admission_policies: vec![Box::new(ListPolicy::from_config(g3_config, MAX_DEVICES as usize))],
+            listeners: Vec::new(),
+            rekey: None,
+            max_retries: g3_config.bootstrap_max_retries.unwrap_or(BOOTSTRAP_MSG_MAX_RETRIES),
+            stats: LbpStats::default(),
+            max_hops: g3_config.max_hops,
+            rand_source: Box::new(ThreadRandSource)
         }
     }
     pub fn set_short_addr (&mut self, short_addr: u16) {
         self.device_manager.set_initial_short_address(short_addr);
     }
+    /// Shares the devices with the other threads
+    pub fn set_registry(&mut self, registry: DeviceRegistry) {
+        self.device_manager.set_registry(registry);
+    }
+    /// Replaces the source of the RandS values, the thread RNG by default
+    pub fn set_rand_source(&mut self, rand_source: Box<dyn EapPskRandSource>) {
+        self.rand_source = rand_source;
+    }
+    /// Replaces the short address allocator set by the configuration
+    pub fn set_short_addr_allocator(&mut self, allocator: Box<dyn ShortAddrAllocator>) {
+        self.device_manager.set_allocator(allocator);
+    }
+
+    pub fn add_listener(&mut self, listener: flume::Sender<DeviceEvent>) {
+        self.listeners.push(listener);
+    }
+
+    fn notify(&self, event: DeviceEvent) {
+        for listener in &self.listeners {
+            if let Err(e) = listener.send(event.clone()) {
+                log::warn!("[BS] Failed to send device event : {}", e);
+            }
+        }
+    }
+
+    fn admit(&self, ext_addr: &TExtendedAddress) -> Admission {
+        if self.psk_store.key_for(ext_addr).is_none() {
+            return Admission::Decline("no pre-shared key".to_string());
+        }
+        let device_count = self.device_manager.count_other_devices(ext_addr);
+        for policy in &self.admission_policies {
+            if let Admission::Decline(reason) = policy.admit(ext_addr, device_count) {
+                return Admission::Decline(reason);
+            }
+        }
+        Admission::Accept
+    }
+
+    fn decline(&mut self, ext_addr: &TExtendedAddress, reason: &str) -> Vec<u8> {
+        log::warn!("[BS] Declining device {} : {}", ext_addr, reason);
+        log::info!(target: "audit", "join declined ext_addr={} reason=\"{}\"", ext_addr, reason);
+        //A known device keeps its slot, the decline confirm brings it back to BS_STATE_WAITING_JOINNING
+        if let Some(device) = self.device_manager.get_device_by_addr(ext_addr) {
+            let mut device = device.deref().borrow_mut();
+            device.state = DeviceState::BS_STATE_SENT_EAP_MSG_DECLINED;
+            device.data = None;
+            self.device_manager.publish(&device);
+        }
+        lbp::DeclineMessage::new(*ext_addr).into()
+    }
 
     fn process_joining_eap_t1(gmk: &Vec<u8>, rekey_gmk: &Vec<u8>,
         p_eap_data: &[u8],
@@ -275,8 +518,8 @@ impl LbpManager {
                 &p_data,
             );
 
-            *p_u8_eap_identifier += 1;
-            *p_u32_nonce += 1;
+            *p_u8_eap_identifier = p_u8_eap_identifier.wrapping_add(1);
+            *p_u32_nonce = p_u32_nonce.wrapping_add(1);
             let mut v: Option<Vec<u8>> = None;
             if let Some(data) = &p_device.data {
                 v = Some(data.to_vec());
@@ -323,7 +566,7 @@ impl LbpManager {
                 return None;
             }
 
-            *p_u8_eap_identifier += 1;
+            *p_u8_eap_identifier = p_u8_eap_identifier.wrapping_add(1);
 
             /* Encode now the LBP message */
             return Some(
@@ -339,31 +582,50 @@ impl LbpManager {
         }
     }
 
-    fn get_next_short_addr(current: &mut u16) -> u16 {
-        *current += 1;
-        return *current;
-    }
     fn process_joining0(&mut self, msg: &JoiningMessage) -> Option<Vec<u8>> {
         log::info!("[BS] Process Joining 0.");
-        let device = self
-            .device_manager
-            .add_or_get_by_addr(&msg.ext_addr)
-            .deref();
+        if msg.bootstrapping_data.len() == 0 {
+            if let Admission::Decline(reason) = self.admit(&msg.ext_addr) {
+                return Some(self.decline(&msg.ext_addr, &reason));
+            }
+        }
+        //Only an admitted device gets a slot and a short address, the later messages need its slot
+        let device = if msg.bootstrapping_data.len() == 0 {
+            match self.device_manager.add_or_get_by_addr(&msg.ext_addr) {
+                Some(device) => device.clone(),
+                None => return Some(self.decline(&msg.ext_addr, "no short address left")),
+            }
+        } else {
+            match self.device_manager.get_device_by_addr(&msg.ext_addr) {
+                Some(device) => device.clone(),
+                None => {
+                    log::warn!("[BS] No bootstrap in progress for {}, dropping joining message", msg.ext_addr);
+                    return None;
+                }
+            }
+        };
+        let device = device.deref();
         // .entry(msg.ext_addr)
         // .or_insert(DeviceSlot::new(msg.ext_addr, LbpManager::get_next_short_addr(&mut self.initialShortAddr)));
 
         if msg.bootstrapping_data.len() == 0 {
             let mut device = device.borrow_mut();
+            //The device restarted its bootstrap in the middle of a rekey, it is rekeyed again after the join
+            if let Some(ref mut rekey) = self.rekey {
+                if rekey.is_in_progress(&msg.ext_addr) {
+                    rekey.reset(&msg.ext_addr);
+                }
+            }
             if device.state != DeviceState::BS_STATE_WAITING_JOINNING {
                 let o_short_addr = device.us_assigned_short_address;
                 let o_ext_addr = device.m_lbd_address;
                 device.reset(o_ext_addr, o_short_addr);
             }
             //First join message
-            if (device.state == DeviceState::BS_STATE_WAITING_JOINNING) {
-                eap_psk_initialize(&self.psk, &mut device.m_psk_context);
-                device.m_rand_s = TEapPskRand::new_random(); //TODO for testing we need deterministic to compare between the C coordinator and Rust coordinator
-                                                            // device.m_randS = config::RAND_S_DEFAULT.to_vec().into();
+            if let (DeviceState::BS_STATE_WAITING_JOINNING, Some(psk)) =
+                (&device.state, self.psk_store.key_for(&msg.ext_addr)) {
+                eap_psk_initialize(psk, &mut device.m_psk_context);
+                device.m_rand_s = self.rand_source.next_rand();
 
                 device.data = Some(eap_psk_encode_message1(
                     self.u8_eap_identifier,
@@ -372,7 +634,7 @@ impl LbpManager {
                 ));
                 log::info!("Process joining message, out_message {:?}", device.data);
                 device.state = DeviceState::BS_STATE_SENT_EAP_MSG_1;
-                self.u8_eap_identifier += 1;
+                self.u8_eap_identifier = self.u8_eap_identifier.wrapping_add(1);
                 return Some(
                     lbp::ChallengeMessage {
                         ext_addr: msg.ext_addr,
@@ -395,6 +657,7 @@ impl LbpManager {
             ) {
                 if pu8_code == EAP_RESPONSE {
                     let mut device = device.borrow_mut();
+                    let rekeying = self.rekey.as_ref().is_some_and(|r| r.is_in_progress(&msg.ext_addr));
                     if pu8_tsubfield == EAP_PSK_T1
                         && (device.state == DeviceState::BS_STATE_WAITING_EAP_MSG_2
                             || device.state == DeviceState::BS_STATE_SENT_EAP_MSG_1)
@@ -405,7 +668,7 @@ impl LbpManager {
                             &mut device,
                             &self.g_id_s,
                             self.current_key_index,
-                            false,
+                            rekeying,
                             &mut self.u8_eap_identifier,
                             &mut self.g_u32_nonce,
                         ) {
@@ -418,6 +681,9 @@ impl LbpManager {
                             device.state = DeviceState::BS_STATE_WAITING_JOINNING;
                             device.uc_pending_confirms = 0;
                             log::info!("[BS] Slot updated to BS_STATE_WAITING_JOINNING");
+                            if let (true, Some(rekey)) = (rekeying, self.rekey.as_mut()) {
+                                rekey.failed(&msg.ext_addr);
+                            }
                         }
                     } else if pu8_tsubfield == EAP_PSK_T3
                         && (device.state == DeviceState::BS_STATE_WAITING_EAP_MSG_4
@@ -431,12 +697,39 @@ impl LbpManager {
                         ) {
                             device.state = DeviceState::BS_STATE_SENT_EAP_MSG_ACCEPTED;
                             log::info!("[BS] Slot updated to BS_STATE_SENT_EAP_MSG_ACCEPTED");
+                            match self.rekey.as_mut() {
+                                Some(rekey) if rekeying => {
+                                    rekey.done(&msg.ext_addr);
+                                    let (done, total) = rekey.progress();
+                                    log::info!("[BS] Rekey of {} done ({}/{})", msg.ext_addr, done, total);
+                                    device.key_index = Some(rekey.key_index());
+                                    self.device_manager.register(&device, rekey.key_index());
+                                }
+                                rekey => {
+                                    //A device joining while the new key is distributed gets it afterwards
+                                    if let Some(rekey) = rekey {
+                                        rekey.reset(&msg.ext_addr);
+                                    }
+                                    device.key_index = Some(self.current_key_index);
+                                    device.join_time.get_or_insert(device_db::now_secs());
+                                    self.device_manager.register(&device, self.current_key_index);
+                                    log::info!(target: "audit", "join accepted ext_addr={} short_addr=0x{:04x}",
+                                        device.m_lbd_address, device.us_assigned_short_address);
+                                    self.notify(DeviceEvent::Joined {
+                                        ext_addr: device.m_lbd_address,
+                                        short_addr: device.us_assigned_short_address,
+                                    });
+                                }
+                            }
                             return Some(result);
                         } else {
                             log::warn!("[BS] LBP error processing EAP T3.");
                             device.state = DeviceState::BS_STATE_WAITING_JOINNING;
                             device.uc_pending_confirms = 0;
                             log::info!("[BS] Slot updated to BS_STATE_WAITING_JOINNING");
+                            if let (true, Some(rekey)) = (rekeying, self.rekey.as_mut()) {
+                                rekey.failed(&msg.ext_addr);
+                            }
                         }
                     } else {
                         /* Abort current BS process */
@@ -482,6 +775,97 @@ impl LbpManager {
         // }
     }
 
+    pub fn stats(&self) -> &LbpStats {
+        &self.stats
+    }
+
+    /// Resends the last message of the slots whose answer did not come within UC_MESSAGE_TIMEOUT_MS.
+    /// A slot that used all its attempts goes back to BS_STATE_WAITING_JOINNING, the slots of the devices
+    /// that never completed the bootstrap are then freed with their short address.
+    pub fn update_devices(&mut self) -> Vec<request::AdpLbpRequest> {
+        let now = self.start_time.elapsed().as_millis();
+        let mut requests = Vec::new();
+        let mut released = Vec::new();
+        let devices: Vec<DeviceSlotRef> = self.device_manager.get_devices().values().cloned().collect();
+        for device in devices {
+            let mut device = device.deref().borrow_mut();
+            if now < device.ul_timeout {
+                continue;
+            }
+            if device.state == DeviceState::BS_STATE_WAITING_JOINNING {
+                if device.key_index.is_none() {
+                    released.push(device.m_lbd_address);
+                }
+                continue;
+            }
+            let ext_addr = device.m_lbd_address;
+            //A confirm is waited for as long as a message and its resends, then it is lost
+            let confirm_lost = device.uc_pending_confirms > 0 && device.uc_confirm_waits > self.max_retries;
+            if confirm_lost {
+                log::warn!("[BS] No confirm for handle {} of {}", device.uc_tx_handle, ext_addr);
+            }
+            if device.uc_tx_attemps >= self.max_retries
+                || device.data.is_none()
+                || device.state == DeviceState::BS_STATE_SENT_EAP_MSG_DECLINED
+                || confirm_lost
+            {
+                self.stats.slot_timeouts += 1;
+                log::warn!("[BS] Timeout, reset slot of {} in {:?} after {} attempts (slot timeouts {})",
+                    ext_addr, device.state, device.uc_tx_attemps, self.stats.slot_timeouts);
+                device.state = DeviceState::BS_STATE_WAITING_JOINNING;
+                device.uc_pending_confirms = 0;
+                device.data = None;
+                if let Some(ref mut rekey) = self.rekey {
+                    rekey.failed(&ext_addr);
+                }
+                if device.key_index.is_none() {
+                    released.push(ext_addr);
+                    continue;
+                }
+                self.device_manager.publish(&device);
+                continue;
+            }
+            if device.uc_pending_confirms > 0 {
+                /* Pending confirm then increase timeout time, nothing is sent so no attempt is used */
+                log::info!("[BS] Pending confirm for {}, waiting", ext_addr);
+                device.ul_timeout = now + UC_MESSAGE_TIMEOUT_MS;
+                device.uc_confirm_waits += 1;
+                continue;
+            }
+            let attempts = device.uc_tx_attemps + 1;
+            let out: Vec<u8> = match device.state {
+                DeviceState::BS_STATE_SENT_EAP_MSG_1 | DeviceState::BS_STATE_WAITING_EAP_MSG_2 => {
+                    device.state = DeviceState::BS_STATE_SENT_EAP_MSG_1;
+                    lbp::ChallengeMessage { ext_addr, bootstrapping_data: device.data.clone() }.into()
+                }
+                DeviceState::BS_STATE_SENT_EAP_MSG_3 | DeviceState::BS_STATE_WAITING_EAP_MSG_4 => {
+                    device.state = DeviceState::BS_STATE_SENT_EAP_MSG_3;
+                    lbp::ChallengeMessage { ext_addr, bootstrapping_data: device.data.clone() }.into()
+                }
+                _ => lbp::AcceptedMessage { ext_addr, bootstrapping_data: device.data.clone() }.into(),
+            };
+            self.stats.retransmissions += 1;
+            log::info!("[BS] Timeout detected, resending {:?} to {} attempt {} (retransmissions {})",
+                device.state, ext_addr, attempts, self.stats.retransmissions);
+            let request = if self.rekey.as_ref().is_some_and(|r| r.is_in_progress(&ext_addr)) {
+                let short_addr = device.us_assigned_short_address;
+                self.slot_request(&mut device, TAddress::Short(short_addr), true, out)
+            } else {
+                self.slot_request(&mut device, ext_addr.into(), false, out)
+            };
+            device.uc_tx_attemps = attempts;
+            self.device_manager.publish(&device);
+            requests.push(request);
+        }
+        for ext_addr in released {
+            if let Some(device) = self.device_manager.remove(&ext_addr) {
+                log::info!("[BS] Bootstrap of {} not completed, short address 0x{:04x} released",
+                    ext_addr, device.borrow().us_assigned_short_address);
+            }
+        }
+        requests
+    }
+
     pub fn process_response(&mut self, lbp_response: &AdpG3LbpReponse) {
         for (_addr, device) in self.device_manager.get_devices() {
             if device.borrow().uc_pending_confirms == 1
@@ -518,8 +902,15 @@ impl LbpManager {
                 } else {
                     device.state = DeviceState::BS_STATE_WAITING_JOINNING;
                     device.uc_pending_confirms = 0;
+                    if let Some(ref mut rekey) = self.rekey {
+                        if rekey.is_in_progress(&device.m_lbd_address) {
+                            log::warn!("[BS] Rekey of {} failed, status {:?}", device.m_lbd_address, lbp_response.status);
+                            rekey.failed(&device.m_lbd_address);
+                        }
+                    }
                 }
                 device.ul_timeout = self.start_time.elapsed().as_millis() + UC_MESSAGE_TIMEOUT_MS;
+                self.device_manager.publish(&device);
             } else if (device.borrow().uc_pending_confirms == 2
                 && lbp_response.handle == device.borrow().uc_pending_tx_handler)
             {
@@ -529,6 +920,261 @@ impl LbpManager {
             }
         }
     }
+    /// The device left the network on its own, its slot and short address are freed.
+    /// Only a frame secured with the GMK and sent from the short address of the device is trusted.
+    fn process_kick_from_lbd(&mut self, msg: &lbp::KickFromLbdMessage) {
+        let short_addr = self.device_manager.get_device_by_addr(&msg.ext_addr).map(|d| d.borrow().us_assigned_short_address);
+        match short_addr {
+            Some(_) if !msg.security_enabled => {
+                log::warn!("[BS] Dropping unsecured KICK_FROM_LBD for {} from 0x{:04x}", msg.ext_addr, msg.src_addr);
+                return;
+            }
+            Some(short_addr) if short_addr != msg.src_addr => {
+                log::warn!("[BS] Dropping KICK_FROM_LBD for {} (0x{:04x}) sent from 0x{:04x}", msg.ext_addr, short_addr, msg.src_addr);
+                return;
+            }
+            _ => {}
+        }
+        match self.device_manager.remove(&msg.ext_addr) {
+            Some(device) => {
+                let short_addr = device.borrow().us_assigned_short_address;
+                log::info!("[BS] Device {} (0x{:04x}) left the network", msg.ext_addr, short_addr);
+                log::info!(target: "audit", "device left ext_addr={} short_addr=0x{:04x}", msg.ext_addr, short_addr);
+                if let Some(ref mut rekey) = self.rekey {
+                    rekey.failed(&msg.ext_addr);
+                }
+                self.notify(DeviceEvent::Left { ext_addr: msg.ext_addr, short_addr });
+            }
+            None => {
+                log::warn!("[BS] KICK_FROM_LBD from unknown device {}", msg.ext_addr);
+            }
+        }
+    }
+
+    /// Forces a device, given by its EUI-64 or its short address, out of the network.
+    /// The slot is freed right away, the device has to bootstrap again to come back.
+    pub fn kick(&mut self, addr: &TAddress) -> Option<request::AdpLbpRequest> {
+        let ext_addr = match addr {
+            TAddress::Extended(ext_addr) => *ext_addr,
+            TAddress::Short(short_addr) => match self.device_manager.get_device_by_short_addr(*short_addr) {
+                Some(device) => device.borrow().m_lbd_address,
+                None => {
+                    log::warn!("[BS] Cannot kick 0x{:04x}, unknown short address", short_addr);
+                    return None;
+                }
+            },
+        };
+        let device = match self.device_manager.remove(&ext_addr) {
+            Some(device) => device,
+            None => {
+                log::warn!("[BS] Cannot kick {}, unknown device", ext_addr);
+                return None;
+            }
+        };
+        let short_addr = device.borrow().us_assigned_short_address;
+        log::info!("[BS] Kicking device {} (0x{:04x})", ext_addr, short_addr);
+        log::info!(target: "audit", "device kicked ext_addr={} short_addr=0x{:04x}", ext_addr, short_addr);
+        if let Some(ref mut rekey) = self.rekey {
+            rekey.failed(&ext_addr);
+        }
+        self.notify(DeviceEvent::Kicked { ext_addr, short_addr });
+
+        //The device holds the GMK, the kick is sent secured to its short address
+        Some(self.secured_request(short_addr, lbp::KickToLbdMessage::new(ext_addr).into()))
+    }
+
+    /// LBP request to a joined device, outside of the bootstrap its confirm is not tracked
+    fn secured_request(&mut self, short_addr: u16, out: Vec<u8>) -> request::AdpLbpRequest {
+        self.uc_nsdu_handle = self.uc_nsdu_handle.wrapping_add(1);
+        request::AdpLbpRequest::new(TAddress::Short(short_addr), out, self.uc_nsdu_handle, self.max_hops, true, 0, true)
+    }
+
+    /// Builds the LBP request carrying the slot data and tracks its confirm
+    fn slot_request(&mut self, device: &mut DeviceSlot, dst_addr: TAddress, security_enable: bool, out: Vec<u8>) -> request::AdpLbpRequest {
+        if (device.uc_pending_confirms > 0) {
+            device.uc_pending_tx_handler = device.uc_tx_handle;
+        }
+        self.uc_nsdu_handle = self.uc_nsdu_handle.wrapping_add(1);
+        device.uc_tx_handle = self.uc_nsdu_handle;
+        device.ul_timeout = self.start_time.elapsed().as_millis() + UC_MESSAGE_TIMEOUT_MS;
+        device.uc_tx_attemps = 0;
+        device.uc_confirm_waits = 0;
+        device.uc_pending_confirms += 1;
+        //The confirm carries this handle back, process_response matches it against uc_tx_handle
+        request::AdpLbpRequest::new(
+            dst_addr,
+            out,
+            device.uc_tx_handle,
+            self.max_hops,
+            true,
+            0,
+            security_enable,
+        )
+    }
+
+    /// Starts distributing gmk under the unused key index to the devices that completed the bootstrap.
+    /// When gmk is the key of an interrupted rekey, the devices already holding it are only told to activate it.
+    pub fn start_rekey(&mut self, gmk: Vec<u8>) -> Result<Vec<usi::OutMessage>, String> {
+        if self.rekey.is_some() {
+            return Err("rekey already in progress".to_string());
+        }
+        if gmk.len() != 16 {
+            return Err("the GMK is 16 bytes".to_string());
+        }
+        if gmk == self.gmk {
+            return Err("the GMK is already active".to_string());
+        }
+        let resumed = gmk == self.rekey_gmk;
+        let key_index = self.current_key_index ^ 0x01;
+        let mut devices = Vec::new();
+        let mut holding = Vec::new();
+        for device in self.device_manager.get_devices().values() {
+            let device = device.borrow();
+            match device.key_index {
+                Some(i) if i == key_index && resumed => holding.push(device.m_lbd_address),
+                Some(_) => devices.push(device.m_lbd_address),
+                None => {}
+            }
+        }
+        log::info!("[BS] Starting rekey of {} devices, key index {}, {} already hold the key", devices.len(), key_index, holding.len());
+        log::info!(target: "audit", "rekey started key_index={} devices={}", key_index, devices.len());
+        let mut rekey = Rekey::new(key_index, devices);
+        for ext_addr in &holding {
+            rekey.done(ext_addr);
+        }
+        self.rekey = Some(rekey);
+        self.rekey_gmk = gmk;
+        self.store_gmk(Some(self.rekey_gmk.clone()));
+
+        //The coordinator needs the new key before any device switches to it
+        let mut out: Vec<usi::OutMessage> = vec![request::AdpMacSetRequest::new(
+            EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE,
+            key_index as u16,
+            &self.rekey_gmk,
+        )
+        .into()];
+        out.extend(self.poll_rekey());
+        Ok(out)
+    }
+
+    /// Writes the GMK in use, and the one being distributed, to the gmk_file
+    fn store_gmk(&self, rekey_gmk: Option<Vec<u8>>) {
+        let path = match self.gmk_file {
+            Some(ref path) => path,
+            None => {
+                log::warn!("[BS] No gmk_file configured, the GMK of the rekey is lost on restart");
+                return;
+            }
+        };
+        let stored = StoredGmk { gmk: self.gmk.clone(), key_index: self.current_key_index, rekey_gmk };
+        if let Err(e) = stored.save(path) {
+            log::error!("[BS] Failed to store the GMK in {} : {}", path, e);
+        }
+    }
+
+    /// Moves the rekey forward, called periodically by the network manager
+    pub fn poll_rekey(&mut self) -> Vec<usi::OutMessage> {
+        let now = self.start_time.elapsed().as_millis();
+        if let Some(ref mut rekey) = self.rekey {
+            if let Some(ext_addr) = rekey.timed_out(now) {
+                log::warn!("[BS] Rekey of {} timed out", ext_addr);
+                rekey.failed(&ext_addr);
+            }
+        }
+        loop {
+            let next = match self.rekey.as_mut() {
+                Some(rekey) => rekey.start_next(now + REKEY_DEVICE_TIMEOUT_MS),
+                None => return Vec::new(),
+            };
+            match next {
+                Some(ext_addr) => {
+                    if let Some(request) = self.rekey_challenge(&ext_addr) {
+                        return vec![request.into()];
+                    }
+                    log::warn!("[BS] Rekey of {} failed, device unknown or without key", ext_addr);
+                    if let Some(ref mut rekey) = self.rekey {
+                        rekey.failed(&ext_addr);
+                    }
+                }
+                None => break,
+            }
+        }
+        if self.rekey.as_ref().is_some_and(|r| r.is_finished()) {
+            return self.activate_rekey();
+        }
+        Vec::new()
+    }
+
+    /// Sends EAP-PSK message 1 to a registered device, its answers go through process_joining0 as a rekey
+    fn rekey_challenge(&mut self, ext_addr: &TExtendedAddress) -> Option<request::AdpLbpRequest> {
+        let device = self.device_manager.get_device_by_addr(ext_addr)?.clone();
+        let psk = self.psk_store.key_for(ext_addr)?;
+        let mut device = device.deref().borrow_mut();
+        let short_addr = device.us_assigned_short_address;
+        device.reset(*ext_addr, short_addr);
+        eap_psk_initialize(psk, &mut device.m_psk_context);
+        device.m_rand_s = self.rand_source.next_rand();
+        device.data = Some(eap_psk_encode_message1(
+            self.u8_eap_identifier,
+            &device.m_rand_s,
+            &self.g_id_s,
+        ));
+        device.state = DeviceState::BS_STATE_SENT_EAP_MSG_1;
+        self.u8_eap_identifier = self.u8_eap_identifier.wrapping_add(1);
+        self.device_manager.publish(&device);
+        log::info!("[BS] Rekey of {} (0x{:04x}) started", ext_addr, short_addr);
+        let out = lbp::ChallengeMessage {
+            ext_addr: *ext_addr,
+            bootstrapping_data: device.data.clone(),
+        }
+        .into();
+        Some(self.slot_request(&mut device, TAddress::Short(short_addr), true, out))
+    }
+
+    /// Kicks the devices that failed to take the new GMK, tells the others to activate it, then switches
+    /// the coordinator to the new key index. All of them are sent secured with the old key.
+    fn activate_rekey(&mut self) -> Vec<usi::OutMessage> {
+        let rekey = match self.rekey.take() {
+            Some(rekey) => rekey,
+            None => return Vec::new(),
+        };
+        let key_index = rekey.key_index();
+        let failed = rekey.failed_devices();
+        let (done, total) = rekey.progress();
+        log::info!("[BS] New GMK distributed to {}/{} devices, activating key index {}", done, total, key_index);
+        log::info!(target: "audit", "rekey activated key_index={} done={} failed={}", key_index, done, failed.len());
+        let mut out: Vec<usi::OutMessage> = Vec::new();
+        for ext_addr in &failed {
+            log::warn!("[BS] Device {} did not take the new GMK, it has to join again", ext_addr);
+            log::info!(target: "audit", "rekey failed ext_addr={}", ext_addr);
+            //A device that left or was kicked during the rekey is already gone
+            if self.device_manager.get_device_by_addr(ext_addr).is_some() {
+                out.extend(self.kick(&TAddress::Extended(*ext_addr)).map(Into::into));
+            }
+        }
+        for ext_addr in rekey.done_devices() {
+            let short_addr = match self.device_manager.get_device_by_addr(&ext_addr) {
+                Some(device) => device.borrow().us_assigned_short_address,
+                None => continue,
+            };
+            let activation = lbp::AcceptedMessage {
+                ext_addr,
+                bootstrapping_data: Some(vec![CONF_PARAM_GMK_ACTIVATION, 1, key_index]),
+            };
+            out.push(self.secured_request(short_addr, activation.into()).into());
+        }
+
+        //Devices joining from now on get the new key
+        self.current_key_index = key_index;
+        self.gmk = self.rekey_gmk.clone();
+        self.store_gmk(None);
+        self.notify(DeviceEvent::RekeyCompleted { key_index, failed });
+
+        let value = vec![key_index];
+        out.push(request::AdpSetRequest::new(EAdpPibAttribute::ADP_IB_ACTIVE_KEY_INDEX, 0, &value).into());
+        out
+    }
+
     pub fn process_msg(&mut self, lbp_message: &lbp::LbpMessage) -> Option<request::AdpLbpRequest> {
         let mut out_message: Option<Vec<u8>> = None;
         let mut addr: Option<TExtendedAddress> = None;
@@ -537,28 +1183,36 @@ impl LbpManager {
             lbp::LbpMessage::Joining(joining_message) => {
                 addr = Some(joining_message.ext_addr);
                 out_message = self.process_joining0(&joining_message);
+                self.device_manager.seen(&joining_message.ext_addr);
+            }
+            lbp::LbpMessage::KickFromLbd(kick_message) => {
+                self.process_kick_from_lbd(kick_message);
+            }
+            //Only sent by the coordinator, a device never sends them
+            lbp::LbpMessage::Accepted(_)
+            | lbp::LbpMessage::Challenge(_)
+            | lbp::LbpMessage::Decline(_)
+            | lbp::LbpMessage::KickToLbd(_) => {
+                log::warn!("[BS] Dropping unexpected LBP message {:?}", lbp_message);
             }
-            lbp::LbpMessage::Accepted(_) => todo!(),
-            lbp::LbpMessage::Challenge(_) => todo!(),
-            lbp::LbpMessage::Decline(_) => todo!(),
-            lbp::LbpMessage::KickFromLbd(_) => todo!(),
-            lbp::LbpMessage::KickToLbd(_) => todo!(),
         }
         if let (Some(out), Some(addr)) = (out_message, addr) {
-            if let Some(device) = self.device_manager.get_device_by_addr(&addr) {
+            if let Some(device) = self.device_manager.get_device_by_addr(&addr).cloned() {
                 let mut device = device.deref().borrow_mut();
-                if (device.uc_pending_confirms > 0) {
-                    device.uc_pending_tx_handler = device.uc_tx_handle;
+                //A device being rekeyed is part of the network, it is reached by its short address
+                if self.rekey.as_ref().is_some_and(|r| r.is_in_progress(&addr)) {
+                    let short_addr = device.us_assigned_short_address;
+                    return Some(self.slot_request(&mut device, TAddress::Short(short_addr), true, out));
                 }
-                self.uc_nsdu_handle += 1;
-                device.uc_tx_handle = self.uc_nsdu_handle;
-                device.ul_timeout = self.start_time.elapsed().as_millis() + UC_MESSAGE_TIMEOUT_MS;
-                device.uc_tx_attemps = 0;
-                device.uc_pending_confirms += 1;
+                return Some(self.slot_request(&mut device, addr.into(), false, out));
+            }
+            else {
+                //Declined devices have no bootstrap slot, the confirm is not tracked
+                self.uc_nsdu_handle = self.uc_nsdu_handle.wrapping_add(1);
                 return Some(request::AdpLbpRequest::new(
                     addr.into(),
                     out,
-                    device.uc_tx_handle - 1,
+                    self.uc_nsdu_handle,
                     self.max_hops,
                     true,
                     0,
@@ -570,76 +1224,6 @@ impl LbpManager {
         None
     }
 
-    // pub fn update_devices(&mut self)
-    // {
-    //     uint8_t uc_i;
-
-    //     for (uc_i = 0; uc_i < BOOTSTRAP_NUM_SLOTS; uc_i++) {
-    //         /* log_show_slots_status(); */
-    //         if (bootstrap_slots[uc_i].e_state != BS_STATE_WAITING_JOINNING) {
-    //             if (timeout_is_past(bootstrap_slots[uc_i].ul_timeout)) {
-    //                 LOG_BOOTSTRAP(("[BS] timeout_is_past for %d\r\n",uc_i));
-    //                 if (bootstrap_slots[uc_i].uc_pending_confirms == 0) {
-    //                     if (bootstrap_slots[uc_i].uc_tx_attemps < BOOTSTRAP_MSG_MAX_RETRIES) {
-    //                         bootstrap_slots[uc_i].uc_tx_attemps++;
-    //                         if (bootstrap_slots[uc_i].e_state == BS_STATE_WAITING_EAP_MSG_2) {
-    //                             bootstrap_slots[uc_i].e_state = BS_STATE_SENT_EAP_MSG_1;
-    //                             LOG_BOOTSTRAP(("[BS] Slot updated to BS_STATE_SENT_EAP_MSG_1\r\n"));
-    //                             log_show_slots_status();
-    //                         } else if (bootstrap_slots[uc_i].e_state == BS_STATE_WAITING_EAP_MSG_4) {
-    //                             bootstrap_slots[uc_i].e_state = BS_STATE_SENT_EAP_MSG_3;
-    //                             LOG_BOOTSTRAP(("[BS] Slot updated to BS_STATE_SENT_EAP_MSG_3\r\n"));
-    //                             log_show_slots_status();
-    //                         }
-
-    //                         struct TAddress dstAddr;
-    //                         struct TAdpGetConfirm getConfirm;
-
-    //                         if (bootstrap_slots[uc_i].us_data_length > 0) {
-    //                             if (bootstrap_slots[uc_i].us_lba_src_addr == 0xFFFF) {
-    //                                 dstAddr.m_u8AddrLength = 8;
-    //                                 memcpy(dstAddr.m_u8ExtendedAddr, &bootstrap_slots[uc_i].m_LbdAddress.m_au8Value, 8);
-    //                             } else {
-    //                                 dstAddr.m_u8AddrLength = 2;
-    //                                 dstAddr.m_u16ShortAddr = bootstrap_slots[uc_i].us_lba_src_addr;
-    //                             }
-
-    //                             if (bootstrap_slots[uc_i].uc_pending_confirms > 0) {
-    //                                 bootstrap_slots[uc_i].uc_pending_tx_handler = bootstrap_slots[uc_i].uc_tx_handle;
-    //                             }
-
-    //                             bootstrap_slots[uc_i].uc_tx_handle = get_next_nsdu_handler();
-    //                             bootstrap_slots[uc_i].ul_timeout = oss_get_up_time_ms() + 1000 * us_msg_timeout_in_s;
-    //                             bootstrap_slots[uc_i].uc_pending_confirms++;
-
-    //                             LOG_BOOTSTRAP(("[BS] Timeout detected. Re-sending MSG for slot: %d Attempt: %d \r\n", uc_i,
-    //                                     bootstrap_slots[uc_i].uc_tx_attemps));
-    //                             log_show_slots_status();
-    //                             LOG_BOOTSTRAP(("[BS] AdpLbpRequest Called, handler: %d \r\n", bootstrap_slots[uc_i].uc_tx_handle));
-    //                             AdpLbpRequest((struct TAdpAddress const *)&dstAddr,     /* Destination address */
-    //                                     bootstrap_slots[uc_i].us_data_length,                              /* NSDU length */
-    //                                     &bootstrap_slots[uc_i].auc_data[0],                                  /* NSDU */
-    //                                     bootstrap_slots[uc_i].uc_tx_handle,                            /* NSDU handle */
-    //                                     g_s_bs_conf.m_u8MaxHop,          						/* Max. Hops */
-    //                                     true,                                       /* Discover route */
-    //                                     0,                                          /* QoS */
-    //                                     false);                                     /* Security enable */
-    //                         }
-    //                     } else {
-    //                         LOG_BOOTSTRAP(("[BS] Reset slot %d:  \r\n", uc_i));
-    //                         bootstrap_slots[uc_i].e_state = BS_STATE_WAITING_JOINNING;
-    //                         bootstrap_slots[uc_i].uc_pending_confirms = 0;
-    //                         bootstrap_slots[uc_i].ul_timeout = 0xFFFFFFFF;
-    //                     }
-    //                 } else { /* Pending confirm then increase timeout time */
-    //                     LOG_BOOTSTRAP(("[BS] Pending confirm\r\n"));
-    //                     bootstrap_slots[uc_i].ul_timeout = oss_get_up_time_ms() + 1000 * us_msg_timeout_in_s;
-    //                 }
-    //             }
-    //         }
-    //     }
-    // }
-
     // pub fn get_short_addr_from_ipv6_addr (&self, ipv6_addr: Ipv6Addr) -> Option<u16> {
     //     if let Ok(extended_addr) = ipv6_addr.try_into() {
     //         self.device_manager.get_device_by_addr(&extended_addr).map_or(None, |ds| {
@@ -651,3 +1235,413 @@ impl LbpManager {
     //     }
     // }
 }
+
+/// Full bootstrap against reference frames, see lbp_functions::tests for how they are built
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp::AdpG3LbpEvent;
+    use crate::common::from_hex_string;
+    use crate::rekey::RekeyStatus;
+
+    const EXT_ADDR: TExtendedAddress = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]);
+    const CHALLENGE1: &str = "a0000080e1fffe0000010400001e2f00000102030405060708090a0b0c0d0e0f8172635445362718";
+    const JOINING2: &str = "10000080e1fffe0000010800003e2f40000102030405060708090a0b0c0d0e0f\
+        101112131415161718191a1b1c1d1e1f708d05e4cb459ea4c68f00e5588ef1fe0080e1fffe000001";
+    const CHALLENGE3: &str = "a0000080e1fffe000001040100562f80000102030405060708090a0b0c0d0e0f\
+        ab046374a7d68c208b89fca38c39bce8000000003d969d0edf8dfb637cb2bd88a8b3cafaca532cdaed49396ac18cac1ca429b0b338a605556d186a127928e972";
+    const JOINING4: &str = "10000080e1fffe0000010801002b2fc0000102030405060708090a0b0c0d0e0f00000001b0babb49ef9760ab7f6f3f75f8804ec534";
+    const ACCEPTED: &str = "90000080e1fffe0000010c020004";
+
+    fn config() -> app_config::G3 {
+        app_config::G3 {
+            mode: 0,
+            pan_id: 0x781d,
+            band: TAdpBand::ADP_BAND_CENELEC_A.into(),
+            psk: [0xab, 0x10, 0x34, 0x11, 0x45, 0x11, 0x1b, 0xc3, 0xc1, 0x2d, 0xe8, 0xff, 0x11, 0x14, 0x22, 0x04],
+            psk_2: [0u8; 16],
+            gmk: vec![0xAF, 0x4D, 0x6D, 0xCC, 0xF1, 0x4D, 0xE7, 0xC1, 0xC4, 0x23, 0x5E, 0x6F, 0xEF, 0x6C, 0x15, 0x1F],
+            rekey_gmk: vec![0u8; 16],
+            ids: Vec::new(),
+            context_information_table_0: Vec::new(),
+            context_information_table_1: Vec::new(),
+            ids_arib: Vec::new(),
+            ids_cenelec_fcc: vec![0x81, 0x72, 0x63, 0x54, 0x45, 0x36, 0x27, 0x18],
+            max_hops: 0x0A,
+            discovery_timeout_secs: 10,
+            device_db: None,
+            psk_file: None,
+            psk_fallback: None,
+            allow_list: None,
+            deny_list: None,
+            max_devices: None,
+            active_key_index: None,
+            gmk_file: None,
+            bootstrap_max_retries: None,
+            host_lbd: None,
+            short_addr_allocation: None,
+            static_short_addrs: None,
+            nv_data_file: None,
+            pcap: None,
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        from_hex_string(s).unwrap()
+    }
+
+    fn lbp_message(frame: &str) -> lbp::LbpMessage {
+        let event = AdpG3LbpEvent {
+            src_addr: 0,
+            nsdu: hex(frame),
+            link_quality_indicator: 0,
+            security_enabled: false,
+        };
+        lbp::adp_message_to_lbp_message(&event).unwrap()
+    }
+
+    fn lbp_manager() -> LbpManager {
+        let mut lbp_manager = LbpManager::new(&config());
+        lbp_manager.set_rand_source(Box::new(FixedRandSource::new(vec![core::array::from_fn(|i| i as u8)])));
+        lbp_manager
+    }
+
+    #[test]
+    fn bootstrap_exchange() {
+        let mut lbp_manager = lbp_manager();
+        let (tx, rx) = flume::unbounded();
+        lbp_manager.add_listener(tx);
+        let registry = DeviceRegistry::new([0u8; 6]);
+        lbp_manager.set_registry(registry.clone());
+
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        let challenge1 = lbp_manager.process_msg(&joining).unwrap();
+        assert_eq!(challenge1.data(), hex(CHALLENGE1));
+
+        let challenge3 = lbp_manager.process_msg(&lbp_message(JOINING2)).unwrap();
+        assert_eq!(challenge3.data(), hex(CHALLENGE3));
+
+        let accepted = lbp_manager.process_msg(&lbp_message(JOINING4)).unwrap();
+        assert_eq!(accepted.data(), hex(ACCEPTED));
+
+        match rx.try_recv() {
+            Ok(DeviceEvent::Joined { ext_addr, short_addr }) => assert_eq!((ext_addr, short_addr), (EXT_ADDR, 0x0001)),
+            other => panic!("unexpected device event {:?}", other),
+        }
+        let info = registry.get_by_short_addr(0x0001).unwrap();
+        assert_eq!((info.ext_addr, info.state, info.key_index), (EXT_ADDR, BootstrapState::Accepted, Some(0)));
+        assert!(info.join_time.is_some());
+    }
+
+    #[test]
+    fn unreadable_psk_file_refuses_joins() {
+        let mut config = config();
+        config.psk_file = Some("/nonexistent/ne-g3-psk.txt".to_string());
+        let mut lbp_manager = LbpManager::new(&config);
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        let decline = lbp_manager.process_msg(&joining).unwrap();
+        let expected: Vec<u8> = lbp::DeclineMessage::new(EXT_ADDR).into();
+        assert_eq!(decline.data(), expected);
+    }
+
+    #[test]
+    fn psk_fallback() {
+        let path = std::env::temp_dir().join(format!("ne-g3-psk-fallback-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "00:80:E1:FF:FE:00:00:02 00112233445566778899AABBCCDDEEFF\n").unwrap();
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        let decline: Vec<u8> = lbp::DeclineMessage::new(EXT_ADDR).into();
+        for (psk_fallback, declined) in [(None, false), (Some(true), false), (Some(false), true)] {
+            let mut config = config();
+            config.psk_file = Some(path.to_string());
+            config.psk_fallback = psk_fallback;
+            let mut lbp_manager = LbpManager::new(&config);
+            lbp_manager.set_rand_source(Box::new(FixedRandSource::new(vec![core::array::from_fn(|i| i as u8)])));
+            let reply = lbp_manager.process_msg(&joining).unwrap();
+            assert_eq!(reply.data() == decline, declined, "psk_fallback {:?}", psk_fallback);
+            if !declined {
+                //The device missing from the file goes on with the global PSK
+                assert_eq!(reply.data(), hex(CHALLENGE1));
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn admission_of_config() {
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        let decline: Vec<u8> = lbp::DeclineMessage::new(EXT_ADDR).into();
+        let mut denied = config();
+        denied.deny_list = Some(vec!["00:80:E1:FF:FE:00:00:01".to_string()]);
+        let mut not_allowed = config();
+        not_allowed.allow_list = Some(vec!["00:80:E1:FF:FE:00:00:02".to_string()]);
+        let mut full = config();
+        full.max_devices = Some(0);
+        for config in [denied, not_allowed, full] {
+            let mut lbp_manager = LbpManager::new(&config);
+            assert_eq!(lbp_manager.process_msg(&joining).unwrap().data(), decline);
+        }
+    }
+
+    #[test]
+    fn kick_from_lbd_is_checked() {
+        let mut lbp_manager = lbp_manager();
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        lbp_manager.process_msg(&joining).unwrap();
+        let kick = |src_addr, security_enabled| {
+            let event = AdpG3LbpEvent {
+                src_addr,
+                nsdu: lbp::KickFromLbdMessage::new(EXT_ADDR).into(),
+                link_quality_indicator: 0,
+                security_enabled,
+            };
+            lbp::adp_message_to_lbp_message(&event).unwrap()
+        };
+
+        lbp_manager.process_msg(&kick(0x0001, false));
+        lbp_manager.process_msg(&kick(0x0002, true));
+        assert!(lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).is_some());
+        lbp_manager.process_msg(&kick(0x0001, true));
+        assert!(lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).is_none());
+    }
+
+    #[test]
+    fn pending_confirm_uses_no_attempt() {
+        let mut lbp_manager = lbp_manager();
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        let challenge1 = lbp_manager.process_msg(&joining).unwrap();
+        let device = lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).unwrap().clone();
+
+        for _ in 0..=lbp_manager.max_retries {
+            device.deref().borrow_mut().ul_timeout = 0;
+            assert!(lbp_manager.update_devices().is_empty());
+        }
+        assert_eq!(device.borrow().uc_tx_attemps, 0);
+        assert_eq!(device.borrow().state, DeviceState::BS_STATE_SENT_EAP_MSG_1);
+
+        let handle = device.borrow().uc_tx_handle;
+        lbp_manager.process_response(&AdpG3LbpReponse { status: EAdpStatus::G3_SUCCESS, handle });
+        device.deref().borrow_mut().ul_timeout = 0;
+        let resent = lbp_manager.update_devices();
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].data(), challenge1.data());
+        assert_eq!(device.borrow().uc_tx_attemps, 1);
+    }
+
+    #[test]
+    fn lost_confirm_releases_the_slot() {
+        let mut lbp_manager = lbp_manager();
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        lbp_manager.process_msg(&joining).unwrap();
+        let device = lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).unwrap().clone();
+
+        for _ in 0..=lbp_manager.max_retries {
+            device.deref().borrow_mut().ul_timeout = 0;
+            assert!(lbp_manager.update_devices().is_empty());
+            assert_eq!(device.borrow().state, DeviceState::BS_STATE_SENT_EAP_MSG_1);
+        }
+        device.deref().borrow_mut().ul_timeout = 0;
+        assert!(lbp_manager.update_devices().is_empty());
+        assert_eq!(device.borrow().state, DeviceState::BS_STATE_WAITING_JOINNING);
+        assert_eq!(device.borrow().uc_pending_confirms, 0);
+        assert_eq!(lbp_manager.stats().slot_timeouts, 1);
+        assert!(lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).is_none());
+
+        //The device can start its join again
+        assert!(lbp_manager.process_msg(&joining).is_some());
+        let device = lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).unwrap();
+        assert_eq!(device.borrow().state, DeviceState::BS_STATE_SENT_EAP_MSG_1);
+    }
+
+    #[test]
+    fn unfinished_bootstrap_releases_short_addr() {
+        let mut lbp_manager = lbp_manager();
+        assert!(lbp_manager.process_msg(&lbp_message(JOINING2)).is_none());
+        assert!(lbp_manager.device_manager.get_devices().is_empty());
+
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        lbp_manager.process_msg(&joining).unwrap();
+        assert!(lbp_manager.device_manager.get_device_by_short_addr(0x0001).is_some());
+        {
+            let device = lbp_manager.device_manager.get_device_by_addr(&EXT_ADDR).unwrap();
+            let mut device = device.deref().borrow_mut();
+            device.uc_pending_confirms = 0;
+            device.uc_tx_attemps = lbp_manager.max_retries;
+            device.ul_timeout = 0;
+        }
+        assert!(lbp_manager.update_devices().is_empty());
+        assert!(lbp_manager.device_manager.get_devices().is_empty());
+        assert!(lbp_manager.device_manager.get_device_by_short_addr(0x0001).is_none());
+        assert!(lbp_manager.device_manager.allocator.reserve(&EXT_ADDR, 0x0001));
+    }
+
+    #[test]
+    fn registry_follows_kick_and_leave() {
+        let mut lbp_manager = lbp_manager();
+        let devices = joined(&mut lbp_manager, &[0, 0]);
+        let registry = DeviceRegistry::new([0u8; 6]);
+        lbp_manager.set_registry(registry.clone());
+        assert_eq!(registry.len(), 2);
+        let short_addrs: Vec<u16> = devices.iter().map(|d| registry.get_by_ext_addr(d).unwrap().short_addr).collect();
+
+        lbp_manager.kick(&TAddress::Extended(devices[0])).unwrap();
+        assert!(registry.get_by_ext_addr(&devices[0]).is_none());
+        assert!(registry.get_by_short_addr(short_addrs[0]).is_none());
+
+        let leave = AdpG3LbpEvent {
+            src_addr: short_addrs[1],
+            nsdu: lbp::KickFromLbdMessage::new(devices[1]).into(),
+            link_quality_indicator: 0,
+            security_enabled: true,
+        };
+        lbp_manager.process_msg(&lbp::adp_message_to_lbp_message(&leave).unwrap());
+        assert!(registry.get_by_short_addr(short_addrs[1]).is_none());
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[test]
+    fn bad_mac_p_is_not_answered() {
+        let mut lbp_manager = lbp_manager();
+        let joining = lbp::LbpMessage::Joining(JoiningMessage { ext_addr: EXT_ADDR, bootstrapping_data: Vec::new() });
+        lbp_manager.process_msg(&joining).unwrap();
+
+        let mut joining2 = hex(JOINING2);
+        joining2[10 + 6 + 32] ^= 0x01;
+        let event = AdpG3LbpEvent { src_addr: 0, nsdu: joining2, link_quality_indicator: 0, security_enabled: false };
+        let msg = lbp::adp_message_to_lbp_message(&event).unwrap();
+        assert!(lbp_manager.process_msg(&msg).is_none());
+    }
+
+    #[test]
+    fn short_addrs_restored_from_db() {
+        let path = std::env::temp_dir().join(format!("ne-g3-restore-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let pinned = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x09]);
+        let moved = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x02]);
+        let mut db = DeviceDb::open(path).unwrap();
+        for (ext_addr, short_addr) in [(EXT_ADDR, 0x0003), (moved, 0x0005)] {
+            let record = device_db::DeviceRecord {
+                ext_addr,
+                short_addr,
+                join_time: 1_697_539_200,
+                last_seen: 1_697_539_260,
+                key_index: 1,
+                state: "BS_STATE_ACCEPTED".to_string(),
+            };
+            db.update(record).unwrap();
+        }
+
+        //0x0005 is now pinned to another device, the device that had it gets a new address on its next join
+        let table = HashMap::from([(pinned, 0x0005)]);
+        let allocator = short_addr::StaticAllocator::new(table, Some(Box::new(short_addr::SequentialAllocator::new(MAX_DEVICES))));
+        let mut device_manager = DeviceManager::new(Some(DeviceDb::open(path).unwrap()), Box::new(allocator));
+        {
+            let device = device_manager.get_device_by_short_addr(0x0003).unwrap().borrow();
+            assert_eq!((device.m_lbd_address, device.key_index, device.join_time), (EXT_ADDR, Some(1), Some(1_697_539_200)));
+        }
+        assert!(device_manager.get_device_by_addr(&moved).is_none());
+
+        //The restored address is not given to another device
+        let short_addrs: Vec<u16> = (0x10..0x14)
+            .map(|last| {
+                let ext_addr = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, last]);
+                device_manager.add_or_get_by_addr(&ext_addr).unwrap().borrow().us_assigned_short_address
+            })
+            .collect();
+        assert_eq!(short_addrs, vec![0x0001, 0x0002, 0x0004, 0x0006]);
+        std::fs::remove_file(path).unwrap();
+    }
+    /// Devices that completed the bootstrap with this key index
+    fn joined(lbp_manager: &mut LbpManager, key_indexes: &[u8]) -> Vec<TExtendedAddress> {
+        let mut devices = Vec::new();
+        for (n, key_index) in key_indexes.iter().enumerate() {
+            let ext_addr = TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, n as u8 + 1]);
+            let device = lbp_manager.device_manager.add_or_get_by_addr(&ext_addr).unwrap();
+            let mut device = device.deref().borrow_mut();
+            device.state = DeviceState::BS_STATE_SENT_EAP_MSG_ACCEPTED;
+            device.key_index = Some(*key_index);
+            devices.push(ext_addr);
+        }
+        devices
+    }
+
+    fn lbp_data(out: &usi::OutMessage, data: Vec<u8>) -> bool {
+        out.data().first() == Some(&crate::adp::G3_SERIAL_MSG_ADP_LBP_REQUEST) && out.data().ends_with(&data)
+    }
+
+    #[test]
+    fn rekey_activation() {
+        let mut lbp_manager = lbp_manager();
+        let devices = joined(&mut lbp_manager, &[0, 0]);
+        let gmk = vec![0x5A; 16];
+        assert!(lbp_manager.start_rekey(config().gmk).is_err());
+        assert!(lbp_manager.start_rekey(vec![0x5A; 8]).is_err());
+
+        let out = lbp_manager.start_rekey(gmk.clone()).unwrap();
+        let key_table: usi::OutMessage = request::AdpMacSetRequest::new(EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE, 1, &gmk).into();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].data(), key_table.data());
+        assert!(lbp_manager.start_rekey(gmk.clone()).is_err());
+        let in_progress = |lbp_manager: &LbpManager| {
+            *devices.iter().find(|d| lbp_manager.rekey.as_ref().unwrap().is_in_progress(d)).unwrap()
+        };
+
+        //Nothing is activated before every device is done or failed
+        let done = in_progress(&lbp_manager);
+        lbp_manager.rekey.as_mut().unwrap().done(&done);
+        let out = lbp_manager.poll_rekey();
+        assert_eq!(out.len(), 1);
+        let failed = in_progress(&lbp_manager);
+        assert_ne!(done, failed);
+        assert!(lbp_manager.poll_rekey().is_empty());
+
+        //The device that failed is kicked, the other told to use key index 1
+        lbp_manager.rekey.as_mut().unwrap().failed(&failed);
+        let out = lbp_manager.poll_rekey();
+        let activation = lbp::AcceptedMessage { ext_addr: done, bootstrapping_data: Some(vec![CONF_PARAM_GMK_ACTIVATION, 1, 1]) };
+        let active_key_index: usi::OutMessage = request::AdpSetRequest::new(EAdpPibAttribute::ADP_IB_ACTIVE_KEY_INDEX, 0, &vec![1]).into();
+        assert_eq!(out.len(), 3);
+        assert!(lbp_data(&out[0], lbp::KickToLbdMessage::new(failed).into()));
+        assert!(lbp_data(&out[1], activation.into()));
+        assert_eq!(out[2].data(), active_key_index.data());
+        assert!(lbp_manager.device_manager.get_device_by_addr(&failed).is_none());
+        assert!(lbp_manager.rekey.is_none());
+        assert!(lbp_manager.start_rekey(gmk).is_err());
+    }
+
+    #[test]
+    fn resumed_rekey() {
+        let path = std::env::temp_dir().join(format!("ne-g3-rekey-gmk-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let gmk = vec![0x5A; 16];
+        //Restarted in the middle of a rekey, the key being distributed was stored
+        let mut config = config();
+        config.rekey_gmk = gmk.clone();
+        config.gmk_file = Some(path.to_string());
+        let mut lbp_manager = LbpManager::new(&config);
+        let devices = joined(&mut lbp_manager, &[1, 0]);
+
+        lbp_manager.start_rekey(gmk.clone()).unwrap();
+        let stored = StoredGmk { gmk: config.gmk.clone(), key_index: 0, rekey_gmk: Some(gmk.clone()) };
+        assert_eq!(StoredGmk::load(path).unwrap(), Some(stored));
+        let rekey = lbp_manager.rekey.as_ref().unwrap();
+        assert_eq!(rekey.status(&devices[0]), Some(RekeyStatus::Done));
+        assert!(rekey.is_in_progress(&devices[1]));
+
+        //The device that already held the key is told to activate it as well
+        lbp_manager.rekey.as_mut().unwrap().done(&devices[1]);
+        let out = lbp_manager.poll_rekey();
+        assert_eq!(out.len(), 3);
+        assert_eq!(out.iter().filter(|o| o.data()[0] == crate::adp::G3_SERIAL_MSG_ADP_LBP_REQUEST).count(), 2);
+        let stored = StoredGmk { gmk: gmk.clone(), key_index: 1, rekey_gmk: None };
+        assert_eq!(StoredGmk::load(path).unwrap(), Some(stored));
+
+        //Another key is distributed to every device
+        let mut lbp_manager = LbpManager::new(&config);
+        joined(&mut lbp_manager, &[1, 0]);
+        lbp_manager.start_rekey(vec![0xA5; 16]).unwrap();
+        assert_eq!(lbp_manager.rekey.as_ref().unwrap().progress(), (0, 2));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,19 +1,43 @@
 
+mod admission;
 mod adp;
+mod adp_client;
 mod app_config;
 mod common;
+mod control;
 mod crc;
+mod device_db;
+mod device_registry;
+mod gmk_store;
+mod lbd;
 mod lbp;
 mod lbp_functions;
 mod lbp_manager;
+mod neighbours;
+mod modem_sim;
 mod network_manager;
+mod nv_data;
+mod path_discovery;
+mod pib;
+mod plc_medium;
+mod psk_store;
+mod ready;
+mod rekey;
+mod short_addr;
 mod ipv6_frag_manager;
 mod request;
+mod routing;
+mod sniffer;
+mod stats;
+mod topology;
 mod usi;
+mod usi_capture;
 mod tun_interface;
+mod transport;
 mod app_manager;
 
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use std::{env, io, str, thread};
 
@@ -31,7 +55,7 @@ extern crate log;
 extern crate env_logger;
 
 use log::Level;
-use clap::{ArgEnum, Parser};
+use clap::{ValueEnum, Parser};
 
 
 use crate::app_config::Mode;
@@ -45,7 +69,7 @@ const TIMER_RESOLUTION: Duration = Duration::from_millis(20000);
 #[clap(author, version, about, long_about = None)]
 struct Cli {
     /// What mode to run the program in
-    #[clap(arg_enum)]
+    #[clap(value_enum)]
     mode: app_config::Mode,
 
     #[clap(short, long)]
@@ -54,8 +78,70 @@ struct Cli {
     #[clap(short, long)]
     speed: Option<u32>,
 
+    /// Appends the USI frames to this capture file
+    #[clap(short, long)]
+    record: Option<String>,
+
     #[clap(short, long, default_value_t = String::from("ne-g3.toml"))]
     config: String,
+
+    /// Runs a simulated modem instead, on `pty` or `tcp:<address>`, options after '?'.
+    /// `pan:pty` or `pan:tcp:<address>` runs a PAN of simulated devices for a coordinator.
+    #[clap(long)]
+    simulate: Option<String>,
+
+    /// Prints the path to this short address (hex) or IPv6 address once the network is started
+    #[clap(long)]
+    trace: Option<path_discovery::PathTarget>,
+
+    /// Prints the routing table of the modem once the network is started
+    #[clap(long)]
+    routes: bool,
+
+    /// Discovers the route to this short address (hex) or IPv6 address, then prints the routing table
+    #[clap(long)]
+    discover_route: Option<path_discovery::PathTarget>,
+
+    /// Max hops of the route discovery
+    #[clap(long, default_value_t = routing::DEFAULT_MAX_HOPS)]
+    max_hops: u8,
+
+    /// Prints the neighbour and POS tables of the modem once the network is started
+    #[clap(long)]
+    neighbours: bool,
+
+    /// Prints the neighbour and POS table entries of this short address (hex) once the network is started
+    #[clap(long, value_parser = neighbours::parse_short_addr)]
+    neighbour: Option<u16>,
+
+    /// Prints the tables of --neighbours or --neighbour as JSON
+    #[clap(long)]
+    json: bool,
+
+    /// Sniffer mode, writes the frames to this pcap file or named pipe
+    #[clap(long)]
+    pcap: Option<String>,
+
+    /// Serves the Prometheus metrics on this address, e.g. 127.0.0.1:9898
+    #[clap(long)]
+    metrics: Option<String>,
+
+    /// Serves the operator commands (kick, rekey) on this Unix socket
+    #[clap(long)]
+    control: Option<String>,
+
+    /// Writes the network topology to this file once the network is started, JSON for a `.json` file,
+    /// GraphViz DOT otherwise, `-` prints it
+    #[clap(long)]
+    topology: Option<String>,
+
+    /// Rewrites the --topology file every this many seconds
+    #[clap(long)]
+    topology_interval: Option<u64>,
+
+    /// Adds a path discovery to every route destination to the --topology
+    #[clap(long)]
+    topology_paths: bool,
     
 
 }
@@ -68,9 +154,21 @@ fn main() {
 
     let cli = Cli::parse();
 
+    if let Some(spec) = cli.simulate {
+        let result = match spec.strip_prefix("pan:") {
+            Some(pan) => plc_medium::run(pan),
+            None => modem_sim::run(&spec),
+        };
+        if let Err(e) = result {
+            log::error!("Simulator {} failed : {}", spec, e);
+        }
+        return;
+    }
+
     match cli.mode {
         app_config::Mode::Coordinator => env::set_var("NEG3_G3.MODE", "0"),
         app_config::Mode::Modem => env::set_var("NEG3_G3.MODE", "1"),
+        app_config::Mode::Sniffer => env::set_var("NEG3_G3.MODE", "2"),
     }
 
     if let Some(device_name) = cli.device {
@@ -79,6 +177,18 @@ fn main() {
     if let Some(device_speed) = cli.speed {
         env::set_var("NEG3_SERIAL.SPEED", device_speed.to_string());
     }
+    if let Some(record) = cli.record {
+        env::set_var("NEG3_SERIAL.RECORD", record);
+    }
+    if let Some(pcap) = cli.pcap {
+        env::set_var("NEG3_G3.PCAP", pcap);
+    }
+    if let Some(metrics) = cli.metrics {
+        env::set_var("NEG3_METRICS.LISTEN", metrics);
+    }
+    if let Some(control) = cli.control {
+        env::set_var("NEG3_CONTROL.SOCKET", control);
+    }
 
     log::trace!("Config file = {}", cli.config);
 
@@ -103,13 +213,11 @@ fn main() {
     let is_coordinator = Mode::try_from_primitive(settings.g3.mode).unwrap() == Mode::Coordinator;
     
 
-    log::info!("Port : {:?}, coordinator {}", settings.serial.name, is_coordinator);
-    let mut port = serialport::new(&(settings.serial.name), settings.serial.speed)
-        .timeout(Duration::from_millis(10))
-        .open()
-        .expect("Failed to open port {}");
-
-    let mut tx_port = port.try_clone().unwrap();
+    let transport: transport::Transport = settings.serial.name.parse().expect("Invalid serial name");
+    log::info!("Port : {}, coordinator {}", transport, is_coordinator);
+    let (port, tx_port) = transport
+        .open_reconnecting(settings.serial.speed)
+        .unwrap_or_else(|e| panic!("Failed to open port {} : {}", transport, e));
     // let (app_tx, app_rx) = flume::unbounded::<Message>();
     // let (usi_tx, usi_rx) = flume::unbounded::<Message>();
     // let (net_tx, net_rx) = flume::unbounded::<adp::Message>();
@@ -119,14 +227,73 @@ fn main() {
     let (app_usi_tx, app_usi_rx) = flume::unbounded::<usi::Message>();
     let mut usi = usi::Port::new(port);
     usi.add_listener(app_usi_tx.clone());
+    if let Some(ref path) = settings.serial.record {
+        match usi_capture::Recorder::create(path) {
+            Ok(recorder) => usi.set_recorder(recorder),
+            Err(e) => log::error!("Failed to open capture file {} : {}", path, e),
+        }
+    }
+    if Mode::try_from_primitive(settings.g3.mode) == Ok(Mode::Sniffer) {
+        match settings.g3.pcap {
+            Some(ref path) => {
+                let (sniffer_tx, sniffer_rx) = flume::unbounded::<usi::Message>();
+                usi.add_listener(sniffer_tx);
+                sniffer::spawn(path.clone(), sniffer_rx);
+            }
+            None => log::warn!("Sniffer mode without pcap, the sniffed frames are only logged"),
+        }
+    }
+    let framing_stats = usi.stats();
     let (tx, rx) = flume::unbounded::<adp::Message>();
     let usi_tx = usi.start(tx_port);
-    let app_manager = AppManager::new(usi_tx.clone(), tx);
+    let adp_client = adp_client::AdpClient::new(usi_tx.clone());
+    let app_manager = AppManager::new(usi_tx.clone(), tx, adp_client.clone());
+    let pan_descriptors = app_manager.pan_descriptors();
     app_manager.start(&settings, app_usi_rx, is_coordinator);
 
    
     
-    let network_manager = network_manager::NetworkManager::new(&settings, usi_tx);
+    let mut network_manager = network_manager::NetworkManager::new(&settings, usi_tx, adp_client.clone());
+    if let Some(ref config) = settings.metrics {
+        let host_stats = network_manager.host_stats();
+        let (device_tx, device_rx) = flume::unbounded();
+        network_manager.add_device_listener(device_tx);
+        host_stats.spawn_device_counter(device_rx);
+        let metrics = Arc::new(stats::Metrics::new(framing_stats, host_stats, network_manager.device_registry()));
+        let interval = config.interval_secs.map_or(stats::DEFAULT_POLL_INTERVAL, Duration::from_secs);
+        stats::spawn_poller(adp_client.clone(), metrics.clone(), interval);
+        match stats::spawn_exporter(&config.listen, metrics) {
+            Ok(addr) => log::info!("Metrics served on http://{}/metrics", addr),
+            Err(e) => log::error!("Failed to serve metrics on {} : {}", config.listen, e),
+        }
+    }
+    if let Some(ref config) = settings.control {
+        match control::spawn(&config.socket, network_manager.command_sender(), network_manager.device_registry()) {
+            Ok(_) => log::info!("Control commands served on {}", config.socket),
+            Err(e) => log::error!("Failed to serve control commands on {} : {}", config.socket, e),
+        }
+    }
+    if let Some(target) = cli.trace {
+        path_discovery::spawn_trace(adp_client.clone(), network_manager.device_registry(), target);
+    }
+    if cli.routes || cli.discover_route.is_some() {
+        routing::spawn_routes(adp_client.clone(), network_manager.device_registry(), cli.discover_route, cli.max_hops);
+    }
+    if cli.neighbours || cli.neighbour.is_some() {
+        neighbours::spawn_neighbours(adp_client.clone(), network_manager.device_registry(), cli.neighbour, cli.json);
+    }
+    if let Some(path) = cli.topology {
+        let interval = cli.topology_interval.map(Duration::from_secs);
+        topology::spawn_topology(
+            adp_client,
+            network_manager.device_registry(),
+            settings.network.clone(),
+            pan_descriptors,
+            path,
+            interval,
+            cli.topology_paths,
+        );
+    }
 
     network_manager.start(&settings, rx);
     log::info!("Network Manager started ...");
@@ -0,0 +1,1010 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adp;
+use crate::adp::{EAdpPibAttribute, EAdpStatus, EMacWrpPibAttribute, TExtendedAddress};
+use crate::common;
+use crate::lbd::{Lbd, LbdState};
+use crate::lbp;
+use crate::lbp_functions::TEapPskKey;
+use crate::transport;
+use crate::usi::{Decoder, InMessage, OutMessage};
+
+/// Period the simulator checks its scheduled confirms and indications with
+const POLL_PERIOD: Duration = Duration::from_millis(10);
+/// Delay of the confirms of requests that go on the medium in a real modem
+const CONFIRM_DELAY: Duration = Duration::from_millis(20);
+/// Time the network start and join take, much shorter than the real ones
+const NETWORK_DELAY: Duration = Duration::from_millis(500);
+/// Time the bootstrap of a join on the medium has to complete
+const JOIN_TIMEOUT: Duration = Duration::from_secs(20);
+/// Time a path or route discovery waits for the reply of its destination
+const PATH_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Valid time of the routes found, in minutes, adpRoutingTableEntryTTL default
+const ROUTE_VALID_TIME: u16 = 360;
+/// Route cost to the coordinator announced by the devices of the PAN in their beacons
+const DEVICE_RC_COORD: u16 = 1;
+/// Max hops of the LBP requests of the bootstrap when ADP_IB_MAX_HOPS was not set
+const DEFAULT_MAX_HOPS: u8 = 8;
+/// The psk of ne-g3.toml
+const DEFAULT_PSK: [u8; 16] = [0xab, 0x10, 0x34, 0x11, 0x45, 0x11, 0x1b, 0xc3, 0xc1, 0x2d, 0xe8, 0xff, 0x11, 0x14, 0x22, 0x04];
+
+/// Options of the simulated modem, `key=value` pairs separated by '&' :
+/// `ext_addr=00:80:E1:FF:FE:00:00:02&short_addr=0002&lqi=200&psk=ab103411...`
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// EUI-64 of the modem
+    pub ext_addr: TExtendedAddress,
+    /// Short address given to the modem when it joins a network
+    pub short_addr: u16,
+    /// Link quality of the discovered PAN and of the data indications, the medium gives its own
+    pub link_quality: u8,
+    /// Key of the bootstrap run by the modem when it joins on the medium
+    pub psk: TEapPskKey,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, 0x01]),
+            short_addr: 0x0001,
+            link_quality: 0xB4,
+            psk: TEapPskKey(DEFAULT_PSK),
+        }
+    }
+}
+
+impl FromStr for SimConfig {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = SimConfig::default();
+        for option in s.split('&').filter(|o| !o.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("invalid simulator option {}", option))?;
+            match key {
+                "ext_addr" => config.ext_addr = value.parse()?,
+                "short_addr" => {
+                    config.short_addr = u16::from_str_radix(value.trim_start_matches("0x"), 16)
+                        .map_err(|_| format!("invalid short address {}", value))?
+                }
+                "lqi" => config.link_quality = value.parse().map_err(|_| format!("invalid lqi {}", value))?,
+                "psk" => {
+                    config.psk = common::from_hex_string(value)
+                        .and_then(|v| v.try_into().ok())
+                        .map(TEapPskKey)
+                        .ok_or_else(|| format!("invalid psk {}", value))?
+                }
+                _ => return Err(format!("unknown simulator option {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Destination of a frame on the medium
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AirAddress {
+    Short(u16),
+    Extended(TExtendedAddress),
+    Broadcast,
+}
+
+#[derive(Debug, Clone)]
+pub enum AirPayload {
+    /// Sent by a discovery, answered by the nodes that are part of a PAN
+    BeaconRequest,
+    Beacon { pan_id: u16, rc_coord: u16 },
+    /// LBP message, secured with the GMK or not as asked by the request
+    Lbp { nsdu: Vec<u8>, secured: bool },
+    /// The IPv6 packet of an ADP data request
+    Data(Vec<u8>),
+    /// Path discovery to the destination of the frame, answered with a PathReply
+    PathRequest { metric_type: u8 },
+    /// Cost of the link the PathRequest came in on
+    PathReply { metric_type: u8, forward_link_cost: u8 },
+    /// Route discovery to the destination of the frame, answered with a RouteReply
+    RouteRequest,
+    /// Cost of the link the RouteRequest came in on
+    RouteReply { forward_link_cost: u8 },
+}
+
+/// What a modem sends on the medium, see plc_medium
+#[derive(Debug, Clone)]
+pub struct AirFrame {
+    /// Short address of the sender, 0xFFFF until it joins
+    pub src: u16,
+    pub src_ext: TExtendedAddress,
+    pub dst: AirAddress,
+    pub payload: AirPayload,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Layer {
+    Adp,
+    Mac,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SimState {
+    /// Waiting for ADP initialize
+    Reset,
+    Initialized,
+    /// Network started, the modem is the coordinator of the PAN
+    Coordinator,
+    Joined,
+}
+
+/// Simulated G3 modem, answers the requests of request.rs as the Microchip ADP serial interface does.
+///
+/// The PIB is a plain store: what is set can be read back, the EUI-64 is read only and attributes never
+/// set are unsupported. The confirms and indications are scheduled, `poll` gives the ones that are due.
+///
+/// Alone, the modem answers the discovery with the PAN of its PIB and a join right away. Once attached
+/// to a medium, discovery, join, data and LBP go through the frames of `take_air_frames` and `receive`:
+/// the join runs the EAP-PSK bootstrap of lbd::Lbd against the coordinator, as the firmware does.
+#[derive(Debug)]
+pub struct ModemSim {
+    config: SimConfig,
+    state: SimState,
+    pib: HashMap<(Layer, u32, u16), Vec<u8>>,
+    outbox: Vec<(Instant, OutMessage)>,
+    on_medium: bool,
+    air: Vec<AirFrame>,
+    discovery_end: Option<Instant>,
+    /// Bootstrap of the join, kept once joined to answer the rekeys and kicks of the coordinator
+    lbd: Option<Lbd>,
+    /// PAN id and deadline of the join in progress
+    join: Option<(u16, Instant)>,
+    /// Destination, metric type and deadline of the path discovery in progress
+    path_discovery: Option<(u16, u8, Instant)>,
+    /// Destination and deadline of the route discovery in progress
+    route_discovery: Option<(u16, Instant)>,
+    /// Short address the LBP frames of each device last came from (its LBA, or itself once joined),
+    /// the coordinator answers through it
+    lbas: HashMap<TExtendedAddress, u16>,
+}
+
+impl ModemSim {
+    pub fn new(config: SimConfig) -> Self {
+        let mut sim = ModemSim {
+            config,
+            state: SimState::Reset,
+            pib: HashMap::new(),
+            outbox: Vec::new(),
+            on_medium: false,
+            air: Vec::new(),
+            discovery_end: None,
+            lbd: None,
+            join: None,
+            path_discovery: None,
+            route_discovery: None,
+            lbas: HashMap::new(),
+        };
+        sim.reset();
+        sim
+    }
+
+    fn reset(&mut self) {
+        self.state = SimState::Reset;
+        self.pib.clear();
+        self.discovery_end = None;
+        self.lbd = None;
+        self.join = None;
+        self.path_discovery = None;
+        self.route_discovery = None;
+        self.lbas.clear();
+        self.pib.insert((Layer::Adp, EAdpPibAttribute::ADP_IB_MANUF_ROUTING_TABLE_COUNT.into(), 0), vec![0, 0]);
+        //The modem gives its EUI-64 least significant byte first
+        let mut ext_addr = self.config.ext_addr.0.to_vec();
+        ext_addr.reverse();
+        self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS, 0, ext_addr);
+        self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, vec![0xFF, 0xFF]);
+        self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, vec![0xFF, 0xFF]);
+    }
+
+    fn set_mac(&mut self, attribute: EMacWrpPibAttribute, idx: u16, value: Vec<u8>) {
+        self.pib.insert((Layer::Mac, attribute.into(), idx), value);
+    }
+
+    pub fn adp_pib(&self, attribute: EAdpPibAttribute, idx: u16) -> Option<&Vec<u8>> {
+        self.pib.get(&(Layer::Adp, attribute.into(), idx))
+    }
+
+    pub fn mac_pib(&self, attribute: EMacWrpPibAttribute, idx: u16) -> Option<&Vec<u8>> {
+        self.pib.get(&(Layer::Mac, attribute.into(), idx))
+    }
+
+    pub fn ext_addr(&self) -> TExtendedAddress {
+        self.config.ext_addr
+    }
+
+    /// MAC short address, 0xFFFF until the modem starts or joins a network
+    pub fn short_addr(&self) -> u16 {
+        match self.mac_pib(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0).map(|v| v.as_slice()) {
+            Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]),
+            _ => 0xFFFF,
+        }
+    }
+
+    /// Has a short address, given by the network start, the join or the bootstrap of the host
+    pub fn in_network(&self) -> bool {
+        self.state != SimState::Reset && self.short_addr() != 0xFFFF
+    }
+
+    /// Sends the data, LBP and discovery requests on a medium instead of answering them alone
+    pub fn attach(&mut self) {
+        self.on_medium = true;
+    }
+
+    /// The frames sent on the medium since the last call
+    pub fn take_air_frames(&mut self) -> Vec<AirFrame> {
+        std::mem::take(&mut self.air)
+    }
+
+    fn transmit(&mut self, dst: AirAddress, payload: AirPayload) {
+        if self.on_medium {
+            self.air.push(AirFrame {
+                src: self.short_addr(),
+                src_ext: self.config.ext_addr,
+                dst,
+                payload,
+            });
+        }
+    }
+
+    fn pan_id(&self) -> Option<u16> {
+        match self.mac_pib(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0).map(|v| v.as_slice()) {
+            Some(&[hi, lo]) if [hi, lo] != [0xFF, 0xFF] => Some(u16::from_be_bytes([hi, lo])),
+            _ => None,
+        }
+    }
+
+    fn send_at(&mut self, at: Instant, data: Vec<u8>) {
+        self.outbox.push((at, OutMessage::new(common::PROTOCOL_ADP_G3, &data)));
+    }
+
+    fn status(&mut self, now: Instant, status: EAdpStatus, cmd: u8) {
+        self.send_at(now, vec![adp::G3_SERIAL_MSG_STATUS, status as u8, cmd]);
+    }
+
+    /// The confirms and indications due at now, in the order they were scheduled
+    pub fn poll(&mut self, now: Instant) -> Vec<OutMessage> {
+        if let Some((pan_id, deadline)) = self.join {
+            if now >= deadline {
+                log::warn!("[SIM] {} bootstrap timed out", self.config.ext_addr);
+                self.lbd = None;
+                self.join_confirm(now, EAdpStatus::G3_TIMEOUT, 0xFFFF, pan_id);
+            }
+        }
+        if let Some((dst_addr, metric_type, deadline)) = self.path_discovery {
+            if now >= deadline {
+                log::warn!("[SIM] No path to {:04X}", dst_addr);
+                self.path_discovery = None;
+                let confirm = path_discovery_confirm(EAdpStatus::G3_TIMEOUT, dst_addr, self.short_addr(), metric_type, &[], &[]);
+                self.send_at(now, confirm);
+            }
+        }
+        if let Some((dst_addr, deadline)) = self.route_discovery {
+            if now >= deadline {
+                log::warn!("[SIM] No route to {:04X}", dst_addr);
+                self.route_discovery = None;
+                self.send_at(now, vec![adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_CONFIRM, EAdpStatus::G3_ROUTE_ERROR as u8]);
+            }
+        }
+        let mut due = Vec::new();
+        let mut i = 0;
+        while i < self.outbox.len() {
+            if self.outbox[i].0 <= now {
+                due.push(self.outbox.remove(i).1);
+            } else {
+                i += 1;
+            }
+        }
+        due
+    }
+
+    /// A frame received from the nodes of the PAN, given to the host as an ADP data indication
+    pub fn indicate_data(&mut self, now: Instant, nsdu: &[u8], link_quality: u8) {
+        let mut v = vec![adp::G3_SERIAL_MSG_ADP_DATA_INDICATION, link_quality];
+        v.extend_from_slice(&(nsdu.len() as u16).to_be_bytes());
+        v.extend_from_slice(nsdu);
+        self.send_at(now, v);
+    }
+
+    pub fn indicate_buffers(&mut self, now: Instant, ready: bool) {
+        self.send_at(now, vec![adp::G3_SERIAL_MSG_ADP_BUFFER_INDICATION, ready as u8]);
+    }
+
+    /// Handles a request from the host
+    pub fn process(&mut self, msg: &InMessage, now: Instant) {
+        if msg.protocol_type != Some(common::PROTOCOL_ADP_G3) {
+            log::warn!("[SIM] Ignoring protocol {:?}", msg.protocol_type);
+            return;
+        }
+        let cmd = match msg.buf.first() {
+            Some(cmd) => common::CMD_PROTOCOL(*cmd),
+            None => return,
+        };
+        let data = &msg.buf[1..];
+        log::debug!("[SIM] Request {} : {}", cmd, common::to_hex_string(data));
+        if self.state == SimState::Reset && cmd != adp::G3_SERIAL_MSG_ADP_INITIALIZE {
+            self.status(now, EAdpStatus::G3_INVALID_REQUEST, cmd);
+            return;
+        }
+        let handled = match cmd {
+            adp::G3_SERIAL_MSG_ADP_INITIALIZE => {
+                self.reset();
+                self.state = SimState::Initialized;
+                self.status(now, EAdpStatus::G3_SUCCESS, cmd);
+                self.indicate_buffers(now, true);
+                true
+            }
+            adp::G3_SERIAL_MSG_ADP_SET_REQUEST => self.set(now, Layer::Adp, data),
+            adp::G3_SERIAL_MSG_ADP_MAC_SET_REQUEST => self.set(now, Layer::Mac, data),
+            adp::G3_SERIAL_MSG_ADP_GET_REQUEST => self.get(now, Layer::Adp, data),
+            adp::G3_SERIAL_MSG_ADP_MAC_GET_REQUEST => self.get(now, Layer::Mac, data),
+            adp::G3_SERIAL_MSG_ADP_NETWORK_START_REQUEST => self.network_start(now, data),
+            adp::G3_SERIAL_MSG_ADP_DISCOVERY_REQUEST => self.discovery(now, data),
+            adp::G3_SERIAL_MSG_ADP_NETWORK_JOIN_REQUEST => self.network_join(now, data),
+            adp::G3_SERIAL_MSG_ADP_DATA_REQUEST => self.data(now, data),
+            adp::G3_SERIAL_MSG_ADP_LBP_REQUEST => self.lbp(now, data),
+            adp::G3_SERIAL_MSG_ADP_PATH_DISCOVERY_REQUEST => self.path_discovery(now, data),
+            adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_REQUEST => self.route_discovery(now, data),
+            adp::G3_SERIAL_MSG_ADP_NETWORK_LEAVE_REQUEST if data.is_empty() => self.network_leave(now),
+            adp::G3_SERIAL_MSG_ADP_RESET_REQUEST if data.is_empty() => {
+                self.reset();
+                self.state = SimState::Initialized;
+                self.send_at(now + CONFIRM_DELAY, vec![adp::G3_SERIAL_MSG_ADP_RESET_CONFIRM, EAdpStatus::G3_SUCCESS as u8]);
+                true
+            }
+            _ => false,
+        };
+        if !handled {
+            log::warn!("[SIM] Invalid request {} : {}", cmd, common::to_hex_string(data));
+            self.status(now, EAdpStatus::G3_INVALID_REQUEST, cmd);
+        }
+    }
+
+    /// attribute id (4), index (2), length (1), value. None when malformed.
+    fn store(&mut self, layer: Layer, data: &[u8]) -> Option<EAdpStatus> {
+        let (attribute_id, idx, value) = match data {
+            [a0, a1, a2, a3, i0, i1, len, value @ ..] if value.len() == *len as usize => {
+                (u32::from_be_bytes([*a0, *a1, *a2, *a3]), u16::from_be_bytes([*i0, *i1]), value.to_vec())
+            }
+            _ => return None,
+        };
+        let read_only = layer == Layer::Mac
+            && attribute_id == u32::from(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS);
+        match read_only {
+            true => Some(EAdpStatus::G3_READ_ONLY),
+            false => {
+                self.pib.insert((layer, attribute_id, idx), value);
+                Some(EAdpStatus::G3_SUCCESS)
+            }
+        }
+    }
+
+    fn set(&mut self, now: Instant, layer: Layer, data: &[u8]) -> bool {
+        let status = match self.store(layer, data) {
+            Some(status) => status,
+            None => return false,
+        };
+        let cmd = match layer {
+            Layer::Adp => adp::G3_SERIAL_MSG_ADP_SET_CONFIRM,
+            Layer::Mac => adp::G3_SERIAL_MSG_ADP_MAC_SET_CONFIRM,
+        };
+        let mut v = vec![cmd, status as u8];
+        v.extend_from_slice(&data[..6]);
+        self.send_at(now, v);
+        true
+    }
+
+    /// attribute id (4), index (2)
+    fn get(&mut self, now: Instant, layer: Layer, data: &[u8]) -> bool {
+        if data.len() != 6 {
+            return false;
+        }
+        let attribute_id = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let idx = u16::from_be_bytes([data[4], data[5]]);
+        let (status, value) = match self.pib.get(&(layer, attribute_id, idx)) {
+            Some(value) => (EAdpStatus::G3_SUCCESS, value.clone()),
+            None => (EAdpStatus::G3_UNSUPPORTED_ATTRIBUTE, Vec::new()),
+        };
+        let cmd = match layer {
+            Layer::Adp => adp::G3_SERIAL_MSG_ADP_GET_CONFIRM,
+            Layer::Mac => adp::G3_SERIAL_MSG_ADP_MAC_GET_CONFIRM,
+        };
+        let mut v = vec![cmd, status as u8];
+        v.extend_from_slice(data);
+        v.push(value.len() as u8);
+        v.extend(value);
+        self.send_at(now, v);
+        true
+    }
+
+    /// PAN id (2)
+    fn network_start(&mut self, now: Instant, data: &[u8]) -> bool {
+        let pan_id = match data {
+            [hi, lo] => vec![*hi, *lo],
+            _ => return false,
+        };
+        let status = match self.state {
+            SimState::Initialized => {
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, pan_id);
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, vec![0x00, 0x00]);
+                self.state = SimState::Coordinator;
+                EAdpStatus::G3_SUCCESS
+            }
+            _ => EAdpStatus::G3_INVALID_REQUEST,
+        };
+        self.send_at(now + NETWORK_DELAY, vec![adp::G3_SERIAL_MSG_ADP_NETWORK_START_CONFIRM, status as u8]);
+        true
+    }
+
+    /// Destination (2), metric type (1). On the medium every node is a neighbour, the path is the direct link.
+    fn path_discovery(&mut self, now: Instant, data: &[u8]) -> bool {
+        let (dst_addr, metric_type) = match data {
+            [hi, lo, metric_type] => (u16::from_be_bytes([*hi, *lo]), *metric_type),
+            _ => return false,
+        };
+        if !self.on_medium || !self.in_network() || self.path_discovery.is_some() {
+            let confirm = path_discovery_confirm(EAdpStatus::G3_INVALID_REQUEST, dst_addr, self.short_addr(), metric_type, &[], &[]);
+            self.send_at(now + CONFIRM_DELAY, confirm);
+            return true;
+        }
+        self.path_discovery = Some((dst_addr, metric_type, now + PATH_DISCOVERY_TIMEOUT));
+        self.transmit(AirAddress::Short(dst_addr), AirPayload::PathRequest { metric_type });
+        true
+    }
+
+    /// Destination (2), max hops (1). The route found is the direct link, added to ADP_IB_ROUTING_TABLE.
+    fn route_discovery(&mut self, now: Instant, data: &[u8]) -> bool {
+        let (dst_addr, max_hops) = match data {
+            [hi, lo, max_hops] => (u16::from_be_bytes([*hi, *lo]), *max_hops),
+            _ => return false,
+        };
+        if !self.on_medium || !self.in_network() || self.route_discovery.is_some() || max_hops == 0 {
+            let confirm = vec![adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_CONFIRM, EAdpStatus::G3_INVALID_REQUEST as u8];
+            self.send_at(now + CONFIRM_DELAY, confirm);
+            return true;
+        }
+        self.route_discovery = Some((dst_addr, now + PATH_DISCOVERY_TIMEOUT));
+        self.transmit(AirAddress::Short(dst_addr), AirPayload::RouteRequest);
+        true
+    }
+
+    /// Replaces the route to the destination of the entry, or adds it after the others
+    fn add_route(&mut self, entry: &adp::TAdpRoutingTableEntry) {
+        let count_key = (Layer::Adp, EAdpPibAttribute::ADP_IB_MANUF_ROUTING_TABLE_COUNT.into(), 0);
+        let count = match self.pib.get(&count_key).map(|v| v.as_slice()) {
+            Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]),
+            _ => 0,
+        };
+        let table: u32 = EAdpPibAttribute::ADP_IB_ROUTING_TABLE.into();
+        let idx = (0..count)
+            .find(|idx| {
+                let route = self.pib.get(&(Layer::Adp, table, *idx));
+                route.and_then(|v| adp::TAdpRoutingTableEntry::try_from(&v[..]).ok()).is_some_and(|r| r.dst_addr == entry.dst_addr)
+            })
+            .unwrap_or(count);
+        self.pib.insert((Layer::Adp, table, idx), entry.into());
+        self.pib.insert(count_key, count.max(idx + 1).to_be_bytes().to_vec());
+    }
+
+    /// Only a device that joined can leave, it keeps its PIB but loses its short address and PAN
+    fn network_leave(&mut self, now: Instant) -> bool {
+        let status = match self.state {
+            SimState::Joined => {
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, vec![0xFF, 0xFF]);
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, vec![0xFF, 0xFF]);
+                self.lbd = None;
+                self.state = SimState::Initialized;
+                EAdpStatus::G3_SUCCESS
+            }
+            _ => EAdpStatus::G3_INVALID_REQUEST,
+        };
+        self.send_at(now + CONFIRM_DELAY, vec![adp::G3_SERIAL_MSG_ADP_NETWORK_LEAVE_CONFIRM, status as u8]);
+        true
+    }
+
+    /// Duration in seconds (1). Alone, the PAN set in the MAC PIB is found, with the coordinator as LBA.
+    /// On the medium, the beacons received until the end of the discovery are indicated.
+    fn discovery(&mut self, now: Instant, data: &[u8]) -> bool {
+        let duration = match data {
+            [duration] => Duration::from_secs(*duration as u64),
+            _ => return false,
+        };
+        if self.on_medium {
+            self.discovery_end = Some(now + duration);
+            self.transmit(AirAddress::Broadcast, AirPayload::BeaconRequest);
+        } else if let Some(pan_id) = self.pan_id() {
+            self.discovery_indication(now + duration / 2, pan_id, self.config.link_quality, 0x0000, 0x0000);
+        }
+        self.send_at(now + duration, vec![adp::G3_SERIAL_MSG_ADP_DISCOVERY_CONFIRM, EAdpStatus::G3_SUCCESS as u8]);
+        true
+    }
+
+    fn discovery_indication(&mut self, at: Instant, pan_id: u16, link_quality: u8, lba: u16, rc_coord: u16) {
+        let mut v = vec![adp::G3_SERIAL_MSG_ADP_DISCOVERY_INDICATION];
+        v.extend_from_slice(&pan_id.to_be_bytes());
+        v.push(link_quality);
+        v.extend_from_slice(&lba.to_be_bytes());
+        v.extend_from_slice(&rc_coord.to_be_bytes());
+        self.send_at(at, v);
+    }
+
+    /// PAN id (2), LBA (2). Alone, the join succeeds with the short address of the configuration.
+    fn network_join(&mut self, now: Instant, data: &[u8]) -> bool {
+        let (pan_id, lba) = match data {
+            [p0, p1, l0, l1] => (u16::from_be_bytes([*p0, *p1]), u16::from_be_bytes([*l0, *l1])),
+            _ => return false,
+        };
+        if self.state != SimState::Initialized || self.join.is_some() {
+            self.send_at(now, join_confirm(EAdpStatus::G3_INVALID_REQUEST, 0xFFFF, pan_id));
+        } else if self.on_medium {
+            let max_hops = match self.adp_pib(EAdpPibAttribute::ADP_IB_MAX_HOPS, 0).map(|v| v.as_slice()) {
+                Some(&[max_hops]) => max_hops,
+                _ => DEFAULT_MAX_HOPS,
+            };
+            let mut lbd = Lbd::new(self.config.ext_addr, self.config.psk.clone(), max_hops);
+            let joining = lbd.start(lba);
+            self.lbd = Some(lbd);
+            self.join = Some((pan_id, now + JOIN_TIMEOUT));
+            self.lbd_requests(vec![joining]);
+        } else {
+            let short_addr = self.config.short_addr;
+            self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, pan_id.to_be_bytes().to_vec());
+            self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, short_addr.to_be_bytes().to_vec());
+            self.state = SimState::Joined;
+            self.send_at(now + NETWORK_DELAY, join_confirm(EAdpStatus::G3_SUCCESS, short_addr, pan_id));
+        }
+        true
+    }
+
+    fn join_confirm(&mut self, now: Instant, status: EAdpStatus, short_addr: u16, pan_id: u16) {
+        self.join = None;
+        self.send_at(now, join_confirm(status, short_addr, pan_id));
+    }
+
+    /// Handle (1), discover route (1), QoS (1), length (2), NSDU
+    fn data(&mut self, now: Instant, data: &[u8]) -> bool {
+        let (handle, nsdu) = match data {
+            [handle, _, _, l0, l1, nsdu @ ..] if nsdu.len() == u16::from_be_bytes([*l0, *l1]) as usize => {
+                (*handle, nsdu.to_vec())
+            }
+            _ => return false,
+        };
+        let status = match self.in_network() {
+            true => {
+                self.transmit(ipv6_destination(&nsdu), AirPayload::Data(nsdu));
+                EAdpStatus::G3_SUCCESS
+            }
+            false => EAdpStatus::G3_INVALID_REQUEST,
+        };
+        self.send_at(now + CONFIRM_DELAY, vec![adp::G3_SERIAL_MSG_ADP_DATA_CONFIRM, status as u8, handle]);
+        true
+    }
+
+    fn lbp(&mut self, now: Instant, data: &[u8]) -> bool {
+        let (handle, dst, secured, nsdu) = match parse_lbp_request(data) {
+            Some(request) => request,
+            None => return false,
+        };
+        let dst = match dst {
+            AirAddress::Extended(ext_addr) => self.lbas.get(&ext_addr).map_or(dst, |lba| AirAddress::Short(*lba)),
+            _ => dst,
+        };
+        self.transmit(dst, AirPayload::Lbp { nsdu, secured });
+        self.send_at(now + CONFIRM_DELAY, vec![adp::G3_SERIAL_MSG_ADP_LBP_CONFIRM, EAdpStatus::G3_SUCCESS as u8, handle]);
+        true
+    }
+
+    /// Applies what the bootstrap gives to the modem: LBP requests are sent, PIB sets stored without confirm
+    fn lbd_requests(&mut self, requests: Vec<OutMessage>) {
+        for request in requests {
+            let data = request.data();
+            match data.first().map(|cmd| common::CMD_PROTOCOL(*cmd)) {
+                Some(adp::G3_SERIAL_MSG_ADP_LBP_REQUEST) => {
+                    if let Some((_, dst, secured, nsdu)) = parse_lbp_request(&data[1..]) {
+                        self.transmit(dst, AirPayload::Lbp { nsdu, secured });
+                    }
+                }
+                Some(adp::G3_SERIAL_MSG_ADP_MAC_SET_REQUEST) => {
+                    self.store(Layer::Mac, &data[1..]);
+                }
+                Some(adp::G3_SERIAL_MSG_ADP_SET_REQUEST) => {
+                    self.store(Layer::Adp, &data[1..]);
+                }
+                _ => log::warn!("[SIM] Dropping bootstrap request {}", common::to_hex_string(data)),
+            }
+        }
+    }
+
+    /// Handles a frame of the medium
+    pub fn receive(&mut self, frame: &AirFrame, link_quality: u8, now: Instant) {
+        if self.state == SimState::Reset {
+            return;
+        }
+        match frame.payload {
+            AirPayload::BeaconRequest => {
+                if let (true, Some(pan_id)) = (self.in_network(), self.pan_id()) {
+                    let rc_coord = match self.state {
+                        SimState::Coordinator => 0,
+                        _ => DEVICE_RC_COORD,
+                    };
+                    self.transmit(AirAddress::Extended(frame.src_ext), AirPayload::Beacon { pan_id, rc_coord });
+                }
+            }
+            AirPayload::Beacon { pan_id, rc_coord } => {
+                if self.discovery_end.is_some_and(|end| now < end) {
+                    self.discovery_indication(now, pan_id, link_quality, frame.src, rc_coord);
+                }
+            }
+            AirPayload::Lbp { ref nsdu, secured } => self.receive_lbp(frame.src, nsdu, secured, link_quality, now),
+            AirPayload::Data(ref nsdu) => {
+                if self.in_network() {
+                    self.indicate_data(now, nsdu, link_quality);
+                }
+            }
+            AirPayload::PathRequest { metric_type } => {
+                if self.in_network() {
+                    let forward_link_cost = link_cost(link_quality);
+                    self.transmit(AirAddress::Short(frame.src), AirPayload::PathReply { metric_type, forward_link_cost });
+                }
+            }
+            AirPayload::PathReply { metric_type, forward_link_cost } => {
+                if let Some((dst_addr, _, _)) = self.path_discovery.filter(|(dst_addr, _, _)| *dst_addr == frame.src) {
+                    self.path_discovery = None;
+                    let forward = [(dst_addr, forward_link_cost)];
+                    let reverse = [(self.short_addr(), link_cost(link_quality))];
+                    let confirm = path_discovery_confirm(EAdpStatus::G3_SUCCESS, dst_addr, self.short_addr(), metric_type, &forward, &reverse);
+                    self.send_at(now, confirm);
+                }
+            }
+            AirPayload::RouteRequest => {
+                if self.in_network() {
+                    let forward_link_cost = link_cost(link_quality);
+                    self.transmit(AirAddress::Short(frame.src), AirPayload::RouteReply { forward_link_cost });
+                }
+            }
+            AirPayload::RouteReply { forward_link_cost } => {
+                if let Some((dst_addr, _)) = self.route_discovery.filter(|(dst_addr, _)| *dst_addr == frame.src) {
+                    self.route_discovery = None;
+                    self.add_route(&adp::TAdpRoutingTableEntry {
+                        dst_addr,
+                        next_hop_addr: dst_addr,
+                        route_cost: forward_link_cost as u16,
+                        hop_count: 1,
+                        weak_link_count: 0,
+                        valid_time: ROUTE_VALID_TIME,
+                    });
+                    self.send_at(now, vec![adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_CONFIRM, EAdpStatus::G3_SUCCESS as u8]);
+                }
+            }
+        }
+    }
+
+    fn receive_lbp(&mut self, src: u16, nsdu: &[u8], secured: bool, link_quality: u8, now: Instant) {
+        let lbd_addr = nsdu.get(2..lbp::LBP_MESSAGE_MIN_LEN);
+        let for_lbd = nsdu.first().is_some_and(|t| t & 0x80 != 0);
+        if self.state != SimState::Coordinator && lbd_addr != Some(&self.config.ext_addr.0[..]) {
+            //LBA of a device joining through this modem, what comes from the device goes to the coordinator
+            //and what comes from the coordinator goes to the device
+            let dst = match (for_lbd, lbd_addr.and_then(|a| TExtendedAddress::try_from(a).ok())) {
+                (false, _) => AirAddress::Short(0x0000),
+                (true, Some(ext_addr)) => AirAddress::Extended(ext_addr),
+                (true, None) => return,
+            };
+            if self.in_network() {
+                self.transmit(dst, AirPayload::Lbp { nsdu: nsdu.to_vec(), secured });
+            }
+            return;
+        }
+        if let (SimState::Coordinator, Some(ext_addr)) = (self.state, lbd_addr.and_then(|a| TExtendedAddress::try_from(a).ok())) {
+            //A device not joined yet sends with no short address and is reached directly
+            match src {
+                0xFFFF => self.lbas.remove(&ext_addr),
+                lba => self.lbas.insert(ext_addr, lba),
+            };
+        }
+        let event = adp::AdpG3LbpEvent {
+            src_addr: src,
+            nsdu: nsdu.to_vec(),
+            link_quality_indicator: link_quality,
+            security_enabled: secured,
+        };
+        let lbd = match self.lbd {
+            Some(ref mut lbd) => lbd,
+            None => {
+                let mut v = vec![adp::G3_SERIAL_MSG_ADP_LBP_INDICATION];
+                v.extend_from_slice(&src.to_be_bytes());
+                v.extend_from_slice(&(nsdu.len() as u16).to_be_bytes());
+                v.extend_from_slice(nsdu);
+                v.extend_from_slice(&[link_quality, secured as u8]);
+                self.send_at(now, v);
+                return;
+            }
+        };
+        let requests = match lbp::adp_message_to_lbp_message(&event) {
+            Some(msg) => lbd.process_msg(&msg),
+            None => return,
+        };
+        let state = lbd.state();
+        self.lbd_requests(requests);
+        match (state, self.join) {
+            (LbdState::Joined, Some((pan_id, _))) => {
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, pan_id.to_be_bytes().to_vec());
+                self.state = SimState::Joined;
+                log::info!("[SIM] {} joined as 0x{:04x}", self.config.ext_addr, self.short_addr());
+                self.join_confirm(now, EAdpStatus::G3_SUCCESS, self.short_addr(), pan_id);
+            }
+            (LbdState::Declined, Some((pan_id, _))) => {
+                self.lbd = None;
+                self.join_confirm(now, EAdpStatus::G3_NOT_PERMITED, 0xFFFF, pan_id);
+            }
+            (LbdState::Kicked, _) => {
+                log::info!("[SIM] {} kicked", self.config.ext_addr);
+                self.lbd = None;
+                self.state = SimState::Initialized;
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0, vec![0xFF, 0xFF]);
+                self.set_mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, vec![0xFF, 0xFF]);
+                self.send_at(now, vec![adp::G3_SERIAL_MSG_ADP_NETWORK_LEAVE_INDICATION]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs the modem on a link until the reader is closed
+    pub fn serve<R: Read + Send + 'static, W: Write>(mut self, reader: R, mut writer: W) -> io::Result<()> {
+        let rx = spawn_reader(reader);
+        loop {
+            match rx.recv_timeout(POLL_PERIOD) {
+                Ok(msg) => self.process(&msg, Instant::now()),
+                Err(flume::RecvTimeoutError::Timeout) => {}
+                Err(flume::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            for out in self.poll(Instant::now()) {
+                if let Some(buf) = out.to_usi() {
+                    writer.write_all(&buf)?;
+                }
+            }
+            writer.flush()?;
+        }
+    }
+}
+
+fn join_confirm(status: EAdpStatus, short_addr: u16, pan_id: u16) -> Vec<u8> {
+    let mut v = vec![adp::G3_SERIAL_MSG_ADP_NETWORK_JOIN_CONFIRM, status as u8];
+    v.extend_from_slice(&short_addr.to_be_bytes());
+    v.extend_from_slice(&pan_id.to_be_bytes());
+    v
+}
+
+/// Status (1), destination (2), expected originator (2), originator (2), metric type (1), forward and reverse
+/// hop counts (1 each), then the hops: address (2), MNS (1), link cost (1)
+fn path_discovery_confirm(status: EAdpStatus, dst_addr: u16, orig_addr: u16, metric_type: u8, forward: &[(u16, u8)], reverse: &[(u16, u8)]) -> Vec<u8> {
+    let mut v = vec![adp::G3_SERIAL_MSG_ADP_PATH_DISCOVERY_CONFIRM, status as u8];
+    v.extend_from_slice(&dst_addr.to_be_bytes());
+    v.extend_from_slice(&orig_addr.to_be_bytes());
+    v.extend_from_slice(&orig_addr.to_be_bytes());
+    v.extend_from_slice(&[metric_type, forward.len() as u8, reverse.len() as u8]);
+    for (hop_address, cost) in forward.iter().chain(reverse) {
+        v.extend_from_slice(&hop_address.to_be_bytes());
+        v.extend_from_slice(&[0, *cost]);
+    }
+    v
+}
+
+/// Cost of a link from the LQI it is received with, the worse the link the higher
+fn link_cost(link_quality: u8) -> u8 {
+    (0xFF - link_quality) / 4
+}
+
+/// Handle (1), max hops (1), discover route (1), QoS (1), security (1), address length (1), NSDU length (2), address, NSDU
+fn parse_lbp_request(data: &[u8]) -> Option<(u8, AirAddress, bool, Vec<u8>)> {
+    match data {
+        [handle, _, _, _, security_enable, addr_len, l0, l1, rest @ ..]
+            if rest.len() == *addr_len as usize + u16::from_be_bytes([*l0, *l1]) as usize =>
+        {
+            let (addr, nsdu) = rest.split_at(*addr_len as usize);
+            let dst = match addr {
+                [0xFF, 0xFF] => AirAddress::Broadcast,
+                [hi, lo] => AirAddress::Short(u16::from_be_bytes([*hi, *lo])),
+                _ => AirAddress::Extended(TExtendedAddress::try_from(addr).ok()?),
+            };
+            Some((*handle, dst, *security_enable != 0, nsdu.to_vec()))
+        }
+        _ => None,
+    }
+}
+
+/// The addresses of the PAN end with the short address, multicast goes to every node
+fn ipv6_destination(packet: &[u8]) -> AirAddress {
+    match packet.get(24..40) {
+        Some(dst) if packet[0] >> 4 == 6 && dst[0] != 0xFF => AirAddress::Short(u16::from_be_bytes([dst[14], dst[15]])),
+        _ => AirAddress::Broadcast,
+    }
+}
+
+/// Decodes the requests of the host in a thread, the channel is closed with the link
+pub(crate) fn spawn_reader<R: Read + Send + 'static>(mut reader: R) -> flume::Receiver<InMessage> {
+    let (tx, rx) = flume::unbounded::<InMessage>();
+    thread::spawn(move || {
+        let mut decoder = Decoder::new();
+        let mut b = [0; 4096];
+        loop {
+            match reader.read(&mut b) {
+                Ok(0) => break,
+                Ok(t) => {
+                    for msg in decoder.decode(&b[..t]) {
+                        if tx.send(msg).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => {}
+                Err(e) => {
+                    log::info!("[SIM] Link closed : {}", e);
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Creates a pseudo terminal in raw mode, the application opens the returned path
+fn open_pty() -> io::Result<(File, File, String)> {
+    use std::os::unix::io::FromRawFd;
+    let mut master = 0;
+    let mut slave = 0;
+    let mut name = [0 as libc::c_char; 128];
+    unsafe {
+        if libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), std::ptr::null_mut(), std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Err(e) = transport::make_raw(slave) {
+            log::warn!("[SIM] Failed to set the pty raw : {}", e);
+        }
+        let name = std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+        Ok((File::from_raw_fd(master), File::from_raw_fd(slave), name))
+    }
+}
+
+/// Gives the host links of `pty` or `tcp:<listen address>` to serve, one at a time
+pub(crate) fn listen<F>(endpoint: &str, mut serve: F) -> io::Result<()>
+where
+    F: FnMut(Box<dyn Read + Send>, Box<dyn Write>) -> io::Result<()>,
+{
+    if endpoint == "pty" {
+        let (master, _slave, name) = open_pty()?;
+        log::info!("[SIM] Listening on pty:{}", name);
+        let reader = master.try_clone()?;
+        serve(Box::new(reader), Box::new(master))
+    } else if let Some(addr) = endpoint.strip_prefix("tcp:") {
+        let listener = TcpListener::bind(addr)?;
+        log::info!("[SIM] Listening on tcp://{}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            log::info!("[SIM] Connection from {}", stream.peer_addr()?);
+            if let Err(e) = serve(Box::new(stream.try_clone()?), Box::new(stream)) {
+                log::warn!("[SIM] Connection closed : {}", e);
+            }
+        }
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid simulator endpoint {}", endpoint)))
+    }
+}
+
+/// Runs a simulated modem on `pty` or `tcp:<listen address>`, followed by the options of SimConfig after '?'
+pub fn run(spec: &str) -> io::Result<()> {
+    let (endpoint, options) = spec.split_once('?').unwrap_or((spec, ""));
+    let config: SimConfig = options
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    log::info!("[SIM] Simulated modem {:?}", config);
+    //Each connection is a modem that was just powered up
+    listen(endpoint, |reader, writer| ModemSim::new(config.clone()).serve(reader, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request;
+
+    /// Through the USI encoding and the parsing of the application
+    fn poll(sim: &mut ModemSim, at: Instant) -> Vec<adp::Message> {
+        let mut decoder = Decoder::new();
+        sim.poll(at)
+            .iter()
+            .flat_map(|out| decoder.decode(&out.to_usi().unwrap()))
+            .filter_map(|msg| adp::usi_message_to_message(&msg))
+            .collect()
+    }
+
+    fn request(sim: &mut ModemSim, out: OutMessage, now: Instant) {
+        let mut decoder = Decoder::new();
+        for msg in decoder.decode(&out.to_usi().unwrap()) {
+            sim.process(&msg, now);
+        }
+    }
+
+    #[test]
+    fn pib_set_and_get() {
+        let mut sim = ModemSim::new(SimConfig::default());
+        let now = Instant::now();
+        request(&mut sim, request::AdpGetRequest::new(EAdpPibAttribute::ADP_IB_MAX_HOPS, 0).into(), now);
+        assert!(matches!(poll(&mut sim, now)[..], [adp::Message::AdpG3MsgStatusResponse(ref r)] if r.status == EAdpStatus::G3_INVALID_REQUEST));
+
+        request(&mut sim, request::AdpInitializeRequest::new(0).into(), now);
+        let v = vec![0x0A];
+        request(&mut sim, request::AdpSetRequest::new(EAdpPibAttribute::ADP_IB_MAX_HOPS, 0, &v).into(), now);
+        request(&mut sim, request::AdpGetRequest::new(EAdpPibAttribute::ADP_IB_MAX_HOPS, 0).into(), now);
+        request(&mut sim, request::AdpGetRequest::new(EAdpPibAttribute::ADP_IB_MAX_HOPS, 1).into(), now);
+        request(&mut sim, request::AdpMacGetRequest::new(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS, 0).into(), now);
+        match &poll(&mut sim, now)[..] {
+            [adp::Message::AdpG3MsgStatusResponse(init), adp::Message::AdpG3BufferEvent(buffers), adp::Message::AdpG3SetResponse(set), adp::Message::AdpG3GetResponse(get), adp::Message::AdpG3GetResponse(unset), adp::Message::AdpG3GetMacResponse(ext_addr)] =>
+            {
+                assert_eq!(init.status, EAdpStatus::G3_SUCCESS);
+                assert_eq!(init.cmd, adp::G3_SERIAL_MSG_ADP_INITIALIZE);
+                assert!(buffers.buffer_ready);
+                assert_eq!(set.status, EAdpStatus::G3_SUCCESS);
+                assert_eq!(get.attribute_val, vec![0x0A]);
+                assert_eq!(unset.status, EAdpStatus::G3_UNSUPPORTED_ATTRIBUTE);
+                assert_eq!(ext_addr.attribute_val, vec![0x01, 0x00, 0x00, 0xFE, 0xFF, 0xE1, 0x80, 0x00]);
+            }
+            other => panic!("unexpected confirms {:?}", other),
+        }
+    }
+
+    #[test]
+    fn discover_and_join() {
+        let config: SimConfig = "short_addr=0005".parse().unwrap();
+        let mut sim = ModemSim::new(config);
+        let now = Instant::now();
+        request(&mut sim, request::AdpInitializeRequest::new(0).into(), now);
+        let pan_id = vec![0x78, 0x1D];
+        request(&mut sim, request::AdpMacSetRequest::new(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID, 0, &pan_id).into(), now);
+        poll(&mut sim, now);
+
+        request(&mut sim, request::AdpDiscoveryRequest::new(2).into(), now);
+        assert!(poll(&mut sim, now).is_empty());
+        match &poll(&mut sim, now + Duration::from_secs(2))[..] {
+            [adp::Message::AdpG3DiscoveryEvent(event), adp::Message::AdpG3DiscoveryResponse(response)] => {
+                assert_eq!(event.pan_descriptor.pan_id, 0x781D);
+                assert_eq!(response.status, EAdpStatus::G3_SUCCESS);
+            }
+            other => panic!("unexpected discovery {:?}", other),
+        }
+
+        request(&mut sim, request::AdpJoinNetworkRequest { pan_id: 0x781D, lba_address: 0 }.into(), now);
+        let data = vec![0x60, 0x00];
+        request(&mut sim, request::AdpDataRequest::new(7, &data, true, 0).into(), now + NETWORK_DELAY);
+        match &poll(&mut sim, now + NETWORK_DELAY + CONFIRM_DELAY)[..] {
+            [adp::Message::AdpG3NetworkJoinResponse(join), adp::Message::AdpG3DataResponse(data)] => {
+                assert_eq!(join.status, EAdpStatus::G3_SUCCESS);
+                assert_eq!(join.network_addr, 0x0005);
+                assert_eq!(join.pan_id, 0x781D);
+                assert_eq!(data.status, EAdpStatus::G3_SUCCESS);
+                assert_eq!(data.nsdu_handle, 7);
+            }
+            other => panic!("unexpected join {:?}", other),
+        }
+
+        let now = now + NETWORK_DELAY + CONFIRM_DELAY;
+        request(&mut sim, request::AdpNetworkLeaveRequest::new().into(), now);
+        match &poll(&mut sim, now + CONFIRM_DELAY)[..] {
+            [adp::Message::AdpG3NetworkLeaveResponse(leave)] => assert_eq!(leave.status, EAdpStatus::G3_SUCCESS),
+            other => panic!("unexpected leave {:?}", other),
+        }
+        assert!(!sim.in_network());
+    }
+}
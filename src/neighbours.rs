@@ -0,0 +1,305 @@
+use std::fmt;
+use std::thread;
+
+use serde_derive::Serialize;
+
+use crate::adp::{EMacWrpPibAttribute, TExtendedAddress};
+use crate::adp_client::{AdpClient, PibError};
+use crate::device_registry::DeviceRegistry;
+use crate::path_discovery::PathTarget;
+use crate::pib::{PibValue, TMacNeighbourEntry, TMacPosEntry};
+use crate::ready;
+
+/// Name of the modulation of a neighbour entry. The type is 0 ROBO, 1 BPSK, 2 QPSK, 3 8PSK, 4 16QAM,
+/// the scheme 0 differential or 1 coherent.
+pub fn modulation(modulation_type: u8, modulation_scheme: u8) -> &'static str {
+    match (modulation_type, modulation_scheme) {
+        (0, _) => "ROBO",
+        (1, 0) => "DBPSK",
+        (1, _) => "BPSK",
+        (2, 0) => "DQPSK",
+        (2, _) => "QPSK",
+        (3, 0) => "D8PSK",
+        (3, _) => "8PSK",
+        (4, _) => "16QAM",
+        _ => "unknown",
+    }
+}
+
+fn mac_get(client: &AdpClient, attribute: EMacWrpPibAttribute, idx: u16) -> Result<PibValue, PibError> {
+    client
+        .mac_get(attribute, idx)?
+        .wait()?
+        .value()
+        .map_err(PibError::InvalidValue)
+}
+
+fn read_count(client: &AdpClient, attribute: EMacWrpPibAttribute) -> Result<u16, PibError> {
+    match mac_get(client, attribute, 0)? {
+        PibValue::U16(count) => Ok(count),
+        other => Err(PibError::InvalidValue(format!("invalid {:?} {:?}", attribute, other))),
+    }
+}
+
+/// Reads the MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_COUNT entries of MAC_WRP_PIB_NEIGHBOUR_TABLE, one get at a time
+pub fn read_neighbour_table(client: &AdpClient) -> Result<Vec<TMacNeighbourEntry>, PibError> {
+    let count = read_count(client, EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_COUNT)?;
+    (0..count)
+        .map(|idx| match mac_get(client, EMacWrpPibAttribute::MAC_WRP_PIB_NEIGHBOUR_TABLE, idx)? {
+            PibValue::Neighbour(entry) => Ok(entry),
+            other => Err(PibError::InvalidValue(format!("invalid neighbour table entry {:?}", other))),
+        })
+        .collect()
+}
+
+/// The neighbour table entry of one neighbour, MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_ELEMENT is indexed by short address
+pub fn read_neighbour(client: &AdpClient, short_addr: u16) -> Result<TMacNeighbourEntry, PibError> {
+    match mac_get(client, EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_ELEMENT, short_addr)? {
+        PibValue::Neighbour(entry) => Ok(entry),
+        other => Err(PibError::InvalidValue(format!("invalid neighbour table element {:?}", other))),
+    }
+}
+
+/// Reads the MAC_WRP_PIB_MANUF_POS_TABLE_COUNT entries of MAC_WRP_PIB_POS_TABLE, one get at a time
+pub fn read_pos_table(client: &AdpClient) -> Result<Vec<TMacPosEntry>, PibError> {
+    let count = read_count(client, EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_POS_TABLE_COUNT)?;
+    (0..count)
+        .map(|idx| match mac_get(client, EMacWrpPibAttribute::MAC_WRP_PIB_POS_TABLE, idx)? {
+            PibValue::Pos(entry) => Ok(entry),
+            other => Err(PibError::InvalidValue(format!("invalid POS table entry {:?}", other))),
+        })
+        .collect()
+}
+
+/// Reads the neighbour and POS tables, only the entries of the neighbour at short_addr if any
+pub fn read_tables(client: &AdpClient, short_addr: Option<u16>) -> Result<(Vec<TMacNeighbourEntry>, Vec<TMacPosEntry>), PibError> {
+    match short_addr {
+        None => Ok((read_neighbour_table(client)?, read_pos_table(client)?)),
+        Some(short_addr) => {
+            let neighbour = read_neighbour(client, short_addr)?;
+            let pos = read_pos_table(client)?.into_iter().filter(|entry| entry.short_addr == short_addr).collect();
+            Ok((vec![neighbour], pos))
+        }
+    }
+}
+
+/// Short address (hex) of `--neighbour`
+pub fn parse_short_addr(s: &str) -> Result<u16, String> {
+    match s.parse()? {
+        PathTarget::Short(short_addr) => Ok(short_addr),
+        PathTarget::Ipv6(_) => Err(format!("{} is not a short address", s)),
+    }
+}
+
+#[derive(Serialize)]
+struct NeighbourView<'a> {
+    #[serde(flatten)]
+    entry: &'a TMacNeighbourEntry,
+    modulation: &'static str,
+    /// Groups of carriers in use, set bits of the tone map
+    tone_groups: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ext_addr: Option<TExtendedAddress>,
+}
+
+#[derive(Serialize)]
+struct PosView<'a> {
+    #[serde(flatten)]
+    entry: &'a TMacPosEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ext_addr: Option<TExtendedAddress>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    neighbours: Vec<NeighbourView<'a>>,
+    pos: Vec<PosView<'a>>,
+}
+
+/// The neighbour and POS tables printed one neighbour per line, or as JSON. The neighbours known to the
+/// registry are given with their EUI-64.
+pub struct NeighbourTables<'a> {
+    neighbours: &'a [TMacNeighbourEntry],
+    pos: &'a [TMacPosEntry],
+    registry: &'a DeviceRegistry,
+}
+
+impl<'a> NeighbourTables<'a> {
+    pub fn new(neighbours: &'a [TMacNeighbourEntry], pos: &'a [TMacPosEntry], registry: &'a DeviceRegistry) -> Self {
+        NeighbourTables { neighbours, pos, registry }
+    }
+
+    fn ext_addr(&self, short_addr: u16) -> Option<TExtendedAddress> {
+        self.registry.get_by_short_addr(short_addr).map(|device| device.ext_addr)
+    }
+
+    pub fn to_json(&self) -> String {
+        let report = Report {
+            neighbours: self
+                .neighbours
+                .iter()
+                .map(|entry| NeighbourView {
+                    entry,
+                    modulation: modulation(entry.modulation_type, entry.modulation_scheme),
+                    tone_groups: tone_groups(&entry.tone_map),
+                    ext_addr: self.ext_addr(entry.short_addr),
+                })
+                .collect(),
+            pos: self.pos.iter().map(|entry| PosView { entry, ext_addr: self.ext_addr(entry.short_addr) }).collect(),
+        };
+        serde_json::to_string_pretty(&report).unwrap_or_default()
+    }
+}
+
+fn tone_groups(tone_map: &[u8; 3]) -> u32 {
+    tone_map.iter().map(|b| b.count_ones()).sum()
+}
+
+impl fmt::Display for NeighbourTables<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "neighbour table, {} neighbours", self.neighbours.len())?;
+        writeln!(f, "addr  modulation  tone map  groups  tx gain  tx res  lqi  tmr valid  valid (min)")?;
+        for n in self.neighbours {
+            write!(
+                f,
+                "{:04X}  {:<10}  {:02X}{:02X}{:02X}    {:>6}  {:>7}  {:>6}  {:>3}  {:>9}  {:>11}",
+                n.short_addr,
+                modulation(n.modulation_type, n.modulation_scheme),
+                n.tone_map[0],
+                n.tone_map[1],
+                n.tone_map[2],
+                tone_groups(&n.tone_map),
+                n.tx_gain,
+                n.tx_res,
+                n.lqi,
+                n.tmr_valid_time,
+                n.neighbour_valid_time
+            )?;
+            if let Some(ext_addr) = self.ext_addr(n.short_addr) {
+                write!(f, "  {}", ext_addr)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "POS table, {} neighbours", self.pos.len())?;
+        writeln!(f, "addr  lqi  valid (min)")?;
+        for p in self.pos {
+            write!(f, "{:04X}  {:>3}  {:>11}", p.short_addr, p.lqi, p.pos_valid_time)?;
+            if let Some(ext_addr) = self.ext_addr(p.short_addr) {
+                write!(f, "  {}", ext_addr)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the neighbour and POS tables of `--neighbours`, or the entries of the `--neighbour` short_addr,
+/// once the network is started
+pub fn spawn_neighbours(client: AdpClient, registry: DeviceRegistry, short_addr: Option<u16>, json: bool) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let tables = || read_tables(&client, short_addr);
+        match ready::when_ready("neighbour tables", tables, ready::pib_not_ready) {
+            Some(Ok((neighbours, pos))) => {
+                let tables = NeighbourTables::new(&neighbours, &pos, &registry);
+                if json {
+                    println!("{}", tables.to_json());
+                } else {
+                    println!("{}", tables);
+                }
+            }
+            Some(Err(e)) => println!("neighbour tables : {}", e),
+            None => println!("neighbour tables : network not ready"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp_client;
+    use crate::pib::PibAttribute;
+
+    fn neighbour(short_addr: u16, modulation_type: u8, tone_map: [u8; 3], lqi: u8) -> TMacNeighbourEntry {
+        TMacNeighbourEntry {
+            short_addr,
+            tone_map,
+            modulation_type,
+            tx_gain: 3,
+            tx_res: 0,
+            tx_coef: [0; 6],
+            modulation_scheme: 0,
+            phase_differential: 0,
+            lqi,
+            tmr_valid_time: 2,
+            neighbour_valid_time: 255,
+        }
+    }
+
+    #[test]
+    fn read_tables() {
+        let neighbours = vec![neighbour(2, 0, [0x3F, 0, 0], 0x30), neighbour(5, 1, [0x3F, 0, 0], 0xB4)];
+        let pos = vec![
+            TMacPosEntry { short_addr: 2, lqi: 0x30, pos_valid_time: 250 },
+            TMacPosEntry { short_addr: 7, lqi: 0x20, pos_valid_time: 100 },
+        ];
+        let (entries, pos_entries) = (neighbours.clone(), pos.clone());
+        let (client, modem) = adp_client::fake_modem(neighbours.len() + pos.len() + 2, move |attribute, idx| {
+            let value = match attribute {
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_COUNT) => PibValue::U16(entries.len() as u16),
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_POS_TABLE_COUNT) => PibValue::U16(pos_entries.len() as u16),
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_NEIGHBOUR_TABLE) => PibValue::Neighbour(entries[idx as usize].clone()),
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_POS_TABLE) => PibValue::Pos(pos_entries[idx as usize].clone()),
+                other => panic!("unexpected get {:?}", other),
+            };
+            Ok(value.encode())
+        });
+        assert_eq!(read_neighbour_table(&client).unwrap(), neighbours);
+        assert_eq!(read_pos_table(&client).unwrap(), pos);
+        modem.join().unwrap();
+
+        let registry = DeviceRegistry::new([0; 6]);
+        let tables = NeighbourTables::new(&neighbours, &pos, &registry);
+        let printed = tables.to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "neighbour table, 2 neighbours",
+                "addr  modulation  tone map  groups  tx gain  tx res  lqi  tmr valid  valid (min)",
+                "0002  ROBO        3F0000         6        3       0   48          2          255",
+                "0005  DBPSK       3F0000         6        3       0  180          2          255",
+                "POS table, 2 neighbours",
+                "addr  lqi  valid (min)",
+                "0002   48          250",
+                "0007   32          100",
+            ]
+        );
+        let json: serde_json::Value = serde_json::from_str(&tables.to_json()).unwrap();
+        assert_eq!(json["neighbours"][0]["modulation"], "ROBO");
+        assert_eq!(json["neighbours"][1]["lqi"], 180);
+        assert_eq!(json["neighbours"][1]["tone_groups"], 6);
+        assert_eq!(json["pos"][1]["short_addr"], 7);
+    }
+    #[test]
+    fn read_one_neighbour() {
+        let neighbour = neighbour(5, 1, [0x3F, 0, 0], 0xB4);
+        let pos = vec![
+            TMacPosEntry { short_addr: 2, lqi: 0x30, pos_valid_time: 250 },
+            TMacPosEntry { short_addr: 5, lqi: 0xB0, pos_valid_time: 100 },
+        ];
+        let (entry, pos_entries) = (neighbour.clone(), pos.clone());
+        let (client, modem) = adp_client::fake_modem(pos.len() + 2, move |attribute, idx| {
+            let value = match attribute {
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_ELEMENT) if idx == 5 => PibValue::Neighbour(entry.clone()),
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_POS_TABLE_COUNT) => PibValue::U16(pos_entries.len() as u16),
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_POS_TABLE) => PibValue::Pos(pos_entries[idx as usize].clone()),
+                other => panic!("unexpected get {:?} {}", other, idx),
+            };
+            Ok(value.encode())
+        });
+        assert_eq!(super::read_tables(&client, Some(5)).unwrap(), (vec![neighbour], vec![pos[1].clone()]));
+        modem.join().unwrap();
+        assert_eq!(parse_short_addr("0x0005"), Ok(5));
+        assert!(parse_short_addr("fe80::1").is_err());
+    }
+}
@@ -6,7 +6,7 @@ use std::{
     process::Command,
     sync::{atomic::AtomicBool, Arc},
     thread::{self, sleep, sleep_ms},
-    time::Duration,
+    time::{Duration, Instant},
     vec,
 };
 
@@ -18,7 +18,7 @@ use pnet_packet::{
     Packet,
 };
 
-use crate::{app_config, tun_interface::TunInterface, adp::{TExtendedAddress, EAdpPibAttribute}, request::AdpSetRequest, lbp_manager, lbp};
+use crate::{app_config, tun_interface::TunInterface, adp::{TAddress, TExtendedAddress, EAdpPibAttribute}, request::AdpSetRequest, lbp_manager, lbp};
 use std::sync::atomic::Ordering;
 use crate::ipv6_frag_manager;
 use crate::request;
@@ -26,17 +26,24 @@ use crate::request;
 
 use crate::{
     adp::{self, EAdpStatus},
-    request::AdpDataRequest,
     usi,
 };
 
-use rand::Rng;
+use crate::device_registry::DeviceRegistry;
+use crate::adp_client::{AdpClient, PendingConfirm};
+use crate::pib::PibValue;
+use crate::nv_data::NvDataFile;
+use crate::stats::HostStats;
+use num_enum::TryFromPrimitive;
 
 enum PacketProtocol {
     IPv4,
     IPv6,
     Other(u8),
 }
+
+//Period of the bootstrap slot timeout check
+const BOOTSTRAP_TIMER: Duration = Duration::from_secs(1);
 fn infer_proto(buf: &[u8]) -> PacketProtocol {
     match buf[0] >> 4 {
         4 => PacketProtocol::IPv4,
@@ -64,12 +71,14 @@ enum TunPayload {
 
 struct TunDevice {    
     listener: flume::Sender<TunPayload>,
+    stats: Arc<HostStats>,
 }
 
 impl TunDevice {
-    pub fn new(listener: flume::Sender<TunPayload>) -> Self {
+    pub fn new(listener: flume::Sender<TunPayload>, stats: Arc<HostStats>) -> Self {
         TunDevice {           
             listener,
+            stats,
         }
     }
 
@@ -143,6 +152,7 @@ impl TunDevice {
         let iface_writer = iface.clone();
         let iface_reader = iface.clone();
 
+        let stats = self.stats.clone();
         #[cfg(target_os = "linux")]
         let skip = 0usize;
         #[cfg(target_os = "macos")]
@@ -167,7 +177,9 @@ impl TunDevice {
                                         log::trace!("--> tun {:?}", buf);
                                         match self.listener.send(
                                             TunPayload::Data(buf[skip..size].to_vec())) {
-                                            Ok(_) => {}
+                                            Ok(_) => {
+                                                self.stats.tun_rx_packets.fetch_add(1, Ordering::Relaxed);
+                                            }
                                             Err(e) => {
                                                 log::warn!(
                                                     "failed to send TunMessage to listener {}",
@@ -198,9 +210,11 @@ impl TunDevice {
                                 log::debug!("TUN interface sending Packet {:?}", data);
                                 match iface_writer.send(&data) {
                                     Ok(size) => {
+                                        stats.tun_tx_packets.fetch_add(1, Ordering::Relaxed);
                                         log::info!("TUN interface wrote {} bytes", size)
                                     }
                                     Err(e) => {
+                                        stats.tun_tx_errors.fetch_add(1, Ordering::Relaxed);
                                         log::warn!("TUN interface failed to write data : {}", e)
                                     }
                                 }
@@ -221,10 +235,28 @@ impl TunDevice {
     }
 }
 
+/// Requests handled by the network manager thread
+#[derive(Debug)]
+pub enum NetworkCommand {
+    /// Forces a device out of the network, by EUI-64 or short address
+    Kick(TAddress),
+    /// Distributes this GMK to the devices then activates it
+    Rekey(Vec<u8>),
+}
+
+/// Outcome of a command, sent back to the one that gave it
+pub type CommandReply = flume::Sender<Result<(), String>>;
+
 pub struct NetworkManager {    
     cmd_tx: flume::Sender<usi::Message>,    
     buffers_available: Arc<AtomicBool>,
-    tun_tx: Option<flume::Sender<TunPayload>>
+    tun_tx: Option<flume::Sender<TunPayload>>,
+    command_tx: flume::Sender<(NetworkCommand, CommandReply)>,
+    command_rx: flume::Receiver<(NetworkCommand, CommandReply)>,
+    device_listeners: Vec<flume::Sender<lbp_manager::DeviceEvent>>,
+    device_registry: DeviceRegistry,
+    adp_client: AdpClient,
+    host_stats: Arc<HostStats>,
 }
 /*
 By design, the G3-PLC protocol stack allows native support of the IPv6 protocol, which grants end-user flexibility to fulfil business requirements when choosing the appropriate higher layers (ISO/OSI transport and application layers). This key feature also secures G3-PLC infrastructures in the long term, thanks to the scalability and future application compatibility provided by IPv6.
@@ -242,13 +274,35 @@ fe80:0000:0000:0000 (hexadecimal representation)
 
 */
 impl <'a> NetworkManager {
-    pub fn new(settings: &'a app_config::Settings, cmd_tx: flume::Sender<usi::Message>) -> Self {
+    pub fn new(settings: &'a app_config::Settings, cmd_tx: flume::Sender<usi::Message>, adp_client: AdpClient) -> Self {
+        let (command_tx, command_rx) = flume::unbounded::<(NetworkCommand, CommandReply)>();
         NetworkManager { 
             buffers_available: Arc::new(AtomicBool::new(true)),           
             cmd_tx: cmd_tx,                        
-            tun_tx: None
+            tun_tx: None,
+            command_tx,
+            command_rx,
+            device_listeners: Vec::new(),
+            device_registry: DeviceRegistry::new(settings.network.ula_host_prefix),
+            adp_client,
+            host_stats: Arc::new(HostStats::default()),
         }
     }
+    pub fn command_sender(&self) -> flume::Sender<(NetworkCommand, CommandReply)> {
+        self.command_tx.clone()
+    }
+    /// The devices known to the coordinator, can be queried from any thread
+    pub fn device_registry(&self) -> DeviceRegistry {
+        self.device_registry.clone()
+    }
+    /// TUN and bootstrap counters, see stats
+    pub fn host_stats(&self) -> Arc<HostStats> {
+        self.host_stats.clone()
+    }
+    /// Receives the joins, leaves and kicks of the devices, coordinator only
+    pub fn add_device_listener(&mut self, listener: flume::Sender<lbp_manager::DeviceEvent>) {
+        self.device_listeners.push(listener);
+    }
     pub fn ipv6_is_unicast_link_local(addr: &Ipv6Addr) -> bool {
         (addr.segments()[0] & 0xffc0) == 0xfe80
     }
@@ -330,9 +384,25 @@ impl <'a> NetworkManager {
 
         thread::spawn(move || {
 
+            let is_coordinator = app_config::Mode::try_from_primitive(settings.g3.mode).map_or(false, |m| m == app_config::Mode::Coordinator);
             let mut lbp_manager = lbp_manager::LbpManager::new(&settings.g3);
-            let mut current_short_dst_addr:Option<u16> = None;
-            let mut current_out_msg : Option<Vec<u8>> = None;
+            for listener in self.device_listeners.drain(..) {
+                lbp_manager.add_listener(listener);
+            }
+            lbp_manager.set_registry(self.device_registry.clone());
+            let mut coord_short_addr_confirm = None;
+            let mut nv_data_file = settings.g3.nv_data_file.as_ref().and_then(|path| match NvDataFile::open(path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    log::error!("Failed to open non-volatile data {} : {}, it will not be persisted", path, e);
+                    None
+                }
+            });
+            //The datagram tag is not part of the indication, it is read before the data is stored
+            let mut nv_data_confirm = None;
+            let mut bootstrap_timer = Instant::now();
+            //Data requests of the TUN packets waiting for their confirm
+            let mut data_confirms: Vec<PendingConfirm<adp::AdpG3DataResponse>> = Vec::new();
 
             loop {
                 match rx.try_recv() {
@@ -364,29 +434,6 @@ impl <'a> NetworkManager {
                             // Obviously a lot of work has to be done in coordinating the multiple device/coordinators operating in concert 
                             // (not sure if this is possible in the current G3 PLC standard or a limitation in Microship's stack implementation).
                             // more layers for distributed database has to be added.
-                            adp::Message::AdpG3GetResponse(response) =>{
-                                if let Ok(attr) = adp::EAdpPibAttribute::try_from(response.attribute_id) {
-                                    match attr {
-                                        adp::EAdpPibAttribute::ADP_IB_COORD_SHORT_ADDRESS => {
-                                            let v = response.attribute_val;
-                                            if v.len() == 2 {
-                                                let coord_short_addr = u16::from_be_bytes([v[0], v[1]]);
-                                                let tun_device = TunDevice::new (tun_tx.clone());
-                                                let (tx, mut rx) = flume::unbounded::<TunPayload>();
-                                                lbp_manager.set_short_addr(coord_short_addr);
-                                                self.tun_tx = Some(tx);
-                                                
-                                                tun_device.start(self.buffers_available.clone(), &settings, 
-                                                    coord_short_addr, rx, &extended_addr);
-                                            }
-                                        }
-                                        _ => {
-
-                                        }
-                                    }
-                                }                                
-                            }
-                        
                             adp::Message::AdpG3NetworkStartResponse(network_start_response) => {
                                 if network_start_response.status == EAdpStatus::G3_SUCCESS {
                                     
@@ -396,10 +443,9 @@ impl <'a> NetworkManager {
                                             "Received network start for device already started"
                                         );
                                     } else {
-                                        let request = request::AdpGetRequest::new (adp::EAdpPibAttribute::ADP_IB_COORD_SHORT_ADDRESS, 0);
-                                        match self.cmd_tx.send(usi::Message::UsiOut(request.into())) {
-                                            Ok(_) => {log::info!("Send to usi ")},
-                                            Err(e) => {log::warn!("Failed to send to usi {}", e)},
+                                        match self.adp_client.get(adp::EAdpPibAttribute::ADP_IB_COORD_SHORT_ADDRESS, 0) {
+                                            Ok(confirm) => coord_short_addr_confirm = Some(confirm),
+                                            Err(e) => log::warn!("Failed to get coordinator short address {}", e),
                                         }
                                     }
                                 }
@@ -414,7 +460,7 @@ impl <'a> NetworkManager {
                                     if self.tun_tx.is_some() {
                                         log::warn!("Received network join response for address : {}, while device already starterd", short_addr);
                                     } else {
-                                        let tun_device = TunDevice::new(tun_tx.clone());
+                                        let tun_device = TunDevice::new(tun_tx.clone(), self.host_stats.clone());
                                         let (tx, mut rx) = flume::unbounded::<TunPayload>();
                                         self.tun_tx = Some(tx);
                                         tun_device.start(self.buffers_available.clone(),&settings, short_addr, rx, &extended_addr);
@@ -425,7 +471,8 @@ impl <'a> NetworkManager {
                                 log::info!("Received buffer ready : {}", event.buffer_ready);
                                 self.buffers_available.store(event.buffer_ready, Ordering::SeqCst);                                
                             }
-                            adp::Message::AdpG3LbpEvent(lbp_event) => {
+                            //In modem mode the lbp events are for the host LBD, see app_manager::host_join
+                            adp::Message::AdpG3LbpEvent(lbp_event) if is_coordinator => {
                                 if let Some(lbp_message) = lbp::adp_message_to_lbp_message(&lbp_event) {
                                     log::info!("Received lbp_event {:?}", lbp_message);
                                     if let Some(result) = lbp_manager.process_msg(&lbp_message) {
@@ -436,21 +483,18 @@ impl <'a> NetworkManager {
                             adp::Message::AdpG3LbpReponse(lbp_response) => {
                                 lbp_manager.process_response (&lbp_response);
                             }
-                            adp::Message::AdpG3SetResponse(resp) => {
-                                if let Ok(attr) = EAdpPibAttribute::try_from(resp.attribute_id) {
-                                    if attr == EAdpPibAttribute::ADP_IB_MANUF_IPV6_ULA_DEST_SHORT_ADDRESS {
-                                        if let Some(ref pkt) = current_out_msg {
-                                            let data_request = AdpDataRequest::new(
-                                                rand::thread_rng().gen(),
-                                                &pkt,
-                                                true,
-                                                0,
-                                            );
-                                            
-                                            match self.cmd_tx.send(usi::Message::UsiOut(data_request.into())) {
-                                                Ok(_) => {log::info!("Send to usi ")},
-                                                Err(e) => {log::warn!("Failed to send to usi {}", e)},
-                                            }                                            
+                            //An indication arriving while the datagram tag is read replaces the one waiting
+                            adp::Message::AdpG3UpdNonVolatileDataEvent(event) => {
+                                if let Some(file) = nv_data_file.as_mut() {
+                                    if file.indicated(event) {
+                                        match self.adp_client.get(adp::EAdpPibAttribute::ADP_IB_MANUF_DATAGRAM_TAG, 0) {
+                                            Ok(confirm) => nv_data_confirm = Some(confirm),
+                                            Err(e) => {
+                                                log::warn!("Failed to get datagram tag {}", e);
+                                                if let Err(e) = file.complete(None) {
+                                                    log::error!("Failed to store non-volatile data : {}", e);
+                                                }
+                                            }
                                         }
                                     }
                                 }
@@ -459,28 +503,103 @@ impl <'a> NetworkManager {
                         }
                     }
                     Err(_) => {}
+                }
+                //The interface of the coordinator starts once its short address is read back
+                if let Some(result) = coord_short_addr_confirm.as_ref().and_then(|c| c.try_wait()) {
+                    coord_short_addr_confirm = None;
+                    match result.map(|response| response.value()) {
+                        Ok(Ok(PibValue::U16(coord_short_addr))) => {
+                            let tun_device = TunDevice::new(tun_tx.clone(), self.host_stats.clone());
+                            let (tx, mut rx) = flume::unbounded::<TunPayload>();
+                            lbp_manager.set_short_addr(coord_short_addr);
+                            self.tun_tx = Some(tx);
+
+                            tun_device.start(self.buffers_available.clone(), &settings,
+                                coord_short_addr, rx, &extended_addr);
+                        }
+                        Ok(value) => log::error!("Invalid coordinator short address {:?}", value),
+                        Err(e) => log::error!("Failed to get coordinator short address : {}", e),
+                    }
+                }
+                if let Some(result) = nv_data_confirm.as_ref().and_then(|c| c.try_wait()) {
+                    nv_data_confirm = None;
+                    let datagram_tag = match result.map(|response| response.value()) {
+                        Ok(Ok(PibValue::U16(datagram_tag))) => Some(datagram_tag),
+                        other => {
+                            log::warn!("Failed to get datagram tag {:?}", other);
+                            None
+                        }
+                    };
+                    if let Some(Err(e)) = nv_data_file.as_mut().map(|file| file.complete(datagram_tag)) {
+                        log::error!("Failed to store non-volatile data : {}", e);
+                    }
+                }
+                data_confirms.retain(|confirm| match confirm.try_wait() {
+                    None => true,
+                    Some(Ok(_)) => false,
+                    Some(Err(e)) => {
+                        log::warn!("Data request failed, packet lost : {}", e);
+                        false
+                    }
+                });
+                match self.command_rx.try_recv() {
+                    Ok((NetworkCommand::Kick(addr), reply)) => {
+                        let result = match lbp_manager.kick(&addr) {
+                            Some(request) => {
+                                if let Err(e) = self.cmd_tx.send(usi::Message::UsiOut(request.into())) {
+                                    log::warn!("Failed to send kick to usi {}", e);
+                                }
+                                Ok(())
+                            }
+                            None => Err("unknown device".to_string()),
+                        };
+                        //The sender may not wait for the outcome
+                        let _ = reply.send(result);
+                    }
+                    Ok((NetworkCommand::Rekey(gmk), reply)) => {
+                        let result = lbp_manager.start_rekey(gmk).map(|out| {
+                            for out in out {
+                                if let Err(e) = self.cmd_tx.send(usi::Message::UsiOut(out)) {
+                                    log::warn!("Failed to send rekey to usi {}", e);
+                                }
+                            }
+                        });
+                        if let Err(ref e) = result {
+                            log::warn!("[BS] Rekey refused : {}", e);
+                        }
+                        let _ = reply.send(result);
+                    }
+                    Err(_) => {}
+                }
+                for out in lbp_manager.poll_rekey() {
+                    if let Err(e) = self.cmd_tx.send(usi::Message::UsiOut(out)) {
+                        log::warn!("Failed to send rekey to usi {}", e);
+                    }
+                }
+                if bootstrap_timer.elapsed() >= BOOTSTRAP_TIMER {
+                    bootstrap_timer = Instant::now();
+                    for request in lbp_manager.update_devices() {
+                        if let Err(e) = self.cmd_tx.send(usi::Message::UsiOut(request.into())) {
+                            log::warn!("Failed to send lbp retransmission to usi {}", e);
+                        }
+                    }
+                    let lbp_stats = lbp_manager.stats();
+                    self.host_stats.lbp_retransmissions.store(lbp_stats.retransmissions as u64, Ordering::Relaxed);
+                    self.host_stats.lbp_slot_timeouts.store(lbp_stats.slot_timeouts as u64, Ordering::Relaxed);
                 }
                  if self.buffers_available.load(Ordering::SeqCst) {
                     match tun_rx.try_recv() {
                         Ok(msg) => {
                             match msg {
                                 TunPayload::Data(pkt) => {
-
-                                    let data_request = AdpDataRequest::new(
-                                        rand::thread_rng().gen(),
-                                        &pkt,
-                                        true,
-                                        0,
-                                    );
-                                    
-                                    match self.cmd_tx.send(usi::Message::UsiOut(data_request.into())) {
-                                        Ok(_) => {log::info!("Send to usi ")},
+                                    //Through the client, the nsdu handle is not used by another data request
+                                    match self.adp_client.send_data(&pkt, true, 0) {
+                                        Ok(confirm) => {
+                                            log::info!("Send to usi ");
+                                            data_confirms.push(confirm);
+                                        }
                                         Err(e) => {log::warn!("Failed to send to usi {}", e)},
                                     }
-                                    //     }
-                                    // }
-                                    
-                                    
                                 }
                                 TunPayload::Stop => { //Should we use this as a notification that the device is stopped or should we have a separate message
                                 }
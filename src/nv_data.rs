@@ -0,0 +1,208 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::adp::{AdpG3UpdNonVolatileDataEvent, EAdpPibAttribute, EMacWrpPibAttribute};
+use crate::app_config::{G3Param, G3ParamType};
+use crate::common;
+use crate::pib::{PibAttribute, PibValue};
+
+/// Added to the stored frame counter on restore. The modem keeps sending frames between two
+/// non-volatile data indications, those counters must not be used again after a restart.
+pub const FRAME_COUNTER_MARGIN: u32 = 1000;
+/// Same for the sequence numbers of the route discoveries and the broadcasts, and the datagram tag
+const SEQ_NUMBER_MARGIN: u16 = 16;
+
+/// The G3 data the modem loses on reset and the neighbours check for freshness: the MAC frame counter,
+/// the discover and broadcast sequence numbers and the datagram tag of the 6LoWPAN fragments.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NvData {
+    pub frame_counter: u32,
+    pub discover_seq_number: u16,
+    pub broadcast_seq_number: u8,
+    pub datagram_tag: u16,
+}
+
+impl NvData {
+    /// The parameters written to the modem before the network start, moved past the values stored
+    pub fn restore_params(&self) -> Vec<G3Param> {
+        let values = [
+            (
+                PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_FRAME_COUNTER),
+                PibValue::U32(self.frame_counter.saturating_add(FRAME_COUNTER_MARGIN)),
+            ),
+            (
+                PibAttribute::Adp(EAdpPibAttribute::ADP_IB_MANUF_DISCOVER_SEQUENCE_NUMBER),
+                PibValue::U16(self.discover_seq_number.wrapping_add(SEQ_NUMBER_MARGIN)),
+            ),
+            (
+                PibAttribute::Adp(EAdpPibAttribute::ADP_IB_MANUF_BROADCAST_SEQUENCE_NUMBER),
+                PibValue::U8(self.broadcast_seq_number.wrapping_add(SEQ_NUMBER_MARGIN as u8)),
+            ),
+            (
+                PibAttribute::Adp(EAdpPibAttribute::ADP_IB_MANUF_DATAGRAM_TAG),
+                PibValue::U16(self.datagram_tag.wrapping_add(SEQ_NUMBER_MARGIN)),
+            ),
+        ];
+        values
+            .into_iter()
+            .filter_map(|(attribute, value)| {
+                let encoded = match attribute.encode(&value) {
+                    Ok(encoded) => encoded,
+                    Err(e) => {
+                        log::error!("Non-volatile data not restored : {}", e);
+                        return None;
+                    }
+                };
+                Some(match attribute {
+                    PibAttribute::Adp(attribute) => (G3ParamType::Adp, attribute.into(), 0, encoded),
+                    PibAttribute::Mac(attribute) => (G3ParamType::Mac, attribute.into(), 0, encoded),
+                })
+            })
+            .collect()
+    }
+}
+
+/// File keeping the last non-volatile data of the modem (JSON), rewritten on every indication
+#[derive(Debug)]
+pub struct NvDataFile {
+    path: PathBuf,
+    data: Option<NvData>,
+    /// Indication waiting for the datagram tag to be read, a newer one replaces it
+    pending: Option<AdpG3UpdNonVolatileDataEvent>,
+}
+
+impl NvDataFile {
+    /// Opens the file, a missing file has no data: the modem starts from its own values
+    pub fn open(path: &str) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let data = match fs::read_to_string(&path) {
+            Ok(content) => Some(serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::info!("Non-volatile data {:?} not found, the modem keeps its own", path);
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(NvDataFile { path, data, pending: None })
+    }
+
+    pub fn data(&self) -> Option<&NvData> {
+        self.data.as_ref()
+    }
+
+    /// Keeps the indication until the datagram tag is read. True when no indication was waiting
+    /// already, the caller then starts the read and gives its result to complete.
+    pub fn indicated(&mut self, event: AdpG3UpdNonVolatileDataEvent) -> bool {
+        self.pending.replace(event).is_none()
+    }
+
+    /// Stores the indication waiting for the datagram tag
+    pub fn complete(&mut self, datagram_tag: Option<u16>) -> io::Result<()> {
+        match self.pending.take() {
+            Some(event) => self.update(&event, datagram_tag),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the data of an indication, the datagram tag is not part of it and is given apart.
+    /// A frame counter going back is refused, its frames would be rejected as replays after a restore.
+    pub fn update(&mut self, event: &AdpG3UpdNonVolatileDataEvent, datagram_tag: Option<u16>) -> io::Result<()> {
+        let previous = self.data.unwrap_or_default();
+        if event.frame_counter < previous.frame_counter {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame counter {} lower than the stored {}", event.frame_counter, previous.frame_counter),
+            ));
+        }
+        self.data = Some(NvData {
+            frame_counter: event.frame_counter,
+            discover_seq_number: event.discover_seq_number,
+            broadcast_seq_number: event.broadcast_seq_number,
+            datagram_tag: datagram_tag.unwrap_or(previous.datagram_tag),
+        });
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(&self.data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        common::write_atomic(&self.path, content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_and_restore() {
+        let path = std::env::temp_dir().join(format!("ne-g3-nv-data-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut file = NvDataFile::open(path).unwrap();
+        assert_eq!(file.data(), None);
+        let event = AdpG3UpdNonVolatileDataEvent { frame_counter: 0x10203, discover_seq_number: 0x10, broadcast_seq_number: 0xFA };
+        file.update(&event, Some(0xFFF8)).unwrap();
+        let event = AdpG3UpdNonVolatileDataEvent { frame_counter: 0x10300, ..event };
+        file.update(&event, None).unwrap();
+
+        let data = *NvDataFile::open(path).unwrap().data().unwrap();
+        assert_eq!(
+            data,
+            NvData { frame_counter: 0x10300, discover_seq_number: 0x10, broadcast_seq_number: 0xFA, datagram_tag: 0xFFF8 }
+        );
+        let params = data.restore_params();
+        let values: Vec<(u32, Vec<u8>)> = params.into_iter().map(|(_, attribute, _, value)| (attribute, value)).collect();
+        assert_eq!(
+            values,
+            vec![
+                (EMacWrpPibAttribute::MAC_WRP_PIB_FRAME_COUNTER.into(), (0x10300 + FRAME_COUNTER_MARGIN).to_be_bytes().to_vec()),
+                (EAdpPibAttribute::ADP_IB_MANUF_DISCOVER_SEQUENCE_NUMBER.into(), vec![0x00, 0x20]),
+                (EAdpPibAttribute::ADP_IB_MANUF_BROADCAST_SEQUENCE_NUMBER.into(), vec![0x0A]),
+                (EAdpPibAttribute::ADP_IB_MANUF_DATAGRAM_TAG.into(), vec![0x00, 0x08]),
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn frame_counter_never_goes_back() {
+        let path = std::env::temp_dir().join(format!("ne-g3-nv-data-back-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut file = NvDataFile::open(path).unwrap();
+        let event = AdpG3UpdNonVolatileDataEvent { frame_counter: 500, discover_seq_number: 1, broadcast_seq_number: 2 };
+        file.update(&event, Some(3)).unwrap();
+        let back = AdpG3UpdNonVolatileDataEvent { frame_counter: 499, ..event.clone() };
+        assert_eq!(file.update(&back, None).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(NvDataFile::open(path).unwrap().data().unwrap().frame_counter, 500);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn newest_pending_indication_is_stored() {
+        let path = std::env::temp_dir().join(format!("ne-g3-nv-data-pending-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let mut file = NvDataFile::open(path).unwrap();
+        let event = AdpG3UpdNonVolatileDataEvent { frame_counter: 100, discover_seq_number: 1, broadcast_seq_number: 2 };
+        assert!(file.indicated(event.clone()));
+        //Arrives while the datagram tag is read, nothing is written before the read completes
+        assert!(!file.indicated(AdpG3UpdNonVolatileDataEvent { frame_counter: 200, ..event.clone() }));
+        assert_eq!(file.data(), None);
+        file.complete(Some(7)).unwrap();
+        assert_eq!(
+            NvDataFile::open(path).unwrap().data(),
+            Some(&NvData { frame_counter: 200, discover_seq_number: 1, broadcast_seq_number: 2, datagram_tag: 7 })
+        );
+        //Completed, the next indication starts a new read
+        assert!(file.indicated(event));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::thread;
+
+use crate::adp::{THopDescriptor, TPathDescriptor};
+use crate::adp_client::{AdpClient, ClientError};
+use crate::device_registry::DeviceRegistry;
+use crate::ready;
+
+/// Metric type of the path discoveries started from the command line, the composite metric of the G3 routing
+pub const DEFAULT_METRIC_TYPE: u8 = 0x0F;
+
+/// Destination of a path discovery: a short address in hex (`0005`, `0x0005`) or an IPv6 address of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathTarget {
+    Short(u16),
+    Ipv6(Ipv6Addr),
+}
+
+impl FromStr for PathTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            return s.parse().map(PathTarget::Ipv6).map_err(|_| format!("invalid IPv6 address {}", s));
+        }
+        let hex = s.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(hex, 16)
+            .map(PathTarget::Short)
+            .map_err(|_| format!("invalid short address {}", s))
+    }
+}
+
+impl fmt::Display for PathTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathTarget::Short(short_addr) => write!(f, "{:04X}", short_addr),
+            PathTarget::Ipv6(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+impl PathTarget {
+    /// The short address of the target, IPv6 addresses are looked up in the registry then, for the
+    /// addresses built from the PAN id and short address, taken from the interface id
+    pub fn resolve(&self, registry: &DeviceRegistry) -> Option<u16> {
+        match self {
+            PathTarget::Short(short_addr) => Some(*short_addr),
+            PathTarget::Ipv6(addr) => {
+                if let Some(device) = registry.get_by_ipv6(addr) {
+                    return Some(device.short_addr);
+                }
+                let o = addr.octets();
+                (o[10..14] == [0x00, 0xff, 0xfe, 0x00]).then(|| u16::from_be_bytes([o[14], o[15]]))
+            }
+        }
+    }
+}
+
+/// Error of the path and route discoveries
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// No short address is known for the target
+    UnknownTarget(PathTarget),
+    Client(ClientError),
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::UnknownTarget(target) => write!(f, "no short address for {}", target),
+            DiscoveryError::Client(e) => write!(f, "discovery failed : {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl From<ClientError> for DiscoveryError {
+    fn from(e: ClientError) -> Self {
+        DiscoveryError::Client(e)
+    }
+}
+
+impl DiscoveryError {
+    /// The target may get its short address once joined, a discovery may succeed once the network is started
+    pub fn not_ready(&self) -> bool {
+        match self {
+            DiscoveryError::UnknownTarget(_) => true,
+            DiscoveryError::Client(e) => ready::network_not_ready(e),
+        }
+    }
+}
+
+/// Runs an ADP path discovery to the target and waits for its forward and reverse paths
+pub fn trace(client: &AdpClient, registry: &DeviceRegistry, target: &PathTarget, metric_type: u8) -> Result<TPathDescriptor, DiscoveryError> {
+    let dst_addr = target.resolve(registry).ok_or(DiscoveryError::UnknownTarget(*target))?;
+    log::info!("Path discovery to {:04X}, metric type {}", dst_addr, metric_type);
+    let response = client.path_discovery(dst_addr, metric_type)?.wait()?;
+    Ok(response.path_descriptor)
+}
+
+/// The path of a discovery printed like traceroute, one line per hop with its link cost.
+/// The hops known to the registry are given with their EUI-64.
+pub struct Traceroute<'a> {
+    path: &'a TPathDescriptor,
+    registry: &'a DeviceRegistry,
+}
+
+impl<'a> Traceroute<'a> {
+    pub fn new(path: &'a TPathDescriptor, registry: &'a DeviceRegistry) -> Self {
+        Traceroute { path, registry }
+    }
+
+    fn hops(&self, f: &mut fmt::Formatter<'_>, name: &str, hops: &[THopDescriptor]) -> fmt::Result {
+        let total: u32 = hops.iter().map(|hop| hop.link_cost as u32).sum();
+        writeln!(f, "{} path, {} hops, cost {}", name, hops.len(), total)?;
+        for (i, hop) in hops.iter().enumerate() {
+            write!(f, "{:>3}  {:04X}", i + 1, hop.hop_address)?;
+            if let Some(device) = self.registry.get_by_short_addr(hop.hop_address) {
+                write!(f, " ({})", device.ext_addr)?;
+            }
+            write!(f, "  link cost {}", hop.link_cost)?;
+            if hop.mns != 0 {
+                write!(f, "  metric not supported")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Traceroute<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "path discovery to {:04X} from {:04X}, metric type {}",
+            self.path.dst_addr, self.path.orig_addr, self.path.metric_type
+        )?;
+        self.hops(f, "forward", &self.path.forward_path)?;
+        self.hops(f, "reverse", &self.path.reverse_path)
+    }
+}
+
+/// Runs the path discovery of `--trace` and prints its path, once the network is started
+pub fn spawn_trace(client: AdpClient, registry: DeviceRegistry, target: PathTarget) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let what = format!("trace to {}", target);
+        match ready::when_ready(&what, || trace(&client, &registry, &target, DEFAULT_METRIC_TYPE), DiscoveryError::not_ready) {
+            Some(Ok(path)) => println!("{}", Traceroute::new(&path, &registry)),
+            Some(Err(e)) => println!("{} : {}", what, e),
+            None => println!("{} : network not ready", what),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        assert_eq!("0005".parse(), Ok(PathTarget::Short(5)));
+        assert_eq!("0x1a2B".parse(), Ok(PathTarget::Short(0x1A2B)));
+        assert!("0x10000".parse::<PathTarget>().is_err());
+
+        let registry = DeviceRegistry::new([0, 0, 0, 0xff, 0xfe, 0]);
+        let link_local: PathTarget = "fe80::781d:ff:fe00:7".parse().unwrap();
+        assert_eq!(link_local.resolve(&registry), Some(7));
+        let eui64: PathTarget = "fe80::280:e1ff:fe00:1".parse().unwrap();
+        assert_eq!(eui64.resolve(&registry), None);
+    }
+
+    #[test]
+    fn print_path() {
+        let path = TPathDescriptor {
+            dst_addr: 5,
+            expected_orig_addr: 0,
+            orig_addr: 0,
+            metric_type: DEFAULT_METRIC_TYPE,
+            forward_path: vec![
+                THopDescriptor { hop_address: 2, mns: 0, link_cost: 10 },
+                THopDescriptor { hop_address: 5, mns: 1, link_cost: 20 },
+            ],
+            reverse_path: vec![THopDescriptor { hop_address: 0, mns: 0, link_cost: 30 }],
+        };
+        let registry = DeviceRegistry::new([0; 6]);
+        let printed = Traceroute::new(&path, &registry).to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "path discovery to 0005 from 0000, metric type 15",
+                "forward path, 2 hops, cost 30",
+                "  1  0002  link cost 10",
+                "  2  0005  link cost 20  metric not supported",
+                "reverse path, 1 hops, cost 30",
+                "  1  0000  link cost 30",
+            ]
+        );
+    }
+}
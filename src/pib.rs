@@ -0,0 +1,560 @@
+//! Typed values of the ADP and MAC PIB attributes, as the serial interface gives and takes them:
+//! multi-byte numbers big endian, the tables one entry per index.
+
+use std::net::Ipv6Addr;
+
+use serde_derive::Serialize;
+
+use crate::adp::{
+    AdpG3GetMacResponse, AdpG3GetResponse, EAdpPibAttribute, EMacWrpPibAttribute, TAdpRoutingTableEntry,
+    TExtendedAddress, ADP_ADDRESS_64BITS,
+};
+use crate::common;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PibAttribute {
+    Adp(EAdpPibAttribute),
+    Mac(EMacWrpPibAttribute),
+}
+
+impl From<EAdpPibAttribute> for PibAttribute {
+    fn from(attribute: EAdpPibAttribute) -> Self {
+        PibAttribute::Adp(attribute)
+    }
+}
+
+impl From<EMacWrpPibAttribute> for PibAttribute {
+    fn from(attribute: EMacWrpPibAttribute) -> Self {
+        PibAttribute::Mac(attribute)
+    }
+}
+
+/// Structure of the value of an attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Bool,
+    U8,
+    U16,
+    U32,
+    /// EUI-64, least significant byte first
+    ExtendedAddress,
+    /// 16 bytes, a GMK or the PSK
+    Key,
+    Prefix,
+    ContextInformation,
+    Routing,
+    Blacklist,
+    Group,
+    Neighbour,
+    Pos,
+    /// Not structured, or manufacturer specific
+    Bytes,
+}
+
+/// Entry of ADP_IB_PREFIX_TABLE, lifetimes in seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TAdpPrefixEntry {
+    pub prefix_len: u8,
+    pub on_link: bool,
+    pub autonomous: bool,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    pub prefix: Ipv6Addr,
+}
+
+/// Entry of ADP_IB_CONTEXT_INFORMATION_TABLE, the 6LoWPAN header compression contexts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TAdpContextInformationEntry {
+    /// Minutes
+    pub valid_time: u16,
+    pub valid_for_compression: bool,
+    pub context_len_bits: u8,
+    /// The first context_len_bits bits of the context
+    pub context: Vec<u8>,
+}
+
+/// Entry of ADP_IB_BLACKLIST_TABLE, a neighbour not used for routing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TAdpBlacklistEntry {
+    pub short_addr: u16,
+    /// Minutes
+    pub valid_time: u16,
+}
+
+/// Entry of ADP_IB_GROUP_TABLE, a multicast group the node belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TAdpGroupEntry {
+    pub group_addr: u16,
+    pub valid: bool,
+}
+
+/// Entry of MAC_WRP_PIB_NEIGHBOUR_TABLE (and MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_ELEMENT)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TMacNeighbourEntry {
+    pub short_addr: u16,
+    /// One bit per group of carriers
+    pub tone_map: [u8; 3],
+    /// See neighbours::modulation
+    pub modulation_type: u8,
+    pub tx_gain: u8,
+    pub tx_res: u8,
+    pub tx_coef: [u8; 6],
+    pub modulation_scheme: u8,
+    pub phase_differential: u8,
+    pub lqi: u8,
+    /// Minutes the tone map stays valid
+    pub tmr_valid_time: u16,
+    /// Minutes the entry stays valid
+    pub neighbour_valid_time: u16,
+}
+
+/// Entry of MAC_WRP_PIB_POS_TABLE, the neighbours heard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TMacPosEntry {
+    pub short_addr: u16,
+    pub lqi: u8,
+    /// Minutes
+    pub pos_valid_time: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PibValue {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    ExtendedAddress(TExtendedAddress),
+    Key([u8; 16]),
+    Prefix(TAdpPrefixEntry),
+    ContextInformation(TAdpContextInformationEntry),
+    Routing(TAdpRoutingTableEntry),
+    Blacklist(TAdpBlacklistEntry),
+    Group(TAdpGroupEntry),
+    Neighbour(TMacNeighbourEntry),
+    Pos(TMacPosEntry),
+    Bytes(Vec<u8>),
+}
+
+/// prefix length (1), on link (1), autonomous (1), valid lifetime (4), preferred lifetime (4), prefix (16)
+const PREFIX_ENTRY_LEN: usize = 27;
+/// short address (2), tone map (3), modulation type (1), tx gain (1), tx res (1), tx coef (6),
+/// modulation scheme (1), phase differential (1), lqi (1), tmr valid time (2), neighbour valid time (2)
+const NEIGHBOUR_ENTRY_LEN: usize = 21;
+
+impl PibAttribute {
+    pub fn kind(&self) -> ValueKind {
+        use EAdpPibAttribute::*;
+        use EMacWrpPibAttribute::*;
+        match self {
+            PibAttribute::Adp(attribute) => match attribute {
+                ADP_IB_RLC_ENABLED
+                | ADP_IB_UNICAST_RREQ_GEN_ENABLE
+                | ADP_IB_SNIFFER_MODE
+                | ADP_IB_DEFAULT_COORD_ROUTE_ENABLED
+                | ADP_IB_DISABLE_DEFAULT_ROUTING
+                | ADP_IB_MANUF_IPV6_HEADER_COMPRESSION
+                | ADP_IB_MANUF_FORCED_NO_ACK_REQUEST
+                | ADP_IB_MANUF_BROADCAST_ROUTE_ALL
+                | ADP_IB_MANUF_KEEP_PARAMS_AFTER_KICK_LEAVE
+                | ADP_IB_MANUF_DISABLE_AUTO_RREQ
+                | ADP_IB_MANUF_UPDATE_NON_VOLATILE_DATA => ValueKind::Bool,
+                ADP_IB_SECURITY_LEVEL
+                | ADP_IB_METRIC_TYPE
+                | ADP_IB_LOW_LQI_VALUE
+                | ADP_IB_HIGH_LQI_VALUE
+                | ADP_IB_RREP_WAIT
+                | ADP_IB_ADD_REV_LINK_COST
+                | ADP_IB_MAX_HOPS
+                | ADP_IB_DEVICE_TYPE
+                | ADP_IB_NET_TRAVERSAL_TIME
+                | ADP_IB_KR
+                | ADP_IB_KM
+                | ADP_IB_KC
+                | ADP_IB_KQ
+                | ADP_IB_KH
+                | ADP_IB_RREQ_RETRIES
+                | ADP_IB_RREQ_WAIT
+                | ADP_IB_WEAK_LQI_VALUE
+                | ADP_IB_KRT
+                | ADP_IB_PATH_DISCOVERY_TIME
+                | ADP_IB_ACTIVE_KEY_INDEX
+                | ADP_IB_MANUF_BROADCAST_SEQUENCE_NUMBER
+                | ADP_IB_MANUF_LQI_TO_COORD
+                | ADP_IB_MANUF_MAX_REPAIR_RESEND_ATTEMPTS
+                | ADP_IB_MANUF_GET_BAND_CONTEXT_TONES => ValueKind::U8,
+                ADP_IB_BROADCAST_LOG_TABLE_ENTRY_TTL
+                | ADP_IB_COORD_SHORT_ADDRESS
+                | ADP_IB_ROUTING_TABLE_ENTRY_TTL
+                | ADP_IB_BLACKLIST_TABLE_ENTRY_TTL
+                | ADP_IB_MAX_JOIN_WAIT_TIME
+                | ADP_IB_DESTINATION_ADDRESS_SET
+                | ADP_IB_MANUF_REASSEMBY_TIMER
+                | ADP_IB_MANUF_DATAGRAM_TAG
+                | ADP_IB_MANUF_ROUTING_TABLE_COUNT
+                | ADP_IB_MANUF_DISCOVER_SEQUENCE_NUMBER
+                | ADP_IB_MANUF_CIRCULAR_ROUTES_DETECTED
+                | ADP_IB_MANUF_LAST_CIRCULAR_ROUTE_ADDRESS
+                | ADP_IB_MANUF_IPV6_ULA_DEST_SHORT_ADDRESS
+                | ADP_IB_MANUF_ALL_NEIGHBORS_BLACKLISTED_COUNT
+                | ADP_IB_MANUF_QUEUED_ENTRIES_REMOVED_TIMEOUT_COUNT
+                | ADP_IB_MANUF_QUEUED_ENTRIES_REMOVED_ROUTE_ERROR_COUNT
+                | ADP_IB_MANUF_PENDING_DATA_IND_SHORT_ADDRESS
+                | ADP_IB_MANUF_DISCOVER_ROUTE_GLOBAL_SEQ_NUM => ValueKind::U16,
+                ADP_IB_MANUF_EAP_PRESHARED_KEY => ValueKind::Key,
+                ADP_IB_PREFIX_TABLE => ValueKind::Prefix,
+                ADP_IB_CONTEXT_INFORMATION_TABLE => ValueKind::ContextInformation,
+                ADP_IB_ROUTING_TABLE => ValueKind::Routing,
+                ADP_IB_BLACKLIST_TABLE => ValueKind::Blacklist,
+                ADP_IB_GROUP_TABLE => ValueKind::Group,
+                ADP_IB_BROADCAST_LOG_TABLE
+                | ADP_IB_SOFT_VERSION
+                | ADP_IB_MANUF_EAP_NETWORK_ACCESS_IDENTIFIER
+                | ADP_IB_MANUF_REGISTER_DEVICE
+                | ADP_IB_MANUF_RANDP
+                | ADP_IB_MANUF_ADP_INTERNAL_VERSION
+                | EAdpPibAttribute::INVALID => ValueKind::Bytes,
+            },
+            PibAttribute::Mac(attribute) => match attribute {
+                MAC_WRP_PIB_PROMISCUOUS_MODE
+                | MAC_WRP_PIB_TIMESTAMP_SUPPORTED
+                | MAC_WRP_PIB_SECURITY_ENABLED
+                | MAC_WRP_PIB_FREQ_NOTCHING
+                | MAC_WRP_PIB_CENELEC_LEGACY_MODE
+                | MAC_WRP_PIB_FCC_LEGACY_MODE
+                | MAC_WRP_PIB_BROADCAST_MAX_CW_ENABLE
+                | MAC_WRP_PIB_MANUF_SECURITY_RESET
+                | MAC_WRP_PIB_MANUF_LBP_FRAME_RECEIVED
+                | MAC_WRP_PIB_MANUF_LNG_FRAME_RECEIVED
+                | MAC_WRP_PIB_MANUF_BCN_FRAME_RECEIVED
+                | MAC_WRP_PIB_MANUF_ENABLE_MAC_SNIFFER => ValueKind::Bool,
+                MAC_WRP_PIB_MAX_BE
+                | MAC_WRP_PIB_BSN
+                | MAC_WRP_PIB_DSN
+                | MAC_WRP_PIB_MAX_CSMA_BACKOFFS
+                | MAC_WRP_PIB_MIN_BE
+                | MAC_WRP_PIB_MAX_FRAME_RETRIES
+                | MAC_WRP_PIB_HIGH_PRIORITY_WINDOW_SIZE
+                | MAC_WRP_PIB_CSMA_FAIRNESS_LIMIT
+                | MAC_WRP_PIB_TMR_TTL
+                | MAC_WRP_PIB_NEIGHBOUR_TABLE_ENTRY_TTL
+                | MAC_WRP_PIB_BEACON_RANDOMIZATION_WINDOW_LENGTH
+                | MAC_WRP_PIB_A
+                | MAC_WRP_PIB_K
+                | MAC_WRP_PIB_MIN_CW_ATTEMPTS
+                | MAC_WRP_PIB_TRANSMIT_ATTEN
+                | MAC_WRP_PIB_MANUF_FORCED_MOD_SCHEME
+                | MAC_WRP_PIB_MANUF_FORCED_MOD_TYPE
+                | MAC_WRP_PIB_MANUF_FORCED_MOD_SCHEME_ON_TMRESPONSE
+                | MAC_WRP_PIB_MANUF_FORCED_MOD_TYPE_ON_TMRESPONSE
+                | MAC_WRP_PIB_MANUF_LAST_RX_MOD_SCHEME
+                | MAC_WRP_PIB_MANUF_LAST_RX_MOD_TYPE
+                | MAC_WRP_PIB_MANUF_RETRIES_LEFT_TO_FORCE_ROBO => ValueKind::U8,
+                MAC_WRP_PIB_ACK_WAIT_DURATION
+                | MAC_WRP_PIB_PAN_ID
+                | MAC_WRP_PIB_SHORT_ADDRESS
+                | MAC_WRP_PIB_RC_COORD
+                | MAC_WRP_PIB_MANUF_COORD_SHORT_ADDRESS
+                | MAC_WRP_PIB_MANUF_MAX_MAC_PAYLOAD_SIZE
+                | MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_COUNT
+                | MAC_WRP_PIB_MANUF_POS_TABLE_COUNT => ValueKind::U16,
+                MAC_WRP_PIB_FRAME_COUNTER
+                | MAC_WRP_PIB_TX_DATA_PACKET_COUNT
+                | MAC_WRP_PIB_RX_DATA_PACKET_COUNT
+                | MAC_WRP_PIB_TX_CMD_PACKET_COUNT
+                | MAC_WRP_PIB_RX_CMD_PACKET_COUNT
+                | MAC_WRP_PIB_CSMA_FAIL_COUNT
+                | MAC_WRP_PIB_CSMA_NO_ACK_COUNT
+                | MAC_WRP_PIB_RX_DATA_BROADCAST_COUNT
+                | MAC_WRP_PIB_TX_DATA_BROADCAST_COUNT
+                | MAC_WRP_PIB_BAD_CRC_COUNT
+                | MAC_WRP_PIB_MANUF_RX_OTHER_DESTINATION_COUNT
+                | MAC_WRP_PIB_MANUF_RX_INVALID_FRAME_LENGTH_COUNT
+                | MAC_WRP_PIB_MANUF_RX_MAC_REPETITION_COUNT
+                | MAC_WRP_PIB_MANUF_RX_WRONG_ADDR_MODE_COUNT
+                | MAC_WRP_PIB_MANUF_RX_UNSUPPORTED_SECURITY_COUNT
+                | MAC_WRP_PIB_MANUF_RX_WRONG_KEY_ID_COUNT
+                | MAC_WRP_PIB_MANUF_RX_INVALID_KEY_COUNT
+                | MAC_WRP_PIB_MANUF_RX_WRONG_FC_COUNT
+                | MAC_WRP_PIB_MANUF_RX_DECRYPTION_ERROR_COUNT
+                | MAC_WRP_PIB_MANUF_RX_SEGMENT_DECODE_ERROR_COUNT => ValueKind::U32,
+                MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS => ValueKind::ExtendedAddress,
+                MAC_WRP_PIB_KEY_TABLE => ValueKind::Key,
+                MAC_WRP_PIB_NEIGHBOUR_TABLE | MAC_WRP_PIB_MANUF_NEIGHBOUR_TABLE_ELEMENT => ValueKind::Neighbour,
+                MAC_WRP_PIB_POS_TABLE => ValueKind::Pos,
+                MAC_WRP_PIB_TONE_MASK
+                | MAC_WRP_PIB_MANUF_DEVICE_TABLE
+                | MAC_WRP_PIB_MANUF_BAND_INFORMATION
+                | MAC_WRP_PIB_MANUF_FORCED_TONEMAP
+                | MAC_WRP_PIB_MANUF_FORCED_TONEMAP_ON_TMRESPONSE
+                | MAC_WRP_PIB_MANUF_MAC_INTERNAL_VERSION
+                | MAC_WRP_PIB_MANUF_MAC_RT_INTERNAL_VERSION
+                | MAC_WRP_PIB_MANUF_PHY_PARAM => ValueKind::Bytes,
+            },
+        }
+    }
+
+    pub fn decode(&self, value: &[u8]) -> Result<PibValue, String> {
+        let invalid = || format!("invalid {:?} value {}", self, common::to_hex_string(value));
+        let v = match (self.kind(), value) {
+            (ValueKind::Bool, [b]) => PibValue::Bool(*b != 0),
+            (ValueKind::U8, [b]) => PibValue::U8(*b),
+            (ValueKind::U16, [b0, b1]) => PibValue::U16(u16::from_be_bytes([*b0, *b1])),
+            (ValueKind::U32, [b0, b1, b2, b3]) => PibValue::U32(u32::from_be_bytes([*b0, *b1, *b2, *b3])),
+            (ValueKind::ExtendedAddress, v) if v.len() == ADP_ADDRESS_64BITS => {
+                let mut v = v.to_vec();
+                v.reverse();
+                PibValue::ExtendedAddress(TExtendedAddress::try_from(v.as_slice()).map_err(|_| invalid())?)
+            }
+            (ValueKind::Key, v) => PibValue::Key(v.try_into().map_err(|_| invalid())?),
+            (ValueKind::Prefix, v) if v.len() == PREFIX_ENTRY_LEN => {
+                let prefix: [u8; 16] = v[11..].try_into().map_err(|_| invalid())?;
+                PibValue::Prefix(TAdpPrefixEntry {
+                    prefix_len: v[0],
+                    on_link: v[1] != 0,
+                    autonomous: v[2] != 0,
+                    valid_lifetime: u32::from_be_bytes([v[3], v[4], v[5], v[6]]),
+                    preferred_lifetime: u32::from_be_bytes([v[7], v[8], v[9], v[10]]),
+                    prefix: Ipv6Addr::from(prefix),
+                })
+            }
+            (ValueKind::ContextInformation, [t0, t1, compression, len_bits, context @ ..])
+                if context.len() == (*len_bits as usize).div_ceil(8) =>
+            {
+                PibValue::ContextInformation(TAdpContextInformationEntry {
+                    valid_time: u16::from_be_bytes([*t0, *t1]),
+                    valid_for_compression: *compression != 0,
+                    context_len_bits: *len_bits,
+                    context: context.to_vec(),
+                })
+            }
+            (ValueKind::Routing, v) => PibValue::Routing(TAdpRoutingTableEntry::try_from(v).map_err(|_| invalid())?),
+            (ValueKind::Blacklist, [a0, a1, t0, t1]) => PibValue::Blacklist(TAdpBlacklistEntry {
+                short_addr: u16::from_be_bytes([*a0, *a1]),
+                valid_time: u16::from_be_bytes([*t0, *t1]),
+            }),
+            (ValueKind::Group, [a0, a1, valid]) => PibValue::Group(TAdpGroupEntry {
+                group_addr: u16::from_be_bytes([*a0, *a1]),
+                valid: *valid != 0,
+            }),
+            (ValueKind::Neighbour, v) if v.len() == NEIGHBOUR_ENTRY_LEN => PibValue::Neighbour(TMacNeighbourEntry {
+                short_addr: u16::from_be_bytes([v[0], v[1]]),
+                tone_map: [v[2], v[3], v[4]],
+                modulation_type: v[5],
+                tx_gain: v[6],
+                tx_res: v[7],
+                tx_coef: [v[8], v[9], v[10], v[11], v[12], v[13]],
+                modulation_scheme: v[14],
+                phase_differential: v[15],
+                lqi: v[16],
+                tmr_valid_time: u16::from_be_bytes([v[17], v[18]]),
+                neighbour_valid_time: u16::from_be_bytes([v[19], v[20]]),
+            }),
+            (ValueKind::Pos, [a0, a1, lqi, t0, t1]) => PibValue::Pos(TMacPosEntry {
+                short_addr: u16::from_be_bytes([*a0, *a1]),
+                lqi: *lqi,
+                pos_valid_time: u16::from_be_bytes([*t0, *t1]),
+            }),
+            (ValueKind::Bytes, v) => PibValue::Bytes(v.to_vec()),
+            _ => return Err(invalid()),
+        };
+        Ok(v)
+    }
+
+    /// The bytes of a set request of the attribute, fails when the value is not of the kind of the attribute
+    pub fn encode(&self, value: &PibValue) -> Result<Vec<u8>, String> {
+        match value.kind() == self.kind() {
+            true => Ok(value.encode()),
+            false => Err(format!("{:?} value for {:?}, expecting {:?}", value.kind(), self, self.kind())),
+        }
+    }
+}
+
+impl PibValue {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            PibValue::Bool(_) => ValueKind::Bool,
+            PibValue::U8(_) => ValueKind::U8,
+            PibValue::U16(_) => ValueKind::U16,
+            PibValue::U32(_) => ValueKind::U32,
+            PibValue::ExtendedAddress(_) => ValueKind::ExtendedAddress,
+            PibValue::Key(_) => ValueKind::Key,
+            PibValue::Prefix(_) => ValueKind::Prefix,
+            PibValue::ContextInformation(_) => ValueKind::ContextInformation,
+            PibValue::Routing(_) => ValueKind::Routing,
+            PibValue::Blacklist(_) => ValueKind::Blacklist,
+            PibValue::Group(_) => ValueKind::Group,
+            PibValue::Neighbour(_) => ValueKind::Neighbour,
+            PibValue::Pos(_) => ValueKind::Pos,
+            PibValue::Bytes(_) => ValueKind::Bytes,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            PibValue::Bool(b) => vec![*b as u8],
+            PibValue::U8(v) => vec![*v],
+            PibValue::U16(v) => v.to_be_bytes().to_vec(),
+            PibValue::U32(v) => v.to_be_bytes().to_vec(),
+            PibValue::ExtendedAddress(addr) => {
+                let mut v = addr.0.to_vec();
+                v.reverse();
+                v
+            }
+            PibValue::Key(key) => key.to_vec(),
+            PibValue::Prefix(p) => {
+                let mut v = vec![p.prefix_len, p.on_link as u8, p.autonomous as u8];
+                v.extend_from_slice(&p.valid_lifetime.to_be_bytes());
+                v.extend_from_slice(&p.preferred_lifetime.to_be_bytes());
+                v.extend_from_slice(&p.prefix.octets());
+                v
+            }
+            PibValue::ContextInformation(c) => {
+                let mut v = c.valid_time.to_be_bytes().to_vec();
+                v.extend_from_slice(&[c.valid_for_compression as u8, c.context_len_bits]);
+                v.extend_from_slice(&c.context);
+                v
+            }
+            PibValue::Routing(entry) => entry.into(),
+            PibValue::Blacklist(b) => {
+                let mut v = b.short_addr.to_be_bytes().to_vec();
+                v.extend_from_slice(&b.valid_time.to_be_bytes());
+                v
+            }
+            PibValue::Group(g) => {
+                let mut v = g.group_addr.to_be_bytes().to_vec();
+                v.push(g.valid as u8);
+                v
+            }
+            PibValue::Neighbour(n) => {
+                let mut v = n.short_addr.to_be_bytes().to_vec();
+                v.extend_from_slice(&n.tone_map);
+                v.extend_from_slice(&[n.modulation_type, n.tx_gain, n.tx_res]);
+                v.extend_from_slice(&n.tx_coef);
+                v.extend_from_slice(&[n.modulation_scheme, n.phase_differential, n.lqi]);
+                v.extend_from_slice(&n.tmr_valid_time.to_be_bytes());
+                v.extend_from_slice(&n.neighbour_valid_time.to_be_bytes());
+                v
+            }
+            PibValue::Pos(p) => {
+                let mut v = p.short_addr.to_be_bytes().to_vec();
+                v.push(p.lqi);
+                v.extend_from_slice(&p.pos_valid_time.to_be_bytes());
+                v
+            }
+            PibValue::Bytes(v) => v.clone(),
+        }
+    }
+}
+
+impl AdpG3GetResponse {
+    /// The value decoded for its attribute
+    pub fn value(&self) -> Result<PibValue, String> {
+        let attribute = EAdpPibAttribute::try_from(self.attribute_id)
+            .map_err(|_| format!("unknown ADP attribute {:08X}", self.attribute_id))?;
+        PibAttribute::Adp(attribute).decode(&self.attribute_val)
+    }
+}
+
+impl AdpG3GetMacResponse {
+    /// The value decoded for its attribute
+    pub fn value(&self) -> Result<PibValue, String> {
+        let attribute = EMacWrpPibAttribute::try_from(self.attribute_id)
+            .map_err(|_| format!("unknown MAC attribute {:08X}", self.attribute_id))?;
+        PibAttribute::Mac(attribute).decode(&self.attribute_val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(attribute: PibAttribute, value: PibValue) {
+        let bytes = attribute.encode(&value).unwrap();
+        assert_eq!(attribute.decode(&bytes), Ok(value), "{:?}", attribute);
+    }
+
+    #[test]
+    fn numbers_and_addresses() {
+        round_trip(EAdpPibAttribute::ADP_IB_MAX_HOPS.into(), PibValue::U8(8));
+        round_trip(EAdpPibAttribute::ADP_IB_RLC_ENABLED.into(), PibValue::Bool(true));
+        round_trip(EMacWrpPibAttribute::MAC_WRP_PIB_BAD_CRC_COUNT.into(), PibValue::U32(0x01020304));
+        round_trip(EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE.into(), PibValue::Key([7; 16]));
+
+        let pan_id = PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_PAN_ID);
+        assert_eq!(pan_id.decode(&[0x78, 0x1D]), Ok(PibValue::U16(0x781D)));
+        assert!(pan_id.decode(&[0x78]).is_err());
+        assert!(pan_id.encode(&PibValue::U8(1)).is_err());
+
+        //The modem gives the EUI-64 least significant byte first
+        let ext_addr = PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_EXTENDED_ADDRESS);
+        let value = ext_addr.decode(&[0x01, 0x00, 0x00, 0xFE, 0xFF, 0xE1, 0x80, 0x00]).unwrap();
+        assert_eq!(value, PibValue::ExtendedAddress("00:80:E1:FF:FE:00:00:01".parse().unwrap()));
+        assert_eq!(value.encode(), vec![0x01, 0x00, 0x00, 0xFE, 0xFF, 0xE1, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn tables() {
+        //context_information_table_0 of ne-g3.toml
+        let context = PibAttribute::Adp(EAdpPibAttribute::ADP_IB_CONTEXT_INFORMATION_TABLE);
+        let bytes = [0x02, 0x00, 0x01, 0x50, 0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x1d];
+        match context.decode(&bytes) {
+            Ok(PibValue::ContextInformation(c)) => {
+                assert_eq!((c.valid_time, c.valid_for_compression, c.context_len_bits), (0x0200, true, 80));
+                assert_eq!(c.context.len(), 10);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(context.decode(&bytes[..13]).is_err());
+
+        round_trip(
+            EAdpPibAttribute::ADP_IB_PREFIX_TABLE.into(),
+            PibValue::Prefix(TAdpPrefixEntry {
+                prefix_len: 64,
+                on_link: true,
+                autonomous: true,
+                valid_lifetime: 0x20C000,
+                preferred_lifetime: 0x20C000,
+                prefix: "fd00:0:2:781d::".parse().unwrap(),
+            }),
+        );
+        round_trip(
+            EAdpPibAttribute::ADP_IB_ROUTING_TABLE.into(),
+            PibValue::Routing(TAdpRoutingTableEntry {
+                dst_addr: 5,
+                next_hop_addr: 2,
+                route_cost: 40,
+                hop_count: 2,
+                weak_link_count: 1,
+                valid_time: 300,
+            }),
+        );
+        round_trip(
+            EAdpPibAttribute::ADP_IB_BLACKLIST_TABLE.into(),
+            PibValue::Blacklist(TAdpBlacklistEntry { short_addr: 9, valid_time: 10 }),
+        );
+        round_trip(
+            EAdpPibAttribute::ADP_IB_GROUP_TABLE.into(),
+            PibValue::Group(TAdpGroupEntry { group_addr: 0x8001, valid: true }),
+        );
+        round_trip(
+            EMacWrpPibAttribute::MAC_WRP_PIB_NEIGHBOUR_TABLE.into(),
+            PibValue::Neighbour(TMacNeighbourEntry {
+                short_addr: 3,
+                tone_map: [0x3F, 0, 0],
+                modulation_type: 1,
+                tx_gain: 2,
+                tx_res: 0,
+                tx_coef: [1, 2, 3, 4, 5, 6],
+                modulation_scheme: 0,
+                phase_differential: 0,
+                lqi: 0xB4,
+                tmr_valid_time: 2,
+                neighbour_valid_time: 255,
+            }),
+        );
+        round_trip(
+            EMacWrpPibAttribute::MAC_WRP_PIB_POS_TABLE.into(),
+            PibValue::Pos(TMacPosEntry { short_addr: 3, lqi: 0x80, pos_valid_time: 255 }),
+        );
+    }
+}
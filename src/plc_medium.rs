@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::adp::{self, TExtendedAddress};
+use crate::common;
+use crate::lbp_functions::TEapPskKey;
+use crate::modem_sim::{self, AirAddress, AirFrame, ModemSim, SimConfig};
+use crate::request;
+use crate::usi::{Decoder, OutMessage};
+
+/// Period the medium delivers the frames and polls the modems with when serving a host
+const STEP_PERIOD: Duration = Duration::from_millis(10);
+/// Duration of the discovery of the devices joining on their own
+const DEVICE_DISCOVERY_SECS: u8 = 1;
+/// Wait of a device before it tries to join again
+const DEVICE_JOIN_RETRY: Duration = Duration::from_secs(5);
+
+/// Link between two nodes of the medium
+#[derive(Debug, Clone, Copy)]
+pub struct Link {
+    pub link_quality: u8,
+    /// Probability for a frame to be lost, 0.0 to 1.0
+    pub loss: f64,
+    pub latency: Duration,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link {
+            link_quality: 0xB4,
+            loss: 0.0,
+            latency: Duration::from_millis(20),
+        }
+    }
+}
+
+/// Options of `--simulate pan:<endpoint>` and of the `pan:` transport, `key=value` pairs separated by '&' :
+/// `devices=20&lqi=180&loss=0.05&latency_ms=30&seed=1&psk=ab103411...`
+#[derive(Debug, Clone)]
+pub struct MediumConfig {
+    /// Number of devices joining the PAN of the host on their own
+    pub devices: u16,
+    /// Link of every pair of nodes
+    pub link: Link,
+    /// Seed of the frame losses, random when not set
+    pub seed: Option<u64>,
+    /// Key of the devices, the psk of ne-g3.toml by default
+    pub psk: Option<TEapPskKey>,
+}
+
+impl Default for MediumConfig {
+    fn default() -> Self {
+        MediumConfig {
+            devices: 10,
+            link: Link::default(),
+            seed: None,
+            psk: None,
+        }
+    }
+}
+
+impl FromStr for MediumConfig {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = MediumConfig::default();
+        for option in s.split('&').filter(|o| !o.is_empty()) {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or_else(|| format!("invalid medium option {}", option))?;
+            let invalid = || format!("invalid medium option {}", option);
+            match key {
+                "devices" => config.devices = value.parse().map_err(|_| invalid())?,
+                "lqi" => config.link.link_quality = value.parse().map_err(|_| invalid())?,
+                "loss" => {
+                    config.link.loss = value
+                        .parse()
+                        .ok()
+                        .filter(|loss| (0.0..=1.0).contains(loss))
+                        .ok_or_else(invalid)?
+                }
+                "latency_ms" => config.link.latency = Duration::from_millis(value.parse().map_err(|_| invalid())?),
+                "seed" => config.seed = Some(value.parse().map_err(|_| invalid())?),
+                "psk" => {
+                    let psk = common::from_hex_string(value).and_then(|v| v.try_into().ok());
+                    config.psk = Some(TEapPskKey(psk.ok_or_else(invalid)?));
+                }
+                _ => return Err(format!("unknown medium option {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// What a device joining on its own is doing, see VirtualMedium::add_device
+#[derive(Debug, Clone, Copy)]
+enum DeviceJoin {
+    Idle { next_attempt: Instant },
+    /// Best PAN heard so far : PAN id, LBA, route cost to the coordinator, link quality
+    Discovering { best: Option<(u16, u16, u16, u8)> },
+    Joining,
+    Joined,
+}
+
+#[derive(Debug)]
+struct Node {
+    sim: ModemSim,
+    /// None for the nodes whose host is outside of the medium, their confirms and indications are given by step
+    join: Option<DeviceJoin>,
+}
+
+/// Virtual PLC medium connecting simulated modems, every node hears every other one.
+///
+/// The frames sent by a modem reach the addressed nodes after the latency of the link, unless lost.
+/// Beacons answer the discoveries, the LBP frames of a device go to the coordinator (through its LBA)
+/// and back, data requests become data indications of the node owning the destination address.
+///
+/// The caller gives the time, a test runs minutes of bootstrap in no time.
+pub struct VirtualMedium {
+    nodes: Vec<Node>,
+    default_link: Link,
+    links: HashMap<(usize, usize), Link>,
+    /// Delivery time, receiving node, frame and link quality
+    in_flight: Vec<(Instant, usize, AirFrame, u8)>,
+    rng: StdRng,
+}
+
+impl VirtualMedium {
+    pub fn new(default_link: Link, seed: Option<u64>) -> Self {
+        VirtualMedium {
+            nodes: Vec::new(),
+            default_link,
+            links: HashMap::new(),
+            in_flight: Vec::new(),
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+        }
+    }
+
+    /// A host coordinator and the devices of the configuration, named 00:80:E1:FF:FE:01:xx:xx
+    pub fn from_config(config: &MediumConfig, now: Instant) -> (Self, usize) {
+        let mut medium = VirtualMedium::new(config.link, config.seed);
+        let host = medium.add_node(ModemSim::new(SimConfig::default()));
+        for i in 0..config.devices {
+            let [hi, lo] = (i + 1).to_be_bytes();
+            let mut sim_config = SimConfig {
+                ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x01, hi, lo]),
+                ..SimConfig::default()
+            };
+            if let Some(ref psk) = config.psk {
+                sim_config.psk = psk.clone();
+            }
+            medium.add_device(ModemSim::new(sim_config), now);
+        }
+        (medium, host)
+    }
+
+    /// A modem driven by a host, outside of the medium
+    pub fn add_node(&mut self, mut sim: ModemSim) -> usize {
+        sim.attach();
+        self.nodes.push(Node { sim, join: None });
+        self.nodes.len() - 1
+    }
+
+    /// A device that initializes and then discovers and joins the PAN on its own, again when it is kicked
+    pub fn add_device(&mut self, sim: ModemSim, now: Instant) -> usize {
+        let node = self.add_node(sim);
+        self.request(node, request::AdpInitializeRequest::new(0).into(), now);
+        self.nodes[node].sim.poll(now);
+        self.nodes[node].join = Some(DeviceJoin::Idle { next_attempt: now });
+        node
+    }
+
+    #[cfg(test)]
+    pub fn node(&self, node: usize) -> &ModemSim {
+        &self.nodes[node].sim
+    }
+
+    /// Replaces the default link between two nodes, both ways
+    #[cfg(test)]
+    pub fn set_link(&mut self, a: usize, b: usize, link: Link) {
+        self.links.insert((a.min(b), a.max(b)), link);
+    }
+
+    fn link(&self, a: usize, b: usize) -> Link {
+        self.links.get(&(a.min(b), a.max(b))).copied().unwrap_or(self.default_link)
+    }
+
+    /// Gives a request to a modem, through the USI encoding as on a real link
+    pub fn request(&mut self, node: usize, out: OutMessage, now: Instant) {
+        let frame = match out.to_usi() {
+            Some(frame) => frame,
+            None => return,
+        };
+        for msg in Decoder::new().decode(&frame) {
+            self.nodes[node].sim.process(&msg, now);
+        }
+    }
+
+    fn receivers(&self, src: usize, dst: &AirAddress) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|i| *i != src)
+            .filter(|i| {
+                let sim = &self.nodes[*i].sim;
+                match dst {
+                    AirAddress::Short(short_addr) => sim.in_network() && sim.short_addr() == *short_addr,
+                    AirAddress::Extended(ext_addr) => sim.ext_addr() == *ext_addr,
+                    AirAddress::Broadcast => true,
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the medium to now : sends the frames of the modems, delivers the ones that are due and
+    /// runs the devices joining on their own. Gives the confirms and indications of the host nodes.
+    pub fn step(&mut self, now: Instant) -> Vec<(usize, OutMessage)> {
+        for src in 0..self.nodes.len() {
+            for frame in self.nodes[src].sim.take_air_frames() {
+                for dst in self.receivers(src, &frame.dst) {
+                    let link = self.link(src, dst);
+                    if self.rng.gen::<f64>() < link.loss {
+                        log::debug!("[MEDIUM] Lost frame {} -> {} : {:?}", src, dst, frame.payload);
+                        continue;
+                    }
+                    self.in_flight.push((now + link.latency, dst, frame.clone(), link.link_quality));
+                }
+            }
+        }
+        let (due, in_flight): (Vec<_>, Vec<_>) = self.in_flight.drain(..).partition(|(at, ..)| *at <= now);
+        self.in_flight = in_flight;
+        for (_, dst, frame, link_quality) in due {
+            self.nodes[dst].sim.receive(&frame, link_quality, now);
+        }
+
+        let mut out = Vec::new();
+        for node in 0..self.nodes.len() {
+            self.start_join(node, now);
+            for msg in self.nodes[node].sim.poll(now) {
+                match self.nodes[node].join {
+                    Some(_) => self.device_event(node, &msg, now),
+                    None => out.push((node, msg)),
+                }
+            }
+        }
+        out
+    }
+
+    fn start_join(&mut self, node: usize, now: Instant) {
+        if let Some(DeviceJoin::Idle { next_attempt }) = self.nodes[node].join {
+            if now >= next_attempt {
+                self.nodes[node].join = Some(DeviceJoin::Discovering { best: None });
+                self.request(node, request::AdpDiscoveryRequest::new(DEVICE_DISCOVERY_SECS).into(), now);
+            }
+        }
+    }
+
+    /// What the host of a device does with the confirms and indications of its modem
+    fn device_event(&mut self, node: usize, msg: &OutMessage, now: Instant) {
+        let data = msg.data();
+        let join = match self.nodes[node].join {
+            Some(join) => join,
+            None => return,
+        };
+        let retry = DeviceJoin::Idle { next_attempt: now + DEVICE_JOIN_RETRY };
+        let next = match (data, join) {
+            //PAN id (2), link quality (1), LBA (2), route cost to the coordinator (2)
+            ([adp::G3_SERIAL_MSG_ADP_DISCOVERY_INDICATION, p0, p1, lqi, l0, l1, r0, r1], DeviceJoin::Discovering { best }) => {
+                let beacon = (u16::from_be_bytes([*p0, *p1]), u16::from_be_bytes([*l0, *l1]), u16::from_be_bytes([*r0, *r1]), *lqi);
+                let better = best.is_none_or(|(_, _, rc_coord, lqi)| (beacon.2, u8::MAX - beacon.3) < (rc_coord, u8::MAX - lqi));
+                DeviceJoin::Discovering { best: if better { Some(beacon) } else { best } }
+            }
+            ([adp::G3_SERIAL_MSG_ADP_DISCOVERY_CONFIRM, ..], DeviceJoin::Discovering { best }) => match best {
+                Some((pan_id, lba_address, _, _)) => {
+                    self.request(node, request::AdpJoinNetworkRequest { pan_id, lba_address }.into(), now);
+                    DeviceJoin::Joining
+                }
+                None => retry,
+            },
+            ([adp::G3_SERIAL_MSG_ADP_NETWORK_JOIN_CONFIRM, status, ..], DeviceJoin::Joining) => match *status {
+                0 => DeviceJoin::Joined,
+                _ => retry,
+            },
+            ([adp::G3_SERIAL_MSG_ADP_NETWORK_LEAVE_INDICATION, ..], _) => retry,
+            _ => join,
+        };
+        self.nodes[node].join = Some(next);
+    }
+
+    /// Runs the medium with `host` driven by the link, until the reader is closed
+    pub fn serve<R: io::Read + Send + 'static, W: Write>(mut self, host: usize, reader: R, mut writer: W) -> io::Result<()> {
+        let rx = modem_sim::spawn_reader(reader);
+        loop {
+            match rx.recv_timeout(STEP_PERIOD) {
+                Ok(msg) => self.nodes[host].sim.process(&msg, Instant::now()),
+                Err(flume::RecvTimeoutError::Timeout) => {}
+                Err(flume::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            for (node, out) in self.step(Instant::now()) {
+                if node != host {
+                    continue;
+                }
+                if let Some(buf) = out.to_usi() {
+                    writer.write_all(&buf)?;
+                }
+            }
+            writer.flush()?;
+        }
+    }
+}
+
+/// Runs a PAN of simulated devices for a coordinator on `pty` or `tcp:<listen address>`,
+/// followed by the options of MediumConfig after '?'
+pub fn run(spec: &str) -> io::Result<()> {
+    let (endpoint, options) = spec.split_once('?').unwrap_or((spec, ""));
+    let config: MediumConfig = options
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    log::info!("[MEDIUM] Simulated PAN {:?}", config);
+    //Each connection starts a PAN whose devices were just powered up
+    modem_sim::listen(endpoint, |reader, writer| {
+        let (medium, host) = VirtualMedium::from_config(&config, Instant::now());
+        medium.serve(host, reader, writer)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp::{EAdpPibAttribute, EAdpStatus, EMacWrpPibAttribute, TAddress, TAdpRoutingTableEntry, THopDescriptor};
+    use crate::app_config;
+    use crate::lbp;
+    use crate::lbp_manager::{DeviceEvent, LbpManager};
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    /// The coordinator side of the network manager, with the real LbpManager
+    struct Coordinator {
+        node: usize,
+        lbp_manager: LbpManager,
+        events: flume::Receiver<DeviceEvent>,
+    }
+
+    impl Coordinator {
+        fn new(medium: &mut VirtualMedium, now: Instant) -> Self {
+            let mut settings = app_config::Settings::new("ne-g3.toml").unwrap();
+            settings.g3.device_db = None;
+            settings.g3.gmk_file = None;
+            let mut lbp_manager = LbpManager::new(&settings.g3);
+            lbp_manager.set_short_addr(0);
+            let (tx, events) = flume::unbounded();
+            lbp_manager.add_listener(tx);
+            let node = medium.add_node(ModemSim::new(SimConfig::default()));
+            medium.request(node, request::AdpInitializeRequest::new(0).into(), now);
+            medium.request(node, request::AdpNetworkStartRequest::new(settings.g3.pan_id).into(), now);
+            Coordinator { node, lbp_manager, events }
+        }
+
+        /// Gives what the other host nodes received
+        fn run(&mut self, medium: &mut VirtualMedium, now: Instant) -> Vec<adp::Message> {
+            let mut requests: Vec<OutMessage> = self.lbp_manager.poll_rekey();
+            let mut others = Vec::new();
+            for (node, out) in medium.step(now) {
+                if node != self.node {
+                    others.extend(messages(&out));
+                    continue;
+                }
+                let frame = out.to_usi().unwrap();
+                for msg in Decoder::new().decode(&frame) {
+                    match adp::usi_message_to_message(&msg) {
+                        Some(adp::Message::AdpG3LbpEvent(event)) => {
+                            let request = lbp::adp_message_to_lbp_message(&event)
+                                .and_then(|lbp_message| self.lbp_manager.process_msg(&lbp_message));
+                            requests.extend(request.map(|request| request.into()));
+                        }
+                        Some(adp::Message::AdpG3LbpReponse(response)) => self.lbp_manager.process_response(&response),
+                        _ => {}
+                    }
+                }
+            }
+            for out in requests {
+                medium.request(self.node, out, now);
+            }
+            others
+        }
+
+        /// Runs the medium until the condition holds, at most for the given simulated time
+        fn run_until<F>(&mut self, medium: &mut VirtualMedium, now: &mut Instant, limit: Duration, mut done: F) -> bool
+        where
+            F: FnMut(&mut Self, &VirtualMedium) -> bool,
+        {
+            let end = *now + limit;
+            while *now < end {
+                *now += STEP;
+                self.run(medium, *now);
+                if done(self, medium) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+
+    fn messages(out: &OutMessage) -> Vec<adp::Message> {
+        Decoder::new()
+            .decode(&out.to_usi().unwrap())
+            .iter()
+            .filter_map(adp::usi_message_to_message)
+            .collect()
+    }
+
+    fn devices(medium: &mut VirtualMedium, count: u16, now: Instant) -> Vec<usize> {
+        (0..count)
+            .map(|i| {
+                let [hi, lo] = (i + 1).to_be_bytes();
+                let config = SimConfig {
+                    ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x01, hi, lo]),
+                    ..SimConfig::default()
+                };
+                medium.add_device(ModemSim::new(config), now)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bootstrap_rekey_and_kick() {
+        let mut now = Instant::now();
+        let mut medium = VirtualMedium::new(Link::default(), Some(1));
+        let mut coordinator = Coordinator::new(&mut medium, now);
+        let devices = devices(&mut medium, 24, now);
+
+        let joined = coordinator.run_until(&mut medium, &mut now, Duration::from_secs(120), |_, medium| {
+            devices.iter().all(|d| medium.node(*d).in_network())
+        });
+        assert!(joined, "not every device joined");
+        let mut short_addrs: Vec<u16> = devices.iter().map(|d| medium.node(*d).short_addr()).collect();
+        short_addrs.sort();
+        short_addrs.dedup();
+        assert_eq!(short_addrs.len(), devices.len());
+        assert!(!short_addrs.contains(&0x0000));
+        let events: Vec<DeviceEvent> = coordinator.events.try_iter().collect();
+        assert_eq!(events.iter().filter(|e| matches!(e, DeviceEvent::Joined { .. })).count(), devices.len());
+
+        let gmk = vec![0x5A; 16];
+        for out in coordinator.lbp_manager.start_rekey(gmk.clone()).unwrap() {
+            medium.request(coordinator.node, out, now);
+        }
+        let mut failed = None;
+        coordinator.run_until(&mut medium, &mut now, Duration::from_secs(120), |coordinator, _| {
+            for event in coordinator.events.try_iter() {
+                if let DeviceEvent::RekeyCompleted { failed: f, .. } = event {
+                    failed = Some(f);
+                }
+            }
+            failed.is_some()
+        });
+        assert_eq!(failed, Some(Vec::new()));
+        //Every device is told to switch to the new key
+        let activated = coordinator.run_until(&mut medium, &mut now, Duration::from_secs(10), |_, medium| {
+            devices.iter().all(|d| {
+                let node = medium.node(*d);
+                node.mac_pib(EMacWrpPibAttribute::MAC_WRP_PIB_KEY_TABLE, 1) == Some(&gmk)
+                    && node.adp_pib(EAdpPibAttribute::ADP_IB_ACTIVE_KEY_INDEX, 0) == Some(&vec![1])
+            })
+        });
+        assert!(activated, "new key not activated on every device");
+        assert!(coordinator.lbp_manager.start_rekey(gmk).is_err());
+
+        let kicked = devices[3];
+        let short_addr = medium.node(kicked).short_addr();
+        let kick = coordinator.lbp_manager.kick(&TAddress::Short(short_addr)).unwrap();
+        medium.request(coordinator.node, kick.into(), now);
+        let left = coordinator.run_until(&mut medium, &mut now, Duration::from_secs(1), |_, medium| {
+            !medium.node(kicked).in_network()
+        });
+        assert!(left, "kicked device still in the network");
+        assert!(devices.iter().filter(|d| **d != kicked).all(|d| medium.node(*d).in_network()));
+    }
+
+    #[test]
+    fn join_through_a_neighbour() {
+        let mut now = Instant::now();
+        let mut medium = VirtualMedium::new(Link::default(), Some(1));
+        let mut coordinator = Coordinator::new(&mut medium, now);
+        let devices = devices(&mut medium, 2, now);
+        //The second device only hears the first one
+        medium.set_link(coordinator.node, devices[1], Link { loss: 1.0, ..Link::default() });
+
+        let joined = coordinator.run_until(&mut medium, &mut now, Duration::from_secs(60), |_, medium| {
+            devices.iter().all(|d| medium.node(*d).in_network())
+        });
+        assert!(joined, "device behind its neighbour did not join");
+        assert_ne!(medium.node(devices[0]).short_addr(), medium.node(devices[1]).short_addr());
+        let events: Vec<DeviceEvent> = coordinator.events.try_iter().collect();
+        assert_eq!(events.iter().filter(|e| matches!(e, DeviceEvent::Joined { .. })).count(), 2);
+    }
+
+    /// A second host node joined to the coordinator, gives its node and short address
+    fn join_host(coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant) -> (usize, u16) {
+        let host = medium.add_node(ModemSim::new(SimConfig {
+            ext_addr: TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x02, 0x00, 0x01]),
+            ..SimConfig::default()
+        }));
+        medium.request(host, request::AdpInitializeRequest::new(0).into(), *now);
+        medium.request(host, request::AdpJoinNetworkRequest { pan_id: 0x781d, lba_address: 0 }.into(), *now);
+        let mut joined = None;
+        coordinator.run_until(medium, now, Duration::from_secs(10), |_, medium| {
+            joined = joined.or(medium.node(host).in_network().then(|| medium.node(host).short_addr()));
+            joined.is_some()
+        });
+        (host, joined.expect("host node did not join"))
+    }
+
+    /// Runs the medium until pick accepts a message of the other host nodes, panics after 20 s
+    fn host_confirm<T, F>(coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant, pick: F) -> T
+    where
+        F: Fn(adp::Message) -> Option<T>,
+    {
+        let end = *now + Duration::from_secs(20);
+        while *now < end {
+            *now += STEP;
+            if let Some(confirm) = coordinator.run(medium, *now).into_iter().find_map(&pick) {
+                return confirm;
+            }
+        }
+        panic!("no confirm")
+    }
+
+    #[test]
+    fn data_between_nodes() {
+        let mut now = Instant::now();
+        let mut medium = VirtualMedium::new(Link::default(), Some(1));
+        let mut coordinator = Coordinator::new(&mut medium, now);
+        let (host, short_addr) = join_host(&mut coordinator, &mut medium, &mut now);
+
+        //IPv6 packet from the coordinator to an address of the host node, as the TUN gives it
+        let mut packet = vec![0x60, 0, 0, 0, 0, 2, 17, 64];
+        packet.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 0]);
+        packet.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0]);
+        packet.extend_from_slice(&short_addr.to_be_bytes());
+        packet.extend_from_slice(&[0xAA, 0x55]);
+        let indications = |coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant| {
+            medium.request(coordinator.node, request::AdpDataRequest::new(1, &packet, true, 0).into(), *now);
+            let mut received = Vec::new();
+            for _ in 0..10 {
+                *now += STEP;
+                for msg in coordinator.run(medium, *now) {
+                    if let adp::Message::AdpG3DataEvent(event) = msg {
+                        received.push(event);
+                    }
+                }
+            }
+            received
+        };
+        let received = indications(&mut coordinator, &mut medium, &mut now);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].nsdu, packet);
+        assert_eq!(received[0].link_quality_indicator, Link::default().link_quality);
+
+        medium.set_link(coordinator.node, host, Link { loss: 1.0, ..Link::default() });
+        assert!(indications(&mut coordinator, &mut medium, &mut now).is_empty());
+    }
+
+    #[test]
+    fn path_discovery() {
+        let mut now = Instant::now();
+        let mut medium = VirtualMedium::new(Link::default(), Some(1));
+        let mut coordinator = Coordinator::new(&mut medium, now);
+        let (host, short_addr) = join_host(&mut coordinator, &mut medium, &mut now);
+
+        let path_discovery = |coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant| {
+            medium.request(host, request::AdpPathDiscoveryRequest::new(0x0000, 0x0F).into(), *now);
+            host_confirm(coordinator, medium, now, |msg| match msg {
+                adp::Message::AdpG3PathDiscoveryResponse(r) => Some(r),
+                _ => None,
+            })
+        };
+        let response = path_discovery(&mut coordinator, &mut medium, &mut now);
+        assert_eq!(response.status, EAdpStatus::G3_SUCCESS);
+        let path = response.path_descriptor;
+        assert_eq!((path.dst_addr, path.orig_addr, path.metric_type), (0x0000, short_addr, 0x0F));
+        let link_cost = (0xFF - Link::default().link_quality) / 4;
+        assert_eq!(path.forward_path, vec![THopDescriptor { hop_address: 0x0000, mns: 0, link_cost }]);
+        assert_eq!(path.reverse_path, vec![THopDescriptor { hop_address: short_addr, mns: 0, link_cost }]);
+
+        medium.set_link(coordinator.node, host, Link { loss: 1.0, ..Link::default() });
+        let response = path_discovery(&mut coordinator, &mut medium, &mut now);
+        assert_eq!(response.status, EAdpStatus::G3_TIMEOUT);
+        assert!(response.path_descriptor.forward_path.is_empty());
+    }
+
+    #[test]
+    fn route_discovery_and_routing_table() {
+        let mut now = Instant::now();
+        let mut medium = VirtualMedium::new(Link::default(), Some(1));
+        let mut coordinator = Coordinator::new(&mut medium, now);
+        let (host, _) = join_host(&mut coordinator, &mut medium, &mut now);
+
+        let route_discovery = |coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant| {
+            medium.request(host, request::AdpRouteDiscoveryRequest::new(0x0000, 8).into(), *now);
+            host_confirm(coordinator, medium, now, |msg| match msg {
+                adp::Message::AdpG3RouteDiscoveryResponse(r) => Some(r.status),
+                _ => None,
+            })
+        };
+        let get = |coordinator: &mut Coordinator, medium: &mut VirtualMedium, now: &mut Instant, attribute, idx| {
+            medium.request(host, request::AdpGetRequest::new(attribute, idx).into(), *now);
+            host_confirm(coordinator, medium, now, |msg| match msg {
+                adp::Message::AdpG3GetResponse(r) => Some(r.attribute_val),
+                _ => None,
+            })
+        };
+        for _ in 0..2 {
+            assert_eq!(route_discovery(&mut coordinator, &mut medium, &mut now), EAdpStatus::G3_SUCCESS);
+            let count = get(&mut coordinator, &mut medium, &mut now, adp::EAdpPibAttribute::ADP_IB_MANUF_ROUTING_TABLE_COUNT, 0);
+            assert_eq!(count, vec![0, 1]);
+        }
+        let entry = get(&mut coordinator, &mut medium, &mut now, adp::EAdpPibAttribute::ADP_IB_ROUTING_TABLE, 0);
+        let entry = TAdpRoutingTableEntry::try_from(&entry[..]).unwrap();
+        assert_eq!((entry.dst_addr, entry.next_hop_addr, entry.hop_count), (0x0000, 0x0000, 1));
+        assert_eq!(entry.route_cost, ((0xFF - Link::default().link_quality) / 4) as u16);
+
+        medium.set_link(coordinator.node, host, Link { loss: 1.0, ..Link::default() });
+        assert_eq!(route_discovery(&mut coordinator, &mut medium, &mut now), EAdpStatus::G3_ROUTE_ERROR);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::adp::TExtendedAddress;
+use crate::common;
+use crate::lbp_functions::TEapPskKey;
+
+/// EAP-PSK keys of the devices allowed to join.
+///
+/// The key file has one device per line, the EUI-64 followed by its 16 bytes PSK, both in hex :
+///
+/// `00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220A`
+///
+/// In CENELEC and FCC bands the EAP-PSK ID_P of a device is its EUI-64, so this is also the ID_P lookup.
+/// Empty lines and lines starting with '#' are ignored, a device given twice is an error.
+#[derive(Debug)]
+pub struct PskStore {
+    keys: HashMap<TExtendedAddress, TEapPskKey>,
+    fallback: Option<TEapPskKey>,
+}
+
+impl PskStore {
+    pub fn new(fallback: Option<TEapPskKey>) -> Self {
+        PskStore {
+            keys: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn load(path: &str, fallback: Option<TEapPskKey>) -> io::Result<Self> {
+        let mut store = PskStore::new(fallback);
+        let content = fs::read_to_string(path)?;
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let ext_addr = fields.next().and_then(|f| f.parse::<TExtendedAddress>().ok());
+            let key = fields
+                .next()
+                .and_then(common::from_hex_string)
+                .and_then(|v| <[u8; 16]>::try_from(v).ok());
+            match (ext_addr, key) {
+                (Some(ext_addr), Some(key)) => {
+                    if store.keys.insert(ext_addr, TEapPskKey(key)).is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{}:{} duplicate psk entry for {}", path, n + 1, ext_addr),
+                        ));
+                    }
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{} invalid psk entry", path, n + 1),
+                    ));
+                }
+            }
+        }
+        log::info!("Loaded {} device keys from {}", store.keys.len(), path);
+        Ok(store)
+    }
+
+    /// The device key, or the network wide key when fallback is enabled
+    pub fn key_for(&self, ext_addr: &TExtendedAddress) -> Option<&TEapPskKey> {
+        self.keys.get(ext_addr).or(self.fallback.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE_1: &str = "00:80:E1:FF:FE:00:00:01";
+    const KEY_1: [u8; 16] = [0xAB, 0x10, 0x34, 0x11, 0x45, 0x11, 0x1B, 0xC3, 0xC1, 0x2D, 0xE8, 0xFF, 0x11, 0x14, 0x22, 0x0A];
+    const GLOBAL: [u8; 16] = [0x5A; 16];
+
+    fn load(name: &str, content: &str, fallback: Option<TEapPskKey>) -> io::Result<PskStore> {
+        let path = std::env::temp_dir().join(format!("ne-g3-psk-{}-{}.txt", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, content).unwrap();
+        let store = PskStore::load(path, fallback);
+        fs::remove_file(path).unwrap();
+        store
+    }
+
+    fn key(store: &PskStore, ext_addr: &str) -> Option<[u8; 16]> {
+        store.key_for(&ext_addr.parse().unwrap()).map(|key| key.0)
+    }
+
+    #[test]
+    fn parse_keys() {
+        let content = "# meters of the first floor\n\n  00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220A\n\
+            00-80-E1-FF-FE-00-00-02   00112233445566778899AABBCCDDEEFF\n";
+        let store = load("parse", content, None).unwrap();
+        assert_eq!(key(&store, DEVICE_1), Some(KEY_1));
+        assert_eq!(key(&store, "00:80:E1:FF:FE:00:00:02").unwrap()[15], 0xFF);
+        assert_eq!(key(&store, "00:80:E1:FF:FE:00:00:03"), None);
+    }
+
+    #[test]
+    fn invalid_entries() {
+        for (name, content) in [
+            ("hex", "00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220G"),
+            ("short", "00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF111422"),
+            ("long", "00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220A0B"),
+            ("eui64", "00:80:E1:FF:FE:00:01 AB10341145111BC3C12DE8FF1114220A"),
+            ("missing", "00:80:E1:FF:FE:00:00:01"),
+            (
+                "duplicate",
+                "00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220A\n00:80:E1:FF:FE:00:00:01 00112233445566778899AABBCCDDEEFF",
+            ),
+        ] {
+            let e = load(name, content, None).err().unwrap_or_else(|| panic!("{} entry accepted", name));
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn fallback_to_global_psk() {
+        let content = "00:80:E1:FF:FE:00:00:01 AB10341145111BC3C12DE8FF1114220A";
+        let with_fallback = load("fallback-on", content, Some(TEapPskKey(GLOBAL))).unwrap();
+        assert_eq!(key(&with_fallback, DEVICE_1), Some(KEY_1));
+        assert_eq!(key(&with_fallback, "00:80:E1:FF:FE:00:00:02"), Some(GLOBAL));
+
+        let without_fallback = load("fallback-off", content, None).unwrap();
+        assert_eq!(key(&without_fallback, "00:80:E1:FF:FE:00:00:02"), None);
+    }
+}
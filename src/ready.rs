@@ -0,0 +1,59 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use crate::adp::EAdpStatus;
+use crate::adp_client::{ClientError, PibError};
+
+/// Time between the attempts of the requests started while the network starts or the device joins
+const RETRY_PERIOD: Duration = Duration::from_secs(10);
+const ATTEMPTS: u32 = 6;
+
+/// The modem refuses the requests of a network not started yet with an invalid request
+pub(crate) fn network_not_ready(e: &ClientError) -> bool {
+    matches!(e, ClientError::Status(_, EAdpStatus::G3_INVALID_REQUEST))
+}
+
+pub(crate) fn pib_not_ready(e: &PibError) -> bool {
+    match e {
+        PibError::Client(e) => network_not_ready(e),
+        PibError::InvalidValue(_) => false,
+    }
+}
+
+/// Calls f at once, then every RETRY_PERIOD while it fails with what not_ready accepts, the network is starting
+/// or the device joining. None when it was never ready.
+pub(crate) fn when_ready<T, E, F, R>(what: &str, mut f: F, not_ready: R) -> Option<Result<T, E>>
+where
+    E: fmt::Display,
+    F: FnMut() -> Result<T, E>,
+    R: Fn(&E) -> bool,
+{
+    for attempt in 1..=ATTEMPTS {
+        match f() {
+            Err(e) if not_ready(&e) => {
+                log::info!("{} not possible yet ({}), attempt {}/{}", what, e, attempt, ATTEMPTS);
+                if attempt < ATTEMPTS {
+                    thread::sleep(RETRY_PERIOD);
+                }
+            }
+            result => return Some(result),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_at_once() {
+        let start = std::time::Instant::now();
+        let mut calls = 0;
+        let result = when_ready("test", || { calls += 1; Ok::<u8, String>(7) }, |_| true);
+        assert_eq!(result, Some(Ok(7)));
+        assert_eq!(calls, 1);
+        assert!(start.elapsed() < RETRY_PERIOD);
+    }
+}
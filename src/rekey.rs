@@ -0,0 +1,167 @@
+use crate::adp::TExtendedAddress;
+
+/// Progress of one device in a GMK rekey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyStatus {
+    Pending,
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// A network wide GMK rekey.
+///
+/// The new GMK is distributed to the devices one at a time over EAP-PSK, the key is activated
+/// once every device is either done or failed. Failed devices keep the old key and have to join again.
+#[derive(Debug)]
+pub struct Rekey {
+    key_index: u8,
+    devices: Vec<(TExtendedAddress, RekeyStatus)>,
+    /// Device being rekeyed and the time (ms since the LbpManager start) at which it is given up
+    current: Option<(TExtendedAddress, u128)>,
+}
+
+impl Rekey {
+    pub fn new(key_index: u8, devices: Vec<TExtendedAddress>) -> Self {
+        Rekey {
+            key_index,
+            devices: devices.into_iter().map(|d| (d, RekeyStatus::Pending)).collect(),
+            current: None,
+        }
+    }
+
+    /// Index the new GMK is distributed under
+    pub fn key_index(&self) -> u8 {
+        self.key_index
+    }
+
+    pub fn status(&self, ext_addr: &TExtendedAddress) -> Option<RekeyStatus> {
+        self.devices.iter().find(|(d, _)| d == ext_addr).map(|(_, s)| *s)
+    }
+
+    pub fn is_in_progress(&self, ext_addr: &TExtendedAddress) -> bool {
+        self.status(ext_addr) == Some(RekeyStatus::InProgress)
+    }
+
+    fn set_status(&mut self, ext_addr: &TExtendedAddress, status: RekeyStatus) {
+        match self.devices.iter_mut().find(|(d, _)| d == ext_addr) {
+            Some(entry) => entry.1 = status,
+            None => self.devices.push((*ext_addr, status)),
+        }
+        if status != RekeyStatus::InProgress && self.current.is_some_and(|(d, _)| d == *ext_addr) {
+            self.current = None;
+        }
+    }
+
+    /// Queues the device again, used when it joins while the new key is being distributed
+    pub fn reset(&mut self, ext_addr: &TExtendedAddress) {
+        self.set_status(ext_addr, RekeyStatus::Pending);
+    }
+
+    /// Picks the next pending device, None while a device is in progress or when none is left
+    pub fn start_next(&mut self, deadline: u128) -> Option<TExtendedAddress> {
+        if self.current.is_some() {
+            return None;
+        }
+        let entry = self.devices.iter_mut().find(|(_, s)| *s == RekeyStatus::Pending)?;
+        entry.1 = RekeyStatus::InProgress;
+        self.current = Some((entry.0, deadline));
+        Some(entry.0)
+    }
+
+    /// The device in progress, if its deadline passed
+    pub fn timed_out(&self, now: u128) -> Option<TExtendedAddress> {
+        self.current.filter(|(_, deadline)| now >= *deadline).map(|(d, _)| d)
+    }
+
+    pub fn done(&mut self, ext_addr: &TExtendedAddress) {
+        self.set_status(ext_addr, RekeyStatus::Done);
+    }
+
+    pub fn failed(&mut self, ext_addr: &TExtendedAddress) {
+        if self.status(ext_addr).is_some() {
+            self.set_status(ext_addr, RekeyStatus::Failed);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.devices
+            .iter()
+            .all(|(_, s)| *s == RekeyStatus::Done || *s == RekeyStatus::Failed)
+    }
+
+    pub fn failed_devices(&self) -> Vec<TExtendedAddress> {
+        self.devices
+            .iter()
+            .filter(|(_, s)| *s == RekeyStatus::Failed)
+            .map(|(d, _)| *d)
+            .collect()
+    }
+
+    pub fn done_devices(&self) -> Vec<TExtendedAddress> {
+        self.devices
+            .iter()
+            .filter(|(_, s)| *s == RekeyStatus::Done)
+            .map(|(d, _)| *d)
+            .collect()
+    }
+
+    /// (done, total) count of devices
+    pub fn progress(&self) -> (usize, usize) {
+        let done = self.devices.iter().filter(|(_, s)| *s == RekeyStatus::Done).count();
+        (done, self.devices.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(last: u8) -> TExtendedAddress {
+        TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, last])
+    }
+
+    #[test]
+    fn one_device_at_a_time() {
+        let mut rekey = Rekey::new(1, vec![device(1), device(2), device(3)]);
+        assert_eq!(rekey.start_next(100), Some(device(1)));
+        assert_eq!(rekey.start_next(100), None);
+        assert!(rekey.is_in_progress(&device(1)));
+        rekey.done(&device(1));
+        assert_eq!(rekey.progress(), (1, 3));
+
+        assert_eq!(rekey.start_next(200), Some(device(2)));
+        assert_eq!(rekey.timed_out(199), None);
+        assert_eq!(rekey.timed_out(200), Some(device(2)));
+        rekey.failed(&device(2));
+        assert_eq!(rekey.status(&device(2)), Some(RekeyStatus::Failed));
+        assert!(!rekey.is_finished());
+
+        //A device joining again while it is rekeyed is queued again
+        assert_eq!(rekey.start_next(300), Some(device(3)));
+        rekey.reset(&device(3));
+        assert_eq!(rekey.status(&device(3)), Some(RekeyStatus::Pending));
+        assert_eq!(rekey.start_next(400), Some(device(3)));
+        assert!(!rekey.is_finished());
+        rekey.done(&device(3));
+
+        assert!(rekey.is_finished());
+        assert_eq!(rekey.start_next(500), None);
+        assert_eq!(rekey.done_devices(), vec![device(1), device(3)]);
+        assert_eq!(rekey.failed_devices(), vec![device(2)]);
+        assert_eq!(rekey.progress(), (2, 3));
+    }
+
+    #[test]
+    fn resumed_devices_are_skipped() {
+        let mut rekey = Rekey::new(0, vec![device(1)]);
+        rekey.done(&device(2));
+        rekey.failed(&device(3));
+        assert_eq!(rekey.status(&device(3)), None);
+        assert_eq!(rekey.start_next(100), Some(device(1)));
+        assert_eq!(rekey.start_next(100), None);
+        rekey.done(&device(1));
+        assert!(rekey.is_finished());
+        assert_eq!(rekey.progress(), (2, 2));
+    }
+}
@@ -72,6 +72,10 @@ impl AdpLbpRequest {
     pub fn new(dst_addr: TAddress, data: Vec<u8>, handle: u8, max_hops: u8, discover_route: bool, quality_of_service:u8, security_enable: bool) -> AdpLbpRequest {
         AdpLbpRequest { dst_addr, data, handle, max_hops, discover_route, quality_of_service, security_enable }
     }
+    /// The LBP message carried by the request
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 impl Into<usi::OutMessage> for AdpLbpRequest {
@@ -301,4 +305,75 @@ impl Into<usi::OutMessage> for AdpJoinNetworkRequest {
         }
         OutMessage::new(common::PROTOCOL_ADP_G3, &v.to_vec())
     }
-}
\ No newline at end of file
+}
+#[derive(Debug, Default)]
+pub struct AdpNetworkLeaveRequest {}
+
+impl AdpNetworkLeaveRequest {
+    pub fn new() -> Self {
+        AdpNetworkLeaveRequest {}
+    }
+}
+
+impl Into<usi::OutMessage> for AdpNetworkLeaveRequest {
+    fn into(self) -> usi::OutMessage {
+        let v = [adp::G3_SERIAL_MSG_ADP_NETWORK_LEAVE_REQUEST];
+        OutMessage::new(common::PROTOCOL_ADP_G3, &v.to_vec())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AdpResetRequest {}
+
+impl AdpResetRequest {
+    pub fn new() -> Self {
+        AdpResetRequest {}
+    }
+}
+
+impl Into<usi::OutMessage> for AdpResetRequest {
+    fn into(self) -> usi::OutMessage {
+        let v = [adp::G3_SERIAL_MSG_ADP_RESET_REQUEST];
+        OutMessage::new(common::PROTOCOL_ADP_G3, &v.to_vec())
+    }
+}
+
+#[derive(Debug)]
+pub struct AdpRouteDiscoveryRequest {
+    dst_addr: u16,
+    max_hops: u8,
+}
+
+impl AdpRouteDiscoveryRequest {
+    pub fn new(dst_addr: u16, max_hops: u8) -> Self {
+        AdpRouteDiscoveryRequest { dst_addr, max_hops }
+    }
+}
+
+impl Into<usi::OutMessage> for AdpRouteDiscoveryRequest {
+    fn into(self) -> usi::OutMessage {
+        let dst_addr_v = self.dst_addr.to_be_bytes();
+        let v = [adp::G3_SERIAL_MSG_ADP_ROUTE_DISCOVERY_REQUEST, dst_addr_v[0], dst_addr_v[1], self.max_hops];
+        OutMessage::new(common::PROTOCOL_ADP_G3, &v.to_vec())
+    }
+}
+
+#[derive(Debug)]
+pub struct AdpPathDiscoveryRequest {
+    dst_addr: u16,
+    metric_type: u8,
+}
+
+impl AdpPathDiscoveryRequest {
+    pub fn new(dst_addr: u16, metric_type: u8) -> Self {
+        AdpPathDiscoveryRequest { dst_addr, metric_type }
+    }
+}
+
+impl Into<usi::OutMessage> for AdpPathDiscoveryRequest {
+    fn into(self) -> usi::OutMessage {
+        let dst_addr_v = self.dst_addr.to_be_bytes();
+        let v = [adp::G3_SERIAL_MSG_ADP_PATH_DISCOVERY_REQUEST, dst_addr_v[0], dst_addr_v[1], self.metric_type];
+        OutMessage::new(common::PROTOCOL_ADP_G3, &v.to_vec())
+    }
+}
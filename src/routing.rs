@@ -0,0 +1,151 @@
+use std::fmt;
+use std::thread;
+
+use crate::adp::{EAdpPibAttribute, TAdpRoutingTableEntry};
+use crate::adp_client::{AdpClient, PibError};
+use crate::device_registry::DeviceRegistry;
+use crate::path_discovery::{DiscoveryError, PathTarget};
+use crate::pib::PibValue;
+use crate::ready;
+
+/// adpMaxHops default of the G3 specification
+pub const DEFAULT_MAX_HOPS: u8 = 8;
+
+#[derive(Debug)]
+pub enum RoutingError {
+    Discovery(DiscoveryError),
+    Pib(PibError),
+}
+
+impl fmt::Display for RoutingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingError::Discovery(e) => write!(f, "{}", e),
+            RoutingError::Pib(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RoutingError {}
+
+impl From<DiscoveryError> for RoutingError {
+    fn from(e: DiscoveryError) -> Self {
+        RoutingError::Discovery(e)
+    }
+}
+
+impl From<PibError> for RoutingError {
+    fn from(e: PibError) -> Self {
+        RoutingError::Pib(e)
+    }
+}
+
+/// Runs an ADP route discovery to the target, the route found replaces the one of the routing table.
+/// Gives the short address of the target.
+pub fn discover_route(client: &AdpClient, registry: &DeviceRegistry, target: &PathTarget, max_hops: u8) -> Result<u16, DiscoveryError> {
+    let dst_addr = target.resolve(registry).ok_or(DiscoveryError::UnknownTarget(*target))?;
+    log::info!("Route discovery to {:04X}, max hops {}", dst_addr, max_hops);
+    client.route_discovery(dst_addr, max_hops)?.wait()?;
+    Ok(dst_addr)
+}
+
+/// Reads the ADP_IB_MANUF_ROUTING_TABLE_COUNT entries of ADP_IB_ROUTING_TABLE, one get at a time
+pub fn read_routing_table(client: &AdpClient) -> Result<Vec<TAdpRoutingTableEntry>, PibError> {
+    let count = match client.get(EAdpPibAttribute::ADP_IB_MANUF_ROUTING_TABLE_COUNT, 0)?.wait()?.value() {
+        Ok(PibValue::U16(count)) => count,
+        other => return Err(PibError::InvalidValue(format!("invalid routing table count {:?}", other))),
+    };
+    (0..count)
+        .map(|idx| match client.get(EAdpPibAttribute::ADP_IB_ROUTING_TABLE, idx)?.wait()?.value() {
+            Ok(PibValue::Routing(entry)) => Ok(entry),
+            other => Err(PibError::InvalidValue(format!("invalid routing table entry {:?}", other))),
+        })
+        .collect()
+}
+
+/// The routing table printed one route per line, the destinations known to the registry with their EUI-64
+pub struct RoutingTable<'a> {
+    entries: &'a [TAdpRoutingTableEntry],
+    registry: &'a DeviceRegistry,
+}
+
+impl<'a> RoutingTable<'a> {
+    pub fn new(entries: &'a [TAdpRoutingTableEntry], registry: &'a DeviceRegistry) -> Self {
+        RoutingTable { entries, registry }
+    }
+}
+
+impl fmt::Display for RoutingTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "routing table, {} routes", self.entries.len())?;
+        writeln!(f, "dst   next hop  cost  hops  weak links  valid (min)")?;
+        for entry in self.entries {
+            write!(
+                f,
+                "{:04X}  {:04X}      {:>4}  {:>4}  {:>10}  {:>11}",
+                entry.dst_addr, entry.next_hop_addr, entry.route_cost, entry.hop_count, entry.weak_link_count, entry.valid_time
+            )?;
+            if let Some(device) = self.registry.get_by_short_addr(entry.dst_addr) {
+                write!(f, "  {}", device.ext_addr)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the route discovery of `--discover-route` if any then prints the routing table, once the network is started
+pub fn spawn_routes(client: AdpClient, registry: DeviceRegistry, discover: Option<PathTarget>, max_hops: u8) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let not_ready = |e: &RoutingError| match e {
+            RoutingError::Discovery(e) => e.not_ready(),
+            RoutingError::Pib(e) => ready::pib_not_ready(e),
+        };
+        let routes = || {
+            if let Some(target) = discover {
+                discover_route(&client, &registry, &target, max_hops)?;
+            }
+            Ok(read_routing_table(&client)?)
+        };
+        match ready::when_ready("routing table", routes, not_ready) {
+            Some(Ok(entries)) => println!("{}", RoutingTable::new(&entries, &registry)),
+            Some(Err(e)) => println!("routing table : {}", e),
+            None => println!("routing table : network not ready"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp_client;
+    use crate::pib::PibAttribute;
+
+    #[test]
+    fn read_table() {
+        let table = vec![
+            TAdpRoutingTableEntry { dst_addr: 5, next_hop_addr: 2, route_cost: 40, hop_count: 2, weak_link_count: 1, valid_time: 300 },
+            TAdpRoutingTableEntry { dst_addr: 2, next_hop_addr: 2, route_cost: 15, hop_count: 1, weak_link_count: 0, valid_time: 360 },
+        ];
+        let entries = table.clone();
+        let (client, modem) = adp_client::fake_modem(table.len() + 1, move |attribute, idx| match attribute {
+            PibAttribute::Adp(EAdpPibAttribute::ADP_IB_MANUF_ROUTING_TABLE_COUNT) => Ok(PibValue::U16(entries.len() as u16).encode()),
+            _ => Ok((&entries[idx as usize]).into()),
+        });
+        assert_eq!(read_routing_table(&client).unwrap(), table);
+        modem.join().unwrap();
+
+        let registry = DeviceRegistry::new([0; 6]);
+        let printed = RoutingTable::new(&table, &registry).to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "routing table, 2 routes",
+                "dst   next hop  cost  hops  weak links  valid (min)",
+                "0005  0002        40     2           1          300",
+                "0002  0002        15     1           0          360",
+            ]
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::adp::TExtendedAddress;
+use crate::app_config;
+
+/// Gives the short addresses of the devices accepted by the coordinator.
+pub trait ShortAddrAllocator: fmt::Debug {
+    /// A free short address for the device, None when there is none left
+    fn allocate(&mut self, ext_addr: &TExtendedAddress) -> Option<u16>;
+    /// Marks the address of a device restored from the device database as used.
+    /// Returns false when the device cannot keep it, it then gets a new address on its next join.
+    fn reserve(&mut self, ext_addr: &TExtendedAddress, short_addr: u16) -> bool;
+    /// The address of a removed device can be given again
+    fn release(&mut self, short_addr: u16);
+    /// Called once the short address of the coordinator is known
+    fn set_coord_short_addr(&mut self, _coord_short_addr: u16) {}
+}
+
+/// Addresses are given in order from a block of `size` addresses after `size * coord short address`.
+/// Released addresses are given again once the end of the block is reached, so that an address is not
+/// reused right after its device left.
+#[derive(Debug)]
+pub struct SequentialAllocator {
+    size: u16,
+    first: u16,
+    /// Offset in the block of the next address to try
+    next: u16,
+    used: HashSet<u16>,
+}
+
+impl SequentialAllocator {
+    pub fn new(size: u16) -> Self {
+        SequentialAllocator {
+            size,
+            first: 1,
+            next: 0,
+            used: HashSet::new(),
+        }
+    }
+}
+
+impl ShortAddrAllocator for SequentialAllocator {
+    fn allocate(&mut self, _ext_addr: &TExtendedAddress) -> Option<u16> {
+        for i in 0..self.size {
+            let offset = (self.next + i) % self.size;
+            let short_addr = self.first.checked_add(offset)?;
+            if !self.used.contains(&short_addr) {
+                self.used.insert(short_addr);
+                self.next = (offset + 1) % self.size;
+                return Some(short_addr);
+            }
+        }
+        None
+    }
+
+    fn reserve(&mut self, _ext_addr: &TExtendedAddress, short_addr: u16) -> bool {
+        self.used.insert(short_addr)
+    }
+
+    fn release(&mut self, short_addr: u16) {
+        self.used.remove(&short_addr);
+    }
+
+    fn set_coord_short_addr(&mut self, coord_short_addr: u16) {
+        let first = (self.size as u32) * (coord_short_addr as u32) + 1;
+        match u16::try_from(first + self.size as u32 - 1) {
+            Ok(_) => self.first = first as u16,
+            Err(_) => log::error!("[BS] No short address block for coordinator 0x{:04x}", coord_short_addr),
+        }
+        self.next = 0;
+        log::info!("[BS] Short addresses 0x{:04x}..0x{:04x}", self.first, self.first as u32 + self.size as u32 - 1);
+    }
+}
+
+/// Fixed EUI-64 to short address table.
+/// Devices missing from the table get an address from the fallback allocator, or none without fallback.
+#[derive(Debug)]
+pub struct StaticAllocator {
+    table: HashMap<TExtendedAddress, u16>,
+    fallback: Option<Box<dyn ShortAddrAllocator>>,
+}
+
+impl StaticAllocator {
+    pub fn new(table: HashMap<TExtendedAddress, u16>, fallback: Option<Box<dyn ShortAddrAllocator>>) -> Self {
+        let mut fallback = fallback;
+        //The pinned addresses are never given to other devices
+        if let Some(ref mut fallback) = fallback {
+            for (ext_addr, short_addr) in table.iter() {
+                fallback.reserve(ext_addr, *short_addr);
+            }
+        }
+        StaticAllocator { table, fallback }
+    }
+
+    fn is_pinned(&self, short_addr: u16) -> bool {
+        self.table.values().any(|s| *s == short_addr)
+    }
+}
+
+impl ShortAddrAllocator for StaticAllocator {
+    fn allocate(&mut self, ext_addr: &TExtendedAddress) -> Option<u16> {
+        match self.table.get(ext_addr) {
+            Some(short_addr) => Some(*short_addr),
+            None => self.fallback.as_mut()?.allocate(ext_addr),
+        }
+    }
+
+    fn reserve(&mut self, ext_addr: &TExtendedAddress, short_addr: u16) -> bool {
+        match self.table.get(ext_addr) {
+            Some(pinned) => *pinned == short_addr,
+            None if self.is_pinned(short_addr) => false,
+            None => self.fallback.as_mut().is_some_and(|f| f.reserve(ext_addr, short_addr)),
+        }
+    }
+
+    fn release(&mut self, short_addr: u16) {
+        if !self.is_pinned(short_addr) {
+            if let Some(ref mut fallback) = self.fallback {
+                fallback.release(short_addr);
+            }
+        }
+    }
+
+    fn set_coord_short_addr(&mut self, coord_short_addr: u16) {
+        if let Some(ref mut fallback) = self.fallback {
+            fallback.set_coord_short_addr(coord_short_addr);
+        }
+    }
+}
+
+/// Entries of `static_short_addrs`, the EUI-64 followed by the short address in hex : `00:80:E1:FF:FE:00:00:01 0010`
+fn parse_table(list: &Option<Vec<String>>) -> HashMap<TExtendedAddress, u16> {
+    let mut table = HashMap::new();
+    for s in list.iter().flatten() {
+        let mut fields = s.split_whitespace();
+        let ext_addr = fields.next().and_then(|f| f.parse::<TExtendedAddress>().ok());
+        let short_addr = fields
+            .next()
+            .and_then(|f| u16::from_str_radix(f.trim_start_matches("0x"), 16).ok());
+        match (ext_addr, short_addr) {
+            (Some(ext_addr), Some(short_addr)) => {
+                table.insert(ext_addr, short_addr);
+            }
+            _ => log::error!("[BS] Invalid static_short_addrs entry {}", s),
+        }
+    }
+    table
+}
+
+/// The allocator set by `short_addr_allocation` : "sequential" (default), where the devices of
+/// `static_short_addrs` keep their address, or "static", where only those devices can join.
+pub fn from_config(g3_config: &app_config::G3, size: u16) -> Box<dyn ShortAddrAllocator> {
+    let table = parse_table(&g3_config.static_short_addrs);
+    match g3_config.short_addr_allocation.as_deref() {
+        Some("static") => Box::new(StaticAllocator::new(table, None)),
+        other => {
+            if let Some(other) = other.filter(|o| *o != "sequential") {
+                log::error!("[BS] Unknown short_addr_allocation {}, using sequential", other);
+            }
+            let sequential = Box::new(SequentialAllocator::new(size));
+            if table.is_empty() {
+                sequential
+            } else {
+                Box::new(StaticAllocator::new(table, Some(sequential)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(last: u8) -> TExtendedAddress {
+        TExtendedAddress([0x00, 0x80, 0xE1, 0xFF, 0xFE, 0x00, 0x00, last])
+    }
+
+    #[test]
+    fn sequential_reuse_and_exhaustion() {
+        let mut allocator = SequentialAllocator::new(4);
+        allocator.set_coord_short_addr(1);
+        let given: Vec<Option<u16>> = (1..=5).map(|n| allocator.allocate(&device(n))).collect();
+        assert_eq!(given, vec![Some(0x0005), Some(0x0006), Some(0x0007), Some(0x0008), None]);
+
+        //A released address is given again once the block wrapped
+        allocator.release(0x0006);
+        assert_eq!(allocator.allocate(&device(6)), Some(0x0006));
+        allocator.release(0x0005);
+        allocator.release(0x0008);
+        assert_eq!(allocator.allocate(&device(7)), Some(0x0008));
+        assert_eq!(allocator.allocate(&device(8)), Some(0x0005));
+        assert_eq!(allocator.allocate(&device(9)), None);
+    }
+
+    #[test]
+    fn sequential_reserved_address_is_not_given() {
+        let mut allocator = SequentialAllocator::new(3);
+        assert!(allocator.reserve(&device(1), 0x0002));
+        assert!(!allocator.reserve(&device(2), 0x0002));
+        assert_eq!(allocator.allocate(&device(3)), Some(0x0001));
+        assert_eq!(allocator.allocate(&device(4)), Some(0x0003));
+        assert_eq!(allocator.allocate(&device(5)), None);
+    }
+
+    #[test]
+    fn static_pinning() {
+        let table = HashMap::from([(device(1), 0x0010), (device(2), 0x0001)]);
+        let mut only_static = StaticAllocator::new(table.clone(), None);
+        assert_eq!(only_static.allocate(&device(1)), Some(0x0010));
+        assert_eq!(only_static.allocate(&device(3)), None);
+        assert!(only_static.reserve(&device(1), 0x0010));
+        assert!(!only_static.reserve(&device(1), 0x0011));
+        assert!(!only_static.reserve(&device(3), 0x0020));
+
+        //The pinned addresses are kept out of the fallback, even once released
+        let mut allocator = StaticAllocator::new(table, Some(Box::new(SequentialAllocator::new(3))));
+        assert_eq!(allocator.allocate(&device(2)), Some(0x0001));
+        assert_eq!(allocator.allocate(&device(3)), Some(0x0002));
+        allocator.release(0x0001);
+        assert!(!allocator.reserve(&device(4), 0x0001));
+        assert!(!allocator.reserve(&device(4), 0x0002));
+        assert!(allocator.reserve(&device(4), 0x0003));
+        assert_eq!(allocator.allocate(&device(5)), None);
+        assert_eq!(allocator.allocate(&device(2)), Some(0x0001));
+    }
+}
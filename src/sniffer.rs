@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::adp;
+use crate::common;
+use crate::usi;
+
+const PCAP_MAGIC: u32 = 0xA1B2_C3D4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 65535;
+/// IEEE 802.15.4 frames without FCS, Wireshark hands their payload to the 6LoWPAN dissector
+pub const LINKTYPE_IEEE802_15_4_NOFCS: u32 = 230;
+
+/// Writes the sniffed frames as a pcap capture, each frame flushed at once so that Wireshark shows
+/// the traffic live when reading a named pipe
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Creates the capture file, or opens the named pipe. Opening a pipe waits for its reader,
+    /// e.g. `mkfifo /tmp/g3 && wireshark -k -i /tmp/g3`.
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        PcapWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MAJOR.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MINOR.to_le_bytes())?;
+        //Time zone and timestamp accuracy, always 0
+        writer.write_all(&[0; 8])?;
+        writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_IEEE802_15_4_NOFCS.to_le_bytes())?;
+        writer.flush()?;
+        Ok(PcapWriter { writer })
+    }
+
+    pub fn write_frame(&mut self, time: SystemTime, frame: &[u8]) -> io::Result<()> {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        self.writer.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(frame)?;
+        self.writer.flush()
+    }
+}
+
+/// Writes the MAC sniffer indications received from the USI port to the pcap file or pipe at path.
+/// The frames are timestamped on reception by the host, the clock of the modem is not the wall clock.
+pub fn spawn(path: String, usi_rx: flume::Receiver<usi::Message>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut pcap = match PcapWriter::create(&path) {
+            Ok(pcap) => pcap,
+            Err(e) => {
+                log::error!("Failed to open pcap {} : {}, sniffed frames are not written", path, e);
+                return;
+            }
+        };
+        log::info!("Writing sniffed frames to {}", path);
+        for msg in usi_rx.iter() {
+            let event = match msg {
+                usi::Message::UsiIn(msg) => match adp::usi_message_to_message(&msg) {
+                    Some(adp::Message::AdpG3MacSnifferEvent(event)) => event,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            log::debug!("Sniffed frame, lqi {} : {}", event.lqi, common::to_hex_string(&event.frame));
+            if let Err(e) = pcap.write_frame(SystemTime::now(), &event.frame) {
+                //A pipe fails once its reader is gone
+                log::error!("Failed to write pcap {} : {}, sniffer stopped", path, e);
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn pcap_frames() {
+        let mut bytes = Vec::new();
+        {
+            let mut pcap = PcapWriter::new(&mut bytes).unwrap();
+            let time = SystemTime::UNIX_EPOCH + Duration::from_micros(1_697_539_200_123_456);
+            pcap.write_frame(time, &[0x41, 0xCC, 0x2A]).unwrap();
+        }
+        assert_eq!(
+            bytes,
+            vec![
+                0xD4, 0xC3, 0xB2, 0xA1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0, 230, 0, 0, 0,
+                0x80, 0x64, 0x2E, 0x65, 0x40, 0xE2, 0x01, 0x00, 3, 0, 0, 0, 3, 0, 0, 0, 0x41, 0xCC, 0x2A,
+            ]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Statistics of the modem and of ne-g3, published in the Prometheus text format.
+//!
+//! The MAC and ADP counters of the modem are read every interval through the AdpClient, the host
+//! counters (USI framing, TUN packets, joins) are kept by the threads that count them.
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::adp::{EAdpPibAttribute, EMacWrpPibAttribute};
+use crate::adp_client::{AdpClient, ClientError};
+use crate::device_registry::DeviceRegistry;
+use crate::lbp_manager::DeviceEvent;
+use crate::pib::{PibAttribute, PibValue};
+use crate::usi::FramingStats;
+
+/// Interval of the modem counter reads when the configuration gives none
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// A scrape slower than this is dropped
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Counters of the host side, updated by the threads of the network manager
+#[derive(Debug, Default)]
+pub struct HostStats {
+    /// IPv6 packets read from the TUN interface, sent to the PLC network
+    pub tun_rx_packets: AtomicU64,
+    /// IPv6 packets received from the PLC network, written to the TUN interface
+    pub tun_tx_packets: AtomicU64,
+    pub tun_tx_errors: AtomicU64,
+    pub joins: AtomicU64,
+    pub leaves: AtomicU64,
+    pub kicks: AtomicU64,
+    pub rekeys: AtomicU64,
+    /// Copies of the LbpStats of the coordinator
+    pub lbp_retransmissions: AtomicU64,
+    pub lbp_slot_timeouts: AtomicU64,
+}
+
+impl HostStats {
+    /// Counts the joins, leaves, kicks and rekeys, see NetworkManager::add_device_listener
+    pub fn spawn_device_counter(self: &Arc<Self>, events: flume::Receiver<DeviceEvent>) -> thread::JoinHandle<()> {
+        let stats = self.clone();
+        thread::spawn(move || {
+            for event in events.iter() {
+                let counter = match event {
+                    DeviceEvent::Joined { .. } => &stats.joins,
+                    DeviceEvent::Left { .. } => &stats.leaves,
+                    DeviceEvent::Kicked { .. } => &stats.kicks,
+                    DeviceEvent::RekeyCompleted { .. } => &stats.rekeys,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    }
+}
+
+struct ModemCounter {
+    attribute: PibAttribute,
+    name: &'static str,
+    help: &'static str,
+}
+
+const fn mac(attribute: EMacWrpPibAttribute, name: &'static str, help: &'static str) -> ModemCounter {
+    ModemCounter { attribute: PibAttribute::Mac(attribute), name, help }
+}
+
+const fn adp(attribute: EAdpPibAttribute, name: &'static str, help: &'static str) -> ModemCounter {
+    ModemCounter { attribute: PibAttribute::Adp(attribute), name, help }
+}
+
+/// The counters read from the modem, an attribute the modem does not support is left out of the metrics
+const MODEM_COUNTERS: &[ModemCounter] = &[
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_TX_DATA_PACKET_COUNT, "neg3_mac_tx_data_packets_total", "MAC data frames sent"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_RX_DATA_PACKET_COUNT, "neg3_mac_rx_data_packets_total", "MAC data frames received"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_TX_CMD_PACKET_COUNT, "neg3_mac_tx_cmd_packets_total", "MAC command frames sent"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_RX_CMD_PACKET_COUNT, "neg3_mac_rx_cmd_packets_total", "MAC command frames received"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_TX_DATA_BROADCAST_COUNT, "neg3_mac_tx_data_broadcasts_total", "MAC broadcast data frames sent"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_RX_DATA_BROADCAST_COUNT, "neg3_mac_rx_data_broadcasts_total", "MAC broadcast data frames received"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_CSMA_FAIL_COUNT, "neg3_mac_csma_fails_total", "Frames not sent, the channel stayed busy"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_CSMA_NO_ACK_COUNT, "neg3_mac_csma_no_acks_total", "Frames sent without acknowledgement"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_BAD_CRC_COUNT, "neg3_mac_bad_crc_total", "Frames received with a bad CRC"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_OTHER_DESTINATION_COUNT, "neg3_mac_rx_other_destination_total", "Frames received for another device"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_INVALID_FRAME_LENGTH_COUNT, "neg3_mac_rx_invalid_frame_length_total", "Frames dropped for their length"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_MAC_REPETITION_COUNT, "neg3_mac_rx_repetitions_total", "Frames received again"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_WRONG_ADDR_MODE_COUNT, "neg3_mac_rx_wrong_addr_mode_total", "Frames dropped for their addressing mode"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_UNSUPPORTED_SECURITY_COUNT, "neg3_mac_rx_unsupported_security_total", "Frames dropped for their security level"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_WRONG_KEY_ID_COUNT, "neg3_mac_rx_wrong_key_id_total", "Frames dropped for their key index"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_INVALID_KEY_COUNT, "neg3_mac_rx_invalid_key_total", "Frames dropped for an invalid key"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_WRONG_FC_COUNT, "neg3_mac_rx_wrong_frame_counter_total", "Frames dropped for their frame counter, replays"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_DECRYPTION_ERROR_COUNT, "neg3_mac_rx_decryption_errors_total", "Frames that failed to decrypt"),
+    mac(EMacWrpPibAttribute::MAC_WRP_PIB_MANUF_RX_SEGMENT_DECODE_ERROR_COUNT, "neg3_mac_rx_segment_decode_errors_total", "Segments that failed to decode"),
+    adp(EAdpPibAttribute::ADP_IB_MANUF_QUEUED_ENTRIES_REMOVED_TIMEOUT_COUNT, "neg3_adp_queued_removed_timeout_total", "Frames dropped from the ADP queue on timeout"),
+    adp(EAdpPibAttribute::ADP_IB_MANUF_QUEUED_ENTRIES_REMOVED_ROUTE_ERROR_COUNT, "neg3_adp_queued_removed_route_error_total", "Frames dropped from the ADP queue on route error"),
+    adp(EAdpPibAttribute::ADP_IB_MANUF_CIRCULAR_ROUTES_DETECTED, "neg3_adp_circular_routes_total", "Circular routes detected"),
+    adp(EAdpPibAttribute::ADP_IB_MANUF_ALL_NEIGHBORS_BLACKLISTED_COUNT, "neg3_adp_all_neighbours_blacklisted_total", "Times all the neighbours were blacklisted"),
+];
+
+/// All the statistics, shared by the poller and the exporter
+pub struct Metrics {
+    framing: Arc<FramingStats>,
+    host: Arc<HostStats>,
+    registry: DeviceRegistry,
+    /// Last value of each of MODEM_COUNTERS
+    modem: Mutex<Vec<Option<u64>>>,
+    poll_errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new(framing: Arc<FramingStats>, host: Arc<HostStats>, registry: DeviceRegistry) -> Self {
+        Metrics {
+            framing,
+            host,
+            registry,
+            modem: Mutex::new(vec![None; MODEM_COUNTERS.len()]),
+            poll_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the modem counters once, one get at a time
+    pub fn poll(&self, client: &AdpClient) {
+        for (i, counter) in MODEM_COUNTERS.iter().enumerate() {
+            let value = match counter.attribute {
+                PibAttribute::Adp(attribute) => client.get(attribute, 0).and_then(|c| c.wait()).map(|r| r.value()),
+                PibAttribute::Mac(attribute) => client.mac_get(attribute, 0).and_then(|c| c.wait()).map(|r| r.value()),
+            };
+            let value = match value {
+                Ok(Ok(PibValue::U16(value))) => Some(value as u64),
+                Ok(Ok(PibValue::U32(value))) => Some(value as u64),
+                Err(ClientError::Disconnected) => return,
+                other => {
+                    log::debug!("Failed to read {:?} : {:?}", counter.attribute, other);
+                    self.poll_errors.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            };
+            self.modem.lock().unwrap()[i] = value;
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+        for (counter, value) in MODEM_COUNTERS.iter().zip(self.modem.lock().unwrap().iter()) {
+            if let Some(value) = value {
+                metric(counter.name, "counter", counter.help, *value);
+            }
+        }
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        metric("neg3_modem_poll_errors_total", "counter", "Modem counters that could not be read", load(&self.poll_errors));
+        metric("neg3_usi_frames_total", "counter", "USI frames received from the modem", load(&self.framing.frames));
+        metric("neg3_usi_crc_errors_total", "counter", "USI frames dropped for their CRC", load(&self.framing.crc_errors));
+        metric("neg3_usi_escape_errors_total", "counter", "USI frames dropped for a bad escape", load(&self.framing.escape_errors));
+        metric("neg3_usi_oversize_errors_total", "counter", "USI frames dropped for their size", load(&self.framing.oversize_errors));
+        metric("neg3_tun_rx_packets_total", "counter", "IPv6 packets read from the TUN interface", load(&self.host.tun_rx_packets));
+        metric("neg3_tun_tx_packets_total", "counter", "IPv6 packets written to the TUN interface", load(&self.host.tun_tx_packets));
+        metric("neg3_tun_tx_errors_total", "counter", "IPv6 packets that failed to write to the TUN interface", load(&self.host.tun_tx_errors));
+        metric("neg3_joins_total", "counter", "Devices that completed the bootstrap", load(&self.host.joins));
+        metric("neg3_leaves_total", "counter", "Devices that left the network", load(&self.host.leaves));
+        metric("neg3_kicks_total", "counter", "Devices kicked by the coordinator", load(&self.host.kicks));
+        metric("neg3_rekeys_total", "counter", "Rekeys completed", load(&self.host.rekeys));
+        metric("neg3_lbp_retransmissions_total", "counter", "Bootstrap messages sent again", load(&self.host.lbp_retransmissions));
+        metric("neg3_lbp_slot_timeouts_total", "counter", "Bootstraps given up", load(&self.host.lbp_slot_timeouts));
+        metric("neg3_devices", "gauge", "Devices known to the coordinator", self.registry.len() as u64);
+        out
+    }
+}
+
+/// Reads the modem counters every interval
+pub fn spawn_poller(client: AdpClient, metrics: Arc<Metrics>, interval: Duration) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        metrics.poll(&client);
+    })
+}
+
+/// Serves the metrics on `GET /metrics` over HTTP, gives the address listened to
+pub fn spawn_exporter(listen: &str, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(listen)?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| scrape(stream, &metrics));
+            if let Err(e) = result {
+                log::warn!("Metrics scrape failed : {}", e);
+            }
+        }
+    });
+    Ok(addr)
+}
+
+fn scrape(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", String::from("see /metrics\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp::EAdpStatus;
+    use crate::adp_client;
+
+    #[test]
+    fn poll_and_scrape() {
+        let host = Arc::new(HostStats::default());
+        let metrics = Arc::new(Metrics::new(Arc::new(FramingStats::default()), host.clone(), DeviceRegistry::new([0; 6])));
+        //The ADP counters are not supported
+        let (client, modem) = adp_client::fake_modem(MODEM_COUNTERS.len(), |attribute, _| match attribute {
+            PibAttribute::Mac(attribute) => Ok((u32::from(attribute) & 0xFF).to_be_bytes().to_vec()),
+            PibAttribute::Adp(_) => Err(EAdpStatus::G3_UNSUPPORTED_ATTRIBUTE),
+        });
+        metrics.poll(&client);
+        modem.join().unwrap();
+
+        let (events_tx, events_rx) = flume::unbounded();
+        let counter = host.spawn_device_counter(events_rx);
+        let ext_addr = "00:80:E1:FF:FE:00:00:01".parse().unwrap();
+        events_tx.send(DeviceEvent::Joined { ext_addr, short_addr: 1 }).unwrap();
+        events_tx.send(DeviceEvent::Kicked { ext_addr, short_addr: 1 }).unwrap();
+        drop(events_tx);
+        counter.join().unwrap();
+
+        let addr = spawn_exporter("127.0.0.1:0", metrics).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let lines: Vec<&str> = response.lines().collect();
+        assert_eq!(lines[0], "HTTP/1.1 200 OK");
+        assert!(lines.contains(&"# TYPE neg3_mac_tx_data_packets_total counter"));
+        assert!(lines.contains(&"neg3_mac_tx_data_packets_total 1"));
+        assert!(lines.contains(&"neg3_mac_bad_crc_total 9"));
+        assert!(!response.contains("neg3_adp_circular_routes_total"));
+        assert!(lines.contains(&"neg3_modem_poll_errors_total 4"));
+        assert!(lines.contains(&"neg3_joins_total 1"));
+        assert!(lines.contains(&"neg3_kicks_total 1"));
+        assert!(lines.contains(&"neg3_devices 0"));
+    }
+}
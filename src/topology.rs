@@ -0,0 +1,383 @@
+//! Map of the network as seen by the modem of ne-g3: the routing table, the neighbour and POS tables,
+//! the path discoveries and the device registry combined into nodes and edges, exported as GraphViz
+//! DOT or JSON.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::Ipv6Addr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use crate::adp::{EMacWrpPibAttribute, TAdpPanDescriptor, TAdpRoutingTableEntry, TExtendedAddress, TPathDescriptor};
+use crate::adp_client::{AdpClient, PibError};
+use crate::app_config;
+use crate::common;
+use crate::device_registry::DeviceRegistry;
+use crate::neighbours;
+use crate::path_discovery::{self, DiscoveryError, PathTarget};
+use crate::pib::{PibValue, TMacNeighbourEntry, TMacPosEntry};
+use crate::ready;
+use crate::routing;
+
+/// Short address of the coordinator of a G3 PAN
+const COORD_SHORT_ADDR: u16 = 0x0000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub short_addr: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext_addr: Option<TExtendedAddress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<Ipv6Addr>,
+    /// The node of the modem of ne-g3
+    pub root: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// A neighbour heard by the root, from the neighbour and POS tables or a PAN descriptor
+    Neighbour,
+    /// A route of the routing table, to the destination through next_hop
+    Route,
+    /// A hop of a path discovery
+    Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_cost: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lqi: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hop_count: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_hop: Option<u16>,
+}
+
+impl Edge {
+    fn new(from: u16, to: u16, kind: EdgeKind) -> Self {
+        Edge { from, to, kind, link_cost: None, lqi: None, hop_count: None, next_hop: None }
+    }
+}
+
+#[derive(Debug)]
+pub struct Topology {
+    pub root: u16,
+    nodes: BTreeMap<u16, Node>,
+    edges: Vec<Edge>,
+    registry: DeviceRegistry,
+    /// ula_net_prefix and ula_host_prefix, the ULA of a node is followed by its short address
+    ula_prefix: Option<([u8; 8], [u8; 6])>,
+}
+
+impl Topology {
+    /// An empty map around root, the short address of the modem. The nodes found in the registry get their EUI-64.
+    pub fn new(root: u16, registry: &DeviceRegistry, network: Option<&app_config::Network>) -> Self {
+        let mut topology = Topology {
+            root,
+            nodes: BTreeMap::new(),
+            edges: Vec::new(),
+            registry: registry.clone(),
+            ula_prefix: network.map(|n| (n.ula_net_prefix, n.ula_host_prefix)),
+        };
+        topology.node(root).root = true;
+        topology
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    fn node(&mut self, short_addr: u16) -> &mut Node {
+        let ext_addr = self.registry.get_by_short_addr(short_addr).map(|device| device.ext_addr);
+        let ipv6 = self
+            .ula_prefix
+            .and_then(|(net, host)| app_config::ula_ipv6_addr_from_pan_id_short_addr(&net, &host, 0, short_addr));
+        self.nodes.entry(short_addr).or_insert(Node { short_addr, ext_addr, ipv6, root: false })
+    }
+
+    /// The edge of that kind between the two nodes, added when missing
+    fn edge(&mut self, from: u16, to: u16, kind: EdgeKind) -> &mut Edge {
+        self.node(from);
+        self.node(to);
+        let i = match self.edges.iter().position(|e| e.from == from && e.to == to && e.kind == kind) {
+            Some(i) => i,
+            None => {
+                self.edges.push(Edge::new(from, to, kind));
+                self.edges.len() - 1
+            }
+        };
+        &mut self.edges[i]
+    }
+
+    /// The routes from the root, one edge to each destination with its next hop
+    pub fn add_routes(&mut self, routes: &[TAdpRoutingTableEntry]) {
+        let root = self.root;
+        for route in routes {
+            self.node(route.next_hop_addr);
+            let edge = self.edge(root, route.dst_addr, EdgeKind::Route);
+            edge.link_cost = Some(route.route_cost);
+            edge.hop_count = Some(route.hop_count);
+            edge.next_hop = Some(route.next_hop_addr);
+        }
+    }
+
+    pub fn add_neighbours(&mut self, neighbours: &[TMacNeighbourEntry]) {
+        let root = self.root;
+        for neighbour in neighbours {
+            self.edge(root, neighbour.short_addr, EdgeKind::Neighbour).lqi = Some(neighbour.lqi);
+        }
+    }
+
+    /// The neighbours of the POS table, the LQI of the neighbour table wins
+    pub fn add_pos(&mut self, pos: &[TMacPosEntry]) {
+        let root = self.root;
+        for entry in pos {
+            self.edge(root, entry.short_addr, EdgeKind::Neighbour).lqi.get_or_insert(entry.lqi);
+        }
+    }
+
+    /// The forward path from the root to the destination and the reverse path back, hop by hop
+    pub fn add_path(&mut self, path: &TPathDescriptor) {
+        for (start, hops) in [(self.root, &path.forward_path), (path.dst_addr, &path.reverse_path)] {
+            let mut from = start;
+            for (i, hop) in hops.iter().enumerate() {
+                let edge = self.edge(from, hop.hop_address, EdgeKind::Path);
+                edge.link_cost = Some(hop.link_cost as u16);
+                edge.hop_count = Some(i as u8 + 1);
+                from = hop.hop_address;
+            }
+        }
+    }
+
+    /// The PANs heard by a device on its discovery: its LBA with the link quality, and the route cost from
+    /// the LBA to the coordinator
+    pub fn add_pan_descriptors(&mut self, descriptors: &[TAdpPanDescriptor]) {
+        let root = self.root;
+        for descriptor in descriptors {
+            self.edge(root, descriptor.lba_address, EdgeKind::Neighbour).lqi = Some(descriptor.link_quality);
+            if descriptor.lba_address != COORD_SHORT_ADDR {
+                self.edge(descriptor.lba_address, COORD_SHORT_ADDR, EdgeKind::Route).link_cost = Some(descriptor.rc_coord);
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct View<'a> {
+            root: u16,
+            nodes: Vec<&'a Node>,
+            edges: &'a [Edge],
+        }
+        let view = View { root: self.root, nodes: self.nodes().collect(), edges: self.edges() };
+        serde_json::to_string_pretty(&view).unwrap_or_default()
+    }
+
+    /// GraphViz digraph: the neighbours dotted and undirected, the routes dashed, the hops of the paths solid
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph g3 {\n    node [shape=box];\n");
+        for node in self.nodes() {
+            let mut label = format!("{:04X}", node.short_addr);
+            if let Some(ext_addr) = node.ext_addr {
+                let _ = write!(label, "\\n{}", ext_addr);
+            }
+            if let Some(ipv6) = node.ipv6 {
+                let _ = write!(label, "\\n{}", ipv6);
+            }
+            let shape = if node.root { ", shape=doubleoctagon" } else { "" };
+            let _ = writeln!(out, "    \"{:04X}\" [label=\"{}\"{}];", node.short_addr, label, shape);
+        }
+        for edge in self.edges() {
+            let mut label = Vec::new();
+            if let Some(next_hop) = edge.next_hop {
+                label.push(format!("via {:04X}", next_hop));
+            }
+            if let Some(link_cost) = edge.link_cost {
+                label.push(format!("cost {}", link_cost));
+            }
+            if let (Some(hop_count), EdgeKind::Route) = (edge.hop_count, edge.kind) {
+                label.push(format!("{} hops", hop_count));
+            }
+            if let Some(lqi) = edge.lqi {
+                label.push(format!("lqi {}", lqi));
+            }
+            let style = match edge.kind {
+                EdgeKind::Neighbour => "dotted, dir=none",
+                EdgeKind::Route => "dashed",
+                EdgeKind::Path => "solid",
+            };
+            let _ = writeln!(
+                out,
+                "    \"{:04X}\" -> \"{:04X}\" [label=\"{}\", style={}];",
+                edge.from,
+                edge.to,
+                label.join(", "),
+                style
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Reads the tables of the modem into a topology, with the PAN descriptors of the network discovery
+/// of the modem mode. With paths, every route destination also gets a path discovery, the destinations
+/// that do not answer are left with their route only.
+pub fn collect(
+    client: &AdpClient,
+    registry: &DeviceRegistry,
+    network: Option<&app_config::Network>,
+    pan_descriptors: &[TAdpPanDescriptor],
+    paths: bool,
+) -> Result<Topology, PibError> {
+    let root = match client.mac_get(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS, 0)?.wait()?.value() {
+        Ok(PibValue::U16(short_addr)) => short_addr,
+        other => return Err(PibError::InvalidValue(format!("invalid short address {:?}", other))),
+    };
+    let mut topology = Topology::new(root, registry, network);
+    let routes = routing::read_routing_table(client)?;
+    topology.add_routes(&routes);
+    topology.add_neighbours(&neighbours::read_neighbour_table(client)?);
+    topology.add_pos(&neighbours::read_pos_table(client)?);
+    topology.add_pan_descriptors(pan_descriptors);
+    if paths {
+        for route in &routes {
+            let target = PathTarget::Short(route.dst_addr);
+            match path_discovery::trace(client, registry, &target, path_discovery::DEFAULT_METRIC_TYPE) {
+                Ok(path) => topology.add_path(&path),
+                Err(DiscoveryError::Client(e)) if ready::network_not_ready(&e) => return Err(e.into()),
+                Err(e) => log::warn!("Topology, no path to {} : {}", target, e),
+            }
+        }
+    }
+    Ok(topology)
+}
+
+/// Writes the topology to path, JSON for a `.json` file and GraphViz DOT otherwise, `-` prints it
+fn export(topology: &Topology, path: &str) -> std::io::Result<()> {
+    let content = if path.ends_with(".json") { topology.to_json() } else { topology.to_dot() };
+    if path == "-" {
+        println!("{}", content);
+        return Ok(());
+    }
+    //A reader never sees half a map
+    common::write_atomic(Path::new(path), content.as_bytes())
+}
+
+/// Exports the topology of `--topology` once the network is started, then every interval if any.
+/// pan_descriptors are those of the last network discovery, `AppManager::pan_descriptors`.
+pub fn spawn_topology(
+    client: AdpClient,
+    registry: DeviceRegistry,
+    network: app_config::Network,
+    pan_descriptors: Arc<Mutex<Vec<TAdpPanDescriptor>>>,
+    path: String,
+    interval: Option<Duration>,
+    paths: bool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let collect = || {
+            let pan_descriptors = pan_descriptors.lock().unwrap().clone();
+            collect(&client, &registry, Some(&network), &pan_descriptors, paths)
+        };
+        let mut result = ready::when_ready("topology", collect, ready::pib_not_ready);
+        loop {
+            match result {
+                Some(Ok(topology)) => match export(&topology, &path) {
+                    Ok(()) => log::info!("Topology, {} nodes, written to {}", topology.nodes().count(), path),
+                    Err(e) => log::error!("Failed to write topology {} : {}", path, e),
+                },
+                Some(Err(e)) => log::error!("Topology : {}", e),
+                None => log::error!("Topology : network not ready"),
+            }
+            match interval {
+                Some(interval) => thread::sleep(interval),
+                None => return,
+            }
+            result = Some(collect());
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adp::THopDescriptor;
+    use crate::adp_client;
+    use crate::pib::PibAttribute;
+
+    #[test]
+    fn build_and_export() {
+        let registry = DeviceRegistry::new([0; 6]);
+        let mut topology = Topology::new(0, &registry, None);
+        topology.add_routes(&[
+            TAdpRoutingTableEntry { dst_addr: 5, next_hop_addr: 2, route_cost: 40, hop_count: 2, weak_link_count: 0, valid_time: 300 },
+            TAdpRoutingTableEntry { dst_addr: 2, next_hop_addr: 2, route_cost: 15, hop_count: 1, weak_link_count: 0, valid_time: 360 },
+        ]);
+        topology.add_pos(&[TMacPosEntry { short_addr: 2, lqi: 0x30, pos_valid_time: 250 }]);
+        topology.add_path(&TPathDescriptor {
+            dst_addr: 5,
+            expected_orig_addr: 0,
+            orig_addr: 0,
+            metric_type: path_discovery::DEFAULT_METRIC_TYPE,
+            forward_path: vec![
+                THopDescriptor { hop_address: 2, mns: 0, link_cost: 10 },
+                THopDescriptor { hop_address: 5, mns: 0, link_cost: 20 },
+            ],
+            reverse_path: vec![
+                THopDescriptor { hop_address: 2, mns: 0, link_cost: 12 },
+                THopDescriptor { hop_address: 0, mns: 0, link_cost: 15 },
+            ],
+        });
+
+        let nodes: Vec<u16> = topology.nodes().map(|n| n.short_addr).collect();
+        assert_eq!(nodes, vec![0, 2, 5]);
+        assert_eq!(topology.edges().len(), 7);
+
+        let dot = topology.to_dot();
+        assert!(dot.starts_with("digraph g3 {\n"));
+        assert!(dot.contains("    \"0000\" [label=\"0000\", shape=doubleoctagon];\n"));
+        assert!(dot.contains("    \"0000\" -> \"0005\" [label=\"via 0002, cost 40, 2 hops\", style=dashed];\n"));
+        assert!(dot.contains("    \"0000\" -> \"0002\" [label=\"lqi 48\", style=dotted, dir=none];\n"));
+        assert!(dot.contains("    \"0002\" -> \"0005\" [label=\"cost 20\", style=solid];\n"));
+        assert!(dot.contains("    \"0002\" -> \"0000\" [label=\"cost 15\", style=solid];\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&topology.to_json()).unwrap();
+        assert_eq!(json["root"], 0);
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert_eq!(json["edges"][0], serde_json::json!({"from": 0, "to": 5, "kind": "route", "link_cost": 40, "hop_count": 2, "next_hop": 2}));
+    }
+
+    #[test]
+    fn pan_descriptors() {
+        //A modem with empty tables
+        let (client, modem) = adp_client::fake_modem(4, |attribute, _| match attribute {
+            PibAttribute::Mac(EMacWrpPibAttribute::MAC_WRP_PIB_SHORT_ADDRESS) => Ok(PibValue::U16(7).encode()),
+            _ => Ok(PibValue::U16(0).encode()),
+        });
+        let registry = DeviceRegistry::new([0; 6]);
+        let descriptors = [TAdpPanDescriptor { pan_id: 0x781D, link_quality: 0x90, lba_address: 3, rc_coord: 25 }];
+        let topology = collect(&client, &registry, None, &descriptors, false).unwrap();
+        modem.join().unwrap();
+        assert_eq!(
+            topology.edges(),
+            &[
+                Edge { lqi: Some(0x90), ..Edge::new(7, 3, EdgeKind::Neighbour) },
+                Edge { link_cost: Some(25), ..Edge::new(3, 0, EdgeKind::Route) },
+            ]
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::modem_sim::{ModemSim, SimConfig};
+use crate::plc_medium::{MediumConfig, VirtualMedium};
+use crate::usi_capture::ReplayReader;
+
+/// Read timeout of the transports, the USI reader thread loops on it
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+/// First wait before reopening a failed link, doubled after every failed attempt up to RECONNECT_MAX
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Link to the modem the USI frames go through, set by `serial.name` (or -d) :
+///
+/// - `/dev/ttyUSB0` or `serial:/dev/ttyUSB0`, a serial port at `serial.speed`
+/// - `tcp://host:port`, e.g. a serial to Ethernet bridge
+/// - `udp://host:port`, optionally `udp://host:port?bind=0.0.0.0:port` for the local address
+/// - `pty:/path`, a pseudo terminal, e.g. the end of a `socat` pair or a simulator
+/// - `replay:/path`, the frames received in a capture file (see usi_capture), what is sent is dropped
+/// - `sim:` or `sim:?<options>`, a simulated modem run in process (see modem_sim::SimConfig for the options)
+/// - `pan:` or `pan:?<options>`, a simulated coordinator modem run in process with a PAN of devices joining it
+///   (see plc_medium::MediumConfig for the options)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Serial { name: String },
+    Tcp { addr: String },
+    Udp { remote: String, bind: String },
+    Pty { path: String },
+    Replay { path: String },
+    Sim { options: String },
+    Pan { options: String },
+}
+
+impl FromStr for Transport {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return Ok(Transport::Tcp { addr: addr.to_string() });
+        }
+        if let Some(addr) = s.strip_prefix("udp://") {
+            let (remote, bind) = match addr.split_once("?bind=") {
+                Some((remote, bind)) => (remote, bind),
+                None => (addr, "0.0.0.0:0"),
+            };
+            return Ok(Transport::Udp { remote: remote.to_string(), bind: bind.to_string() });
+        }
+        if let Some(path) = s.strip_prefix("pty:") {
+            return Ok(Transport::Pty { path: path.to_string() });
+        }
+        if let Some(path) = s.strip_prefix("replay:") {
+            return Ok(Transport::Replay { path: path.to_string() });
+        }
+        if let Some(options) = s.strip_prefix("sim:") {
+            return Ok(Transport::Sim { options: options.trim_start_matches('?').to_string() });
+        }
+        if let Some(options) = s.strip_prefix("pan:") {
+            return Ok(Transport::Pan { options: options.trim_start_matches('?').to_string() });
+        }
+        let name = s.strip_prefix("serial:").unwrap_or(s);
+        if name.is_empty() {
+            return Err(format!("invalid transport {}", s));
+        }
+        Ok(Transport::Serial { name: name.to_string() })
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Serial { name } => write!(f, "serial:{}", name),
+            Transport::Tcp { addr } => write!(f, "tcp://{}", addr),
+            Transport::Udp { remote, bind } => write!(f, "udp://{}?bind={}", remote, bind),
+            Transport::Pty { path } => write!(f, "pty:{}", path),
+            Transport::Replay { path } => write!(f, "replay:{}", path),
+            Transport::Sim { options } => write!(f, "sim:?{}", options),
+            Transport::Pan { options } => write!(f, "pan:?{}", options),
+        }
+    }
+}
+
+pub type TransportReader = Box<dyn Read + Send>;
+pub type TransportWriter = Box<dyn Write + Send>;
+
+/// One datagram per USI frame, the socket is connected to the remote end
+struct UdpLink(UdpSocket);
+
+impl Read for UdpLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for UdpLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A read timeout is reported as TimedOut, as the serial port does
+struct TimeoutReader<R>(R);
+
+impl<R: Read> Read for TimeoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::Error::new(io::ErrorKind::TimedOut, e)),
+            Ok(0) if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+            r => r,
+        }
+    }
+}
+
+/// In process end of the link to the simulated modem
+struct ChannelReader {
+    rx: flume::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = match self.rx.recv_timeout(READ_TIMEOUT) {
+                Ok(data) => data,
+                Err(flume::RecvTimeoutError::Timeout) => return Err(io::Error::new(io::ErrorKind::TimedOut, "no data")),
+                Err(flume::RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "simulator stopped"))
+                }
+            };
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+struct ChannelWriter(flume::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "simulator stopped"))?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn channel_pair() -> (ChannelReader, ChannelWriter) {
+    let (tx, rx) = flume::unbounded();
+    (ChannelReader { rx, pending: Vec::new() }, ChannelWriter(tx))
+}
+
+/// Puts a terminal in raw mode, without it the line discipline rewrites or echoes bytes of the USI frames
+pub(crate) fn make_raw(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Reopens the link through Transport::open when a read fails, e.g. a dropped TCP bridge or a serial
+/// adapter enumerated again. The writer of the new link replaces the one of SharedWriter.
+struct ReconnectingReader {
+    transport: Transport,
+    speed: u32,
+    reader: TransportReader,
+    writer: Arc<Mutex<TransportWriter>>,
+}
+
+impl ReconnectingReader {
+    fn reopen(&mut self) {
+        let mut wait = RECONNECT_MIN;
+        loop {
+            thread::sleep(wait);
+            match self.transport.open(self.speed) {
+                Ok((reader, writer)) => {
+                    log::info!("Link {} reopened", self.transport);
+                    self.reader = reader;
+                    *self.writer.lock().unwrap() = writer;
+                    return;
+                }
+                Err(e) => {
+                    wait = (wait * 2).min(RECONNECT_MAX);
+                    log::warn!("Failed to reopen {} : {}, next attempt in {:?}", self.transport, e, wait);
+                }
+            }
+        }
+    }
+}
+
+impl Read for ReconnectingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reader.read(buf) {
+            Err(e) if e.kind() != io::ErrorKind::TimedOut => {
+                log::warn!("Link {} failed : {}, reopening", self.transport, e);
+                self.reopen();
+                Err(io::Error::new(io::ErrorKind::TimedOut, "link reopened"))
+            }
+            r => r,
+        }
+    }
+}
+
+struct SharedWriter(Arc<Mutex<TransportWriter>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+impl Transport {
+    /// Opens the link like open, a link that fails afterwards is opened again with backoff.
+    /// The replay and the simulators are opened once, opening them again would restart them.
+    pub fn open_reconnecting(&self, speed: u32) -> io::Result<(TransportReader, TransportWriter)> {
+        let (reader, writer) = self.open(speed)?;
+        match self {
+            Transport::Replay { .. } | Transport::Sim { .. } | Transport::Pan { .. } => Ok((reader, writer)),
+            _ => {
+                let writer = Arc::new(Mutex::new(writer));
+                let reader = ReconnectingReader { transport: self.clone(), speed, reader, writer: writer.clone() };
+                Ok((Box::new(reader), Box::new(SharedWriter(writer))))
+            }
+        }
+    }
+
+    /// Opens the link, the reader and the writer can be used from different threads
+    pub fn open(&self, speed: u32) -> io::Result<(TransportReader, TransportWriter)> {
+        match self {
+            Transport::Serial { name } => {
+                let port = serialport::new(name, speed).timeout(READ_TIMEOUT).open()?;
+                let writer = port.try_clone()?;
+                Ok((Box::new(port), Box::new(writer)))
+            }
+            Transport::Tcp { addr } => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                let writer = stream.try_clone()?;
+                Ok((Box::new(TimeoutReader(stream)), Box::new(writer)))
+            }
+            Transport::Udp { remote, bind } => {
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(remote)?;
+                socket.set_read_timeout(Some(READ_TIMEOUT))?;
+                let writer = socket.try_clone()?;
+                Ok((Box::new(TimeoutReader(UdpLink(socket))), Box::new(UdpLink(writer))))
+            }
+            Transport::Pty { path } => {
+                let file = OpenOptions::new().read(true).write(true).open(path)?;
+                make_raw(file.as_raw_fd())?;
+                let writer = file.try_clone()?;
+                Ok((Box::new(TimeoutReader(file)), Box::new(writer)))
+            }
+            Transport::Replay { path } => Ok((Box::new(ReplayReader::open(path)?), Box::new(io::sink()))),
+            Transport::Sim { options } => {
+                let config: SimConfig = options
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let (reader, sim_writer) = channel_pair();
+                let (sim_reader, writer) = channel_pair();
+                thread::spawn(move || {
+                    if let Err(e) = ModemSim::new(config).serve(sim_reader, sim_writer) {
+                        log::info!("[SIM] Stopped : {}", e);
+                    }
+                });
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            Transport::Pan { options } => {
+                let config: MediumConfig = options
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let (reader, medium_writer) = channel_pair();
+                let (medium_reader, writer) = channel_pair();
+                thread::spawn(move || {
+                    let (medium, host) = VirtualMedium::from_config(&config, Instant::now());
+                    if let Err(e) = medium.serve(host, medium_reader, medium_writer) {
+                        log::info!("[MEDIUM] Stopped : {}", e);
+                    }
+                });
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn tcp_link_is_reopened() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = Transport::Tcp { addr: listener.local_addr().unwrap().to_string() };
+        let (mut reader, mut writer) = transport.open_reconnecting(0).unwrap();
+        let read = |reader: &mut TransportReader| {
+            let mut buf = [0u8; 16];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(n) => return buf[..n].to_vec(),
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(e) => panic!("read failed {}", e),
+                }
+            }
+        };
+
+        let (mut bridge, _) = listener.accept().unwrap();
+        bridge.write_all(&[0x7E, 0x01]).unwrap();
+        assert_eq!(read(&mut reader), vec![0x7E, 0x01]);
+        drop(bridge);
+
+        let accept = thread::spawn(move || listener.accept().unwrap().0);
+        let mut buf = [0u8; 16];
+        while !matches!(reader.read(&mut buf), Err(ref e) if e.to_string() == "link reopened") {}
+        let mut bridge = accept.join().unwrap();
+        bridge.write_all(&[0x7D, 0x5E]).unwrap();
+        assert_eq!(read(&mut reader), vec![0x7D, 0x5E]);
+        writer.write_all(&[0x02]).unwrap();
+        let mut sent = [0u8; 1];
+        bridge.read_exact(&mut sent).unwrap();
+        assert_eq!(sent, [0x02]);
+    }
+
+    #[test]
+    fn pty_is_raw() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+        //A pty with the default line discipline, which turns CR into LF
+        let (mut master, name) = unsafe {
+            let (mut master, mut slave) = (0, 0);
+            let mut name = [0 as libc::c_char; 128];
+            assert_eq!(libc::openpty(&mut master, &mut slave, name.as_mut_ptr(), std::ptr::null_mut(), std::ptr::null_mut()), 0);
+            libc::close(slave);
+            let name = std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+            (File::from_raw_fd(master), name)
+        };
+        let transport = Transport::Pty { path: name };
+        let (mut reader, _writer) = transport.open(0).unwrap();
+        //Ends with LF so that a cooked pty hands every byte over as well
+        master.write_all(&[0x7E, 0x0D, 0x7D, 0x5E, 0x0A]).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 16];
+        while received.len() < 5 {
+            match reader.read(&mut buf) {
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => panic!("read failed {}", e),
+            }
+        }
+        assert_eq!(received, vec![0x7E, 0x0D, 0x7D, 0x5E, 0x0A]);
+    }
+}
@@ -1,7 +1,11 @@
 use log::{trace, warn};
 use std::{
-    collections::VecDeque,
     io::{Read, Write},
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
     time::{Duration, SystemTime},
 };
@@ -9,6 +13,7 @@ use std::{
 use crate::common::{self, array_to_hex_string, to_hex_string, PROTOCOL_PRIME_API};
 use crate::crc;
 use crate::usi;
+use crate::usi_capture::{Direction, Recorder};
 
 // use crossbeam_channel::{bounded, Sender};
 
@@ -29,17 +34,31 @@ pub trait MessageHandler {
     fn process(&mut self, msg: usi::Message) -> bool;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramingError {
+    Crc,
+    /// ESC followed by ESC or by a delimiter
+    Escape,
+    /// More than MAX_PAYLOAD_LEN bytes of payload
+    Oversize,
+}
+
 #[derive(Clone, Debug)]
 enum RxState {
     RxIdle, // Inactive
     RxMsg,  // Receiving message
     RxEsc,  // Processing escape char
-    RxError,
+    RxError(FramingError),
     RxDone,
 }
 
 pub const RECEIVE_TIMEOUT: Duration = Duration::from_millis(10);
 
+/// Largest payload accepted from the modem, longer frames are dropped
+pub const MAX_PAYLOAD_LEN: usize = 4096;
+/// Header, payload and the longest CRC (CRC32)
+const MAX_FRAME_LEN: usize = 2 + MAX_PAYLOAD_LEN + 4;
+
 #[derive(Debug)]
 pub struct OutMessage {
     protocol: u8,
@@ -53,6 +72,13 @@ impl OutMessage {
             data: data.to_vec(),
         }
     }
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+    /// Command byte followed by the parameters
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
     pub fn to_usi(&self) -> Option<Vec<u8>> {
         let mut v: Vec<u8> = Vec::with_capacity(4096); //TODO define those limits
                                                        //Header is 2 bytes
@@ -168,7 +194,9 @@ impl InMessage {
         }
         self.buf = self.buf[..self.payload_len].to_vec();
         if self.protocol_type == Some(PROTOCOL_PRIME_API) {
-            self.buf[0] = common::CMD_PROTOCOL(self.buf[0]);
+            if let Some(cmd) = self.buf.get_mut(0) {
+                *cmd = common::CMD_PROTOCOL(*cmd);
+            }
         }
     }
     fn process_header(&mut self) {
@@ -211,7 +239,7 @@ impl InMessage {
                 | common::MNGP_PRIME_EN_PIBQRY
                 | common::MNGP_PRIME_EN_PIBRSP => {
                     let crc_len = 4;
-                    if let Some(tb) = self.buf.get(self.buf.len() - (crc_len)..) {
+                    if let Some(tb) = self.buf.len().checked_sub(crc_len).and_then(|start| self.buf.get(start..)) {
                         let rx_crc: u32 = (tb[0] as u32) << 24
                             | (tb[1] as u32) << 16
                             | (tb[2] as u32) << 8
@@ -228,7 +256,7 @@ impl InMessage {
                 | common::PROTOCOL_COORD_G3
                 | common::PROTOCOL_PRIMEoUDP => {
                     let crc_len = 2;
-                    if let Some(tb) = self.buf.get(self.buf.len() - (crc_len)..) {
+                    if let Some(tb) = self.buf.len().checked_sub(crc_len).and_then(|start| self.buf.get(start..)) {
                         let rx_crc = (tb[0] as u16) << 8 | (tb[1] as u16);
                         if let Some(d) = self.buf.get(0..(self.payload_len + 2)) {
                             
@@ -240,7 +268,7 @@ impl InMessage {
                 }
                 common::PROTOCOL_PRIME_API => {
                     let crc_len = 1;
-                    if let Some(tb) = self.buf.get(self.buf.len() - (crc_len)..) {
+                    if let Some(tb) = self.buf.len().checked_sub(crc_len).and_then(|start| self.buf.get(start..)) {
                         let rx_crc = tb[0];
                         if let Some(d) = self.buf.get(0..(self.payload_len as usize + 2)) {
                             return rx_crc == crc::evalCrc8(&d.to_vec());
@@ -252,22 +280,18 @@ impl InMessage {
         }
         return false;
     }
-    pub fn process(&mut self, data: &mut VecDeque<u8>) {
-        loop {
-            if let Some(c) = data.pop_front() {
-                self.process_ch(c);
-                match (self.rxState) {
-                    RxState::RxDone => {
-                        break;
-                    }
-                    RxState::RxError => {
-                        break;
-                    }
-                    _ => {}
-                }
-            } else {
-                break;
-            }
+    /// A message whose opening delimiter was already received, e.g. the closing delimiter of the previous frame
+    fn opened() -> Self {
+        let mut message = InMessage::new();
+        message.rxState = RxState::RxMsg;
+        message
+    }
+
+    fn push(&mut self, ch: u8) {
+        if self.buf.len() >= MAX_FRAME_LEN || self.payload_len > MAX_PAYLOAD_LEN {
+            self.rxState = RxState::RxError(FramingError::Oversize);
+        } else {
+            self.buf.push(ch);
         }
     }
 
@@ -292,24 +316,24 @@ impl InMessage {
                             self.rxState = RxState::RxDone;
                         } else {
                             log::warn!("CRC failed");
-                            self.rxState = RxState::RxError;
+                            self.rxState = RxState::RxError(FramingError::Crc);
                         }
                     }
                 } else {
-                    self.buf.push(ch);
+                    self.push(ch);
                     if self.protocol_type == None {
                         self.process_header();
                     }
                 }
             }
             RxState::RxEsc => {
-                if ch == common::PROTOCOL_ESC {
-                    log::warn!("Received ESC in Msg state");
-                    self.rxState = RxState::RxError;
+                if ch == common::PROTOCOL_ESC || ch == common::PROTOCOL_DELIMITER {
+                    log::warn!("Received 0x{:02x} in Esc state", ch);
+                    self.rxState = RxState::RxError(FramingError::Escape);
                 } else {
-                    self.buf.push(ch ^ 0x20);
-                    self.process_header();
                     self.rxState = RxState::RxMsg;
+                    self.push(ch ^ 0x20);
+                    self.process_header();
                 }
             }
             _ => {}
@@ -317,6 +341,79 @@ impl InMessage {
     }
 }
 
+/// Framing counters of the USI link, shared with the other threads
+#[derive(Debug, Default)]
+pub struct FramingStats {
+    pub frames: AtomicU64,
+    pub crc_errors: AtomicU64,
+    pub escape_errors: AtomicU64,
+    pub oversize_errors: AtomicU64,
+}
+
+impl FramingStats {
+    pub fn errors(&self) -> u64 {
+        self.crc_errors.load(Ordering::Relaxed)
+            + self.escape_errors.load(Ordering::Relaxed)
+            + self.oversize_errors.load(Ordering::Relaxed)
+    }
+}
+
+/// Streaming USI decoder, the bytes of a read can hold any number of frames and partial frames.
+///
+/// After a CRC error the delimiter closing the bad frame opens the next one, after an escape error
+/// or an oversize frame the bytes are dropped up to the next delimiter.
+#[derive(Debug)]
+pub struct Decoder {
+    message: InMessage,
+    stats: Arc<FramingStats>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            message: InMessage::new(),
+            stats: Arc::new(FramingStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<FramingStats> {
+        self.stats.clone()
+    }
+
+    /// The frames completed by data, the remaining bytes are kept for the next call
+    pub fn decode(&mut self, data: &[u8]) -> Vec<InMessage> {
+        let mut frames = Vec::new();
+        for ch in data {
+            self.message.process_ch(*ch);
+            match self.message.rxState {
+                RxState::RxDone => {
+                    //The closing delimiter may also be the opening one of the next frame
+                    let mut message = mem::replace(&mut self.message, InMessage::opened());
+                    message.remove_header_and_crc();
+                    self.stats.frames.fetch_add(1, Ordering::Relaxed);
+                    frames.push(message);
+                }
+                RxState::RxError(error) => {
+                    let counter = match error {
+                        FramingError::Crc => &self.stats.crc_errors,
+                        FramingError::Escape => &self.stats.escape_errors,
+                        FramingError::Oversize => &self.stats.oversize_errors,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("Failed to parse message : {:?}, resyncing", error);
+                    self.message = match error {
+                        FramingError::Crc => InMessage::opened(),
+                        FramingError::Escape if *ch == common::PROTOCOL_DELIMITER => InMessage::opened(),
+                        _ => InMessage::new(),
+                    };
+                }
+                _ => {}
+            }
+        }
+        frames
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum PortState {
     Stopped,
@@ -326,11 +423,11 @@ pub enum PortState {
 }
 
 pub struct Port<'a, T> {
-    message: usi::InMessage,
-    buf: VecDeque<u8>,
+    decoder: Decoder,
     receiver: T,
     state: &'a PortState,
     listeners: Vec<flume::Sender<Message>>,
+    recorder: Option<Recorder>,
 }
 
 //thread object should be static, makes sense! Since threads may live for the duration of the program
@@ -341,11 +438,11 @@ where
 {
     pub fn new(receiver: T) -> Port<'a, T> {
         Port {
-            message: usi::InMessage::new(),
-            buf: VecDeque::with_capacity(4096),
+            decoder: Decoder::new(),
             receiver,
             state: &PortState::Stopped,
             listeners: Vec::new(),
+            recorder: None,
         }
     }
     // pub fn post_cmd(&self, cmd: &'a MessageType) {
@@ -355,6 +452,15 @@ where
         self.listeners.push(listener);
     }
 
+    pub fn stats(&self) -> Arc<FramingStats> {
+        self.decoder.stats()
+    }
+
+    /// Records the frames of both directions, see usi_capture
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
     // pub fn process<T>(&mut self, port: &mut T, listener:&Box<dyn message::MessageListener>) -> Option<Vec<u8>>
     fn process(&mut self) {
         let mut b = [0; 4096];
@@ -366,41 +472,27 @@ where
                 } else {
                     debug!("usi received {} : size {} ", array_to_hex_string(b[..t].to_vec()), t);
                 }
-                // for ch in &mut b[..t] {
-                //     //TODO, push whole slice
-                //     self.buf.push_back(*ch);
-                // }
-                self.buf.extend(b[..t].iter());
-                // self.buf.append(b[..t].to_vec());
-                // let ch = b[0];
-                // info!("Received {}", ch);
-                self.message.process(&mut self.buf);
-                match self.message.get_state() {
-                    usi::RxState::RxDone => {
-                        self.message.remove_header_and_crc();
-                        for listener in &self.listeners {
-                            listener.send(usi::Message::UsiIn(self.message.clone()));
-                        }
-                        // if let Some(ref sender) = sender {
-                        //     sender.send(self.message.clone());
-                        // }
-                        self.message = usi::InMessage::new();
+                for message in self.decoder.decode(&b[..t]) {
+                    if let Some(ref recorder) = self.recorder {
+                        recorder.record(Direction::In, message.protocol_type.unwrap_or(common::PROTOCOL_INVALID), &message.buf);
                     }
-                    usi::RxState::RxError => {
-                        log::warn!("Failed to parse message : RxError");
-                        self.message = usi::InMessage::new();
+                    for listener in &self.listeners {
+                        listener.send(usi::Message::UsiIn(message.clone()));
                     }
-                    _ => {}
                 }
             }
             Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
             Err(e) => {
                 warn!("Error {}", e);
+                //Transport::open_reconnecting reopens the links, what fails here is not reopened (e.g. the
+                //end of a replay), do not spin on it
+                thread::sleep(Duration::from_secs(1));
             }
         }
     }
     pub fn start<S: 'a +  Write + Send>(mut self, mut sender: S) -> flume::Sender<Message> {
         let (tx, rx) = flume::unbounded::<Message>();
+        let recorder = self.recorder.clone();
         // let c_rx = rx.clone();
         thread::spawn(move || loop {
             self.process();
@@ -413,6 +505,9 @@ where
                         Message::UsiOut(cmd) => {
                             // self.send(&cmd);
                             if let Some(buf) = cmd.to_usi() {
+                                if let Some(ref recorder) = recorder {
+                                    recorder.record(Direction::Out, cmd.protocol(), cmd.data());
+                                }
                                 log::info!("--> {}", common::to_hex_string(&buf));
                                 log::info!("Writing {} bytes to usi", buf.len());
                                 match sender.write_all(&buf) {
@@ -446,3 +541,79 @@ where
 //         return Err(String::from("Invalid UsiCommand"));
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        OutMessage::new(common::PROTOCOL_ADP_G3, data).to_usi().unwrap()
+    }
+
+    #[test]
+    fn two_frames_in_one_read() {
+        let mut decoder = Decoder::new();
+        let mut data = frame(&[0x01, 0x02, 0x7e, 0x03]);
+        data.extend(frame(&[0x04, 0x7d]));
+        let frames = decoder.decode(&data);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buf, vec![0x01, 0x02, 0x7e, 0x03]);
+        assert_eq!(frames[1].buf, vec![0x04, 0x7d]);
+        assert_eq!(decoder.stats().frames.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let mut decoder = Decoder::new();
+        let data = frame(&[0x01, 0x02, 0x03]);
+        let (first, second) = data.split_at(4);
+        assert!(decoder.decode(first).is_empty());
+        let frames = decoder.decode(second);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buf, vec![0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn shared_delimiter() {
+        let mut decoder = Decoder::new();
+        let mut data = frame(&[0x01]);
+        data.extend(&frame(&[0x02])[1..]);
+        assert_eq!(decoder.decode(&data).len(), 2);
+    }
+
+    #[test]
+    fn resync_after_crc_error() {
+        let mut decoder = Decoder::new();
+        let mut data = frame(&[0x01, 0x02, 0x03]);
+        data[3] ^= 0x01;
+        data.extend(frame(&[0x04]));
+        let frames = decoder.decode(&data);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buf, vec![0x04]);
+        assert_eq!(decoder.stats().crc_errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn resync_after_escape_error() {
+        let mut decoder = Decoder::new();
+        let mut data = vec![common::PROTOCOL_DELIMITER, 0x00, 0x01, common::PROTOCOL_ESC, common::PROTOCOL_ESC, 0x05];
+        data.extend(frame(&[0x04]));
+        let frames = decoder.decode(&data);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buf, vec![0x04]);
+        assert_eq!(decoder.stats().escape_errors.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn oversize_frame_is_dropped() {
+        let mut decoder = Decoder::new();
+        let mut data = vec![common::PROTOCOL_DELIMITER];
+        data.extend(std::iter::repeat(0x55).take(2 * MAX_FRAME_LEN));
+        data.extend(frame(&[0x04]));
+        let frames = decoder.decode(&data);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buf, vec![0x04]);
+        assert_eq!(decoder.stats().oversize_errors.load(Ordering::Relaxed), 1);
+        assert!(decoder.message.buf.is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, LineWriter, Lines, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common;
+use crate::usi::OutMessage;
+
+/// How long a replay read waits for the next frame, as the read timeout of the transports
+const REPLAY_READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the modem
+    In,
+    /// To the modem
+    Out,
+}
+
+/// One USI frame of a capture file, the file holds one record per line in JSON :
+/// `{"ts_us":1697539200123456,"dir":"in","protocol":37,"data":"0a00..."}`
+///
+/// `data` is the payload in hex, command byte first, without the header, CRC and escaping.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Microseconds since the UNIX epoch
+    pub ts_us: u64,
+    pub dir: Direction,
+    /// USI protocol type, e.g. PROTOCOL_ADP_G3 (37)
+    pub protocol: u8,
+    pub data: String,
+}
+
+impl Record {
+    pub fn new(dir: Direction, protocol: u8, data: &[u8]) -> Self {
+        let ts_us = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        Record {
+            ts_us,
+            dir,
+            protocol,
+            data: data.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    /// The frame as sent on the link
+    pub fn to_usi(&self) -> Option<Vec<u8>> {
+        let data = common::from_hex_string(&self.data)?;
+        OutMessage::new(self.protocol, &data).to_usi()
+    }
+}
+
+/// Appends the frames of both directions to a capture file, shared by the reader and writer threads of the port
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<LineWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            writer: Arc::new(Mutex::new(LineWriter::new(file))),
+        })
+    }
+
+    pub fn record(&self, dir: Direction, protocol: u8, data: &[u8]) {
+        let record = Record::new(dir, protocol, data);
+        let result = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.writer.lock().unwrap(), "{}", line));
+        if let Err(e) = result {
+            log::warn!("Failed to record usi frame : {}", e);
+        }
+    }
+}
+
+/// Gives the frames received from the modem in a capture file, at the pace they were recorded.
+/// The frames sent to the modem are skipped, see transport::Transport::Replay.
+pub struct ReplayReader {
+    lines: Lines<BufReader<File>>,
+    /// Start of the replay and timestamp of the first record
+    start: Option<(Instant, u64)>,
+    next: Option<Record>,
+    pending: VecDeque<u8>,
+    done: bool,
+}
+
+impl ReplayReader {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(ReplayReader {
+            lines: BufReader::new(file).lines(),
+            start: None,
+            next: None,
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    fn next_record(&mut self) -> Option<Record> {
+        for line in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::warn!("Failed to read capture : {}", e);
+                    return None;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) => {
+                    self.start.get_or_insert((Instant::now(), record.ts_us));
+                    if record.dir == Direction::In {
+                        return Some(record);
+                    }
+                }
+                Err(e) => log::warn!("Invalid capture record {} : {}", line, e),
+            }
+        }
+        None
+    }
+
+    fn timed_out() -> io::Error {
+        thread::sleep(REPLAY_READ_TIMEOUT);
+        io::Error::new(io::ErrorKind::TimedOut, "no frame")
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if self.next.is_none() {
+                self.next = self.next_record();
+            }
+            let record = match self.next {
+                Some(ref record) => record,
+                None => {
+                    if !self.done {
+                        log::info!("Replay finished");
+                        self.done = true;
+                    }
+                    return Err(Self::timed_out());
+                }
+            };
+            let (start, first_ts) = self.start.unwrap_or((Instant::now(), record.ts_us));
+            let due = start + Duration::from_micros(record.ts_us.saturating_sub(first_ts));
+            let now = Instant::now();
+            if due > now + REPLAY_READ_TIMEOUT {
+                return Err(Self::timed_out());
+            }
+            thread::sleep(due.saturating_duration_since(now));
+            match self.next.take().and_then(|record| record.to_usi()) {
+                Some(frame) => self.pending.extend(frame),
+                None => log::warn!("Skipping invalid capture frame"),
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usi::Decoder;
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!("ne-g3-capture-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+        let recorder = Recorder::create(path).unwrap();
+        recorder.record(Direction::Out, common::PROTOCOL_ADP_G3, &[0x12, 0x00, 0x01]);
+        recorder.record(Direction::In, common::PROTOCOL_ADP_G3, &[0x01, 0x7e, 0x7d]);
+
+        let mut reader = ReplayReader::open(path).unwrap();
+        let mut decoder = Decoder::new();
+        let mut b = [0u8; 4];
+        let mut frames = Vec::new();
+        while frames.is_empty() {
+            match reader.read(&mut b) {
+                Ok(t) => frames.extend(decoder.decode(&b[..t])),
+                Err(e) => panic!("no frame replayed : {}", e),
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].protocol_type, Some(common::PROTOCOL_ADP_G3));
+        assert_eq!(frames[0].buf, vec![0x01, 0x7e, 0x7d]);
+        assert_eq!(reader.read(&mut b).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+}